@@ -1,41 +1,111 @@
+/// arpeggiator settings
+mod arpeggiator;
+/// automation lanes
+mod automation;
+/// change notifications of the data layer
+mod change;
 /// clip data object
 mod clip;
 /// common objects
 mod common;
+/// undo and redo of edits
+mod history;
+/// midi inputs of a project and how their messages are filtered
+mod inputs;
+/// markers and locators
+mod locators;
 /// the midi event objects
 mod midi_event;
 /// the midi message object
 mod midi_message;
+/// logical midi ports of a project
+mod ports;
 /// the project data object
 mod project;
+/// takes recorded from midi inputs
+mod recording;
+/// session grid of clip slots and scenes
+mod session;
+/// synchronization of other devices
+mod sync;
+/// conversion between ticks and seconds
+mod tempo_map;
+/// smpte timecode positions
+mod timecode;
 /// the track data object
 mod track;
 
 use std::ops::Deref;
+use std::ops::DerefMut;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::sync::LockResult;
+use std::sync::PoisonError;
 use std::sync::RwLock;
-
+use std::sync::RwLockWriteGuard;
+
+pub use arpeggiator::ArpMode;
+pub use arpeggiator::ArpeggiatorSettings;
+pub use automation::AutomationLane;
+pub use automation::AutomationTarget;
+pub use automation::AutomationValue;
+pub use automation::Breakpoint;
+pub use automation::CurveShape;
+pub use change::ChangeNotification;
+pub use change::DataChange;
 pub use clip::Clip;
 pub use clip::ClipId;
 pub use clip::ClipKey;
 
 pub use common::DataId;
+pub use inputs::InputFilter;
+pub use inputs::InputMap;
+pub use inputs::InputPort;
+pub use locators::Locators;
+pub use locators::Marker;
+pub use locators::MarkerCollection;
 pub use midi_message::MidiMessage;
+pub use ports::MidiPort;
+pub use ports::PortMap;
+pub use project::EventLocation;
+pub use recording::RecordMode;
+pub use recording::RecordedEvent;
+pub use recording::Take;
+pub use session::ClipSlot;
+pub use session::FollowAction;
+pub use session::Scene;
+pub use session::Session;
+pub use sync::ClockOutput;
+pub use sync::SyncSettings;
+pub use sync::TimecodeOutput;
+pub use tempo_map::TempoMap;
+pub use timecode::FrameRate;
+pub use timecode::Timecode;
+pub use track::ArrangedEvent;
 pub use track::Track;
 pub use track::TrackId;
 
 /// event list
 pub mod event_list;
+/// standard midi file export
+pub mod smf;
 
 use self::project::Project;
 use crate::{instrument::Instrument, Tick};
 use thiserror::Error;
+use tokio::sync::broadcast;
+
+/// number of change notifications kept for subscribers which fall behind
+const CHANGE_CAPACITY: usize = 1024;
 
 /// holds state of the editor, like note editor or automation editor modes.
 #[derive(Default, Debug)]
 pub struct EditorState {
     /// data id of the selected clip, so it can be found later
     selected_clip: DataId,
+    /// track selected by the user, the midi inputs are echoed to its instrument
+    selected_track: Option<TrackId>,
 }
 
 /// interface for talking with main hexencer data object
@@ -43,13 +113,15 @@ pub struct EditorState {
 pub struct StorageInterface {
     /// inner object actually holding data
     inner: Arc<std::sync::RwLock<DataLayer>>,
+    /// revision of the data, increased by every write which changed something
+    revision: Arc<AtomicU64>,
+    /// broadcasts the changes made through 'write'
+    changes: broadcast::Sender<ChangeNotification>,
 }
 
 impl Default for StorageInterface {
     fn default() -> Self {
-        Self {
-            inner: Arc::new(RwLock::new(DataLayer::fake_data())), // TODO replace this after testing is complete
-        }
+        Self::from_data_layer(DataLayer::fake_data()) // TODO replace this after testing is complete
     }
 }
 
@@ -64,8 +136,79 @@ impl Deref for StorageInterface {
 impl StorageInterface {
     /// creates a new interface for data
     pub fn new() -> Self {
+        Self::from_data_layer(DataLayer::fake_data())
+    }
+
+    /// creates a new interface around the given data
+    pub fn from_data_layer(data_layer: DataLayer) -> Self {
+        let (changes, _) = broadcast::channel(CHANGE_CAPACITY);
         Self {
-            inner: Arc::new(RwLock::new(DataLayer::fake_data())),
+            inner: Arc::new(RwLock::new(data_layer)),
+            revision: Arc::new(AtomicU64::new(0)),
+            changes,
+        }
+    }
+
+    /// locks the data for writing, changes are broadcast when the guard is dropped
+    pub fn write(&self) -> LockResult<StorageWriteGuard<'_>> {
+        let storage = self;
+        match self.inner.write() {
+            Ok(guard) => Ok(StorageWriteGuard { guard, storage }),
+            Err(error) => Err(PoisonError::new(StorageWriteGuard {
+                guard: error.into_inner(),
+                storage,
+            })),
+        }
+    }
+
+    /// get the current revision of the data
+    pub fn revision(&self) -> u64 {
+        self.revision.load(Ordering::Acquire)
+    }
+
+    /// subscribe to the changes made to the data
+    pub fn subscribe(&self) -> broadcast::Receiver<ChangeNotification> {
+        self.changes.subscribe()
+    }
+}
+
+/// write access to the data layer, publishes the changes made when dropped
+#[derive(Debug)]
+pub struct StorageWriteGuard<'a> {
+    /// the lock guard of the data layer
+    guard: RwLockWriteGuard<'a, DataLayer>,
+    /// the storage which is written to
+    storage: &'a StorageInterface,
+}
+
+impl Deref for StorageWriteGuard<'_> {
+    type Target = DataLayer;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl DerefMut for StorageWriteGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
+
+impl Drop for StorageWriteGuard<'_> {
+    fn drop(&mut self) {
+        let changes = self.guard.take_changes();
+        if changes.is_empty() {
+            return;
+        }
+        // the revision is increased while the lock is still held, so it matches the data
+        let revision = self.storage.revision.fetch_add(1, Ordering::AcqRel) + 1;
+        for change in changes {
+            // sending only fails when nobody is subscribed
+            let _ = self
+                .storage
+                .changes
+                .send(ChangeNotification { revision, change });
         }
     }
 }
@@ -108,6 +251,43 @@ impl DataLayer {
         self.bpm
     }
 
+    /// set the bpm of the project
+    pub fn set_bpm(&mut self, bpm: f64) {
+        self.bpm = bpm;
+        self.project_manager.record(DataChange::TempoChanged(bpm));
+    }
+
+    /// get the track selected by the user
+    pub fn selected_track(&self) -> Option<TrackId> {
+        self.editor_state.selected_track
+    }
+
+    /// select a track, or clear the selection by passing 'None'
+    pub fn select_track(&mut self, track_id: Option<TrackId>) {
+        self.editor_state.selected_track = track_id;
+        self.project_manager
+            .record(DataChange::TrackSelected(track_id));
+    }
+
+    /// takes the changes made since the last call
+    pub fn take_changes(&mut self) -> Vec<DataChange> {
+        self.project_manager.take_changes()
+    }
+
+    /// export the project to a standard midi file,
+    /// automation lanes are sampled every 'automation_resolution' ticks
+    pub fn export_smf(&self, automation_resolution: Tick) -> Vec<u8> {
+        smf::write_project(&self.project_manager, self.bpm, automation_resolution)
+    }
+
+    /// replace the markers of the project with the markers found in a standard midi file
+    pub fn import_smf_markers(&mut self, bytes: &[u8]) -> Result<(), smf::SmfError> {
+        let markers = smf::read_markers(bytes)?;
+        self.project_manager
+            .edit_markers(|current| *current = markers);
+        Ok(())
+    }
+
     /// get the bpm of the project as a string
     pub fn bpm_str(&self) -> String {
         self.bpm.to_string()
@@ -115,12 +295,7 @@ impl DataLayer {
 
     /// add a new clip to the track specified by 'track_id'
     pub fn add_clip(&mut self, track_id: TrackId, clip: Clip) -> Result<(), DataLayerError> {
-        if let Some(track) = self.project_manager.track_collection.get_mut(track_id) {
-            track.add_clip(clip);
-        } else {
-            return Err(DataLayerError::NoTrack(track_id));
-        }
-        Ok(())
+        self.project_manager.add_clip(track_id, clip)
     }
 
     /// set the playhead tick
@@ -197,7 +372,7 @@ mod tests {
         data.project_manager.add_track(track);
 
         {
-            let clips = data.project_manager.track_collection.get_clips(0).unwrap();
+            let clips = data.project_manager.tracks().get_clips(0).unwrap();
             assert_eq!(clips.len(), 0);
         }
 
@@ -205,11 +380,53 @@ mod tests {
         data.add_clip(track_id, clip).unwrap();
 
         {
-            let clips = data.project_manager.track_collection.get_clips(0).unwrap();
+            let clips = data.project_manager.tracks().get_clips(0).unwrap();
             assert_eq!(clips.len(), 1);
         }
     }
 
+    #[test]
+    fn writes_broadcast_their_changes() {
+        let storage = StorageInterface::from_data_layer(DataLayer::default());
+        let mut changes = storage.subscribe();
+
+        let track_id = {
+            let mut data = storage.write().unwrap();
+            data.project_manager.push_track();
+            data.project_manager.tracks().get(0).unwrap().id
+        };
+        assert_eq!(storage.revision(), 1);
+
+        let clip = Clip::new(0.into(), "test", 120.into());
+        let clip_id = clip.id;
+        {
+            let mut data = storage.write().unwrap();
+            data.add_clip(track_id, clip).unwrap();
+            data.set_bpm(90.0);
+        }
+        let _ = storage.write().unwrap();
+        assert_eq!(storage.revision(), 2);
+
+        let received: Vec<_> = std::iter::from_fn(|| changes.try_recv().ok()).collect();
+        assert_eq!(
+            received,
+            vec![
+                ChangeNotification {
+                    revision: 1,
+                    change: DataChange::TrackAdded(track_id)
+                },
+                ChangeNotification {
+                    revision: 2,
+                    change: DataChange::ClipAdded { track_id, clip_id }
+                },
+                ChangeNotification {
+                    revision: 2,
+                    change: DataChange::TempoChanged(90.0)
+                },
+            ]
+        );
+    }
+
     #[test]
     fn can_set_tick() {
         let mut data = DataLayer::default();
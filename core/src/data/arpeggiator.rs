@@ -0,0 +1,98 @@
+use crate::Tick;
+
+/// order in which an arpeggiator steps through the held notes
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArpMode {
+    /// lowest to highest note
+    #[default]
+    Up,
+    /// highest to lowest note
+    Down,
+    /// lowest to highest and back down, without repeating the outer notes
+    UpDown,
+    /// a random held note on every step
+    Random,
+    /// the order in which the notes were pressed
+    AsPlayed,
+}
+
+/// settings of an arpeggiator, stored on a 'Track'
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ArpeggiatorSettings {
+    /// order in which the held notes are played
+    pub mode: ArpMode,
+    /// length of one step, 120 ticks is a 16th note at 480 ppqn
+    pub rate: Tick,
+    /// number of octaves the pattern spans, 1 plays the held notes only
+    pub octaves: u8,
+    /// length of each note as a fraction of the step, 0.0-1.0
+    pub gate: f32,
+    /// delay of every second step as a fraction of the step, 0.0-0.5
+    pub swing: f32,
+    /// keep playing the notes after they are released, until a new chord is played
+    pub latch: bool,
+}
+
+impl Default for ArpeggiatorSettings {
+    fn default() -> Self {
+        Self {
+            mode: ArpMode::Up,
+            rate: Tick::from(120),
+            octaves: 1,
+            gate: 0.5,
+            swing: 0.0,
+            latch: false,
+        }
+    }
+}
+
+impl ArpeggiatorSettings {
+    /// creates new settings with the given mode and rate
+    pub fn new(mode: ArpMode, rate: Tick) -> Self {
+        Self {
+            mode,
+            rate,
+            ..Default::default()
+        }
+    }
+
+    /// returns the tick at which the given step starts, with swing applied to odd steps
+    pub fn step_start(&self, step: u64) -> Tick {
+        let rate = self.rate.as_f64();
+        let mut start = step as f64 * rate;
+        if step % 2 == 1 {
+            start += rate * self.swing.clamp(0.0, 0.5) as f64;
+        }
+        Tick::from(start.round() as u64)
+    }
+
+    /// returns the length of a single note, derived from the rate and gate
+    pub fn note_length(&self) -> Tick {
+        let length = (self.rate.as_f64() * self.gate.clamp(0.0, 1.0) as f64).round() as u64;
+        Tick::from(length.max(1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn swing_delays_odd_steps() {
+        let mut settings = ArpeggiatorSettings::new(ArpMode::Up, Tick::from(120));
+        settings.swing = 0.25;
+
+        assert_eq!(settings.step_start(0), Tick::from(0));
+        assert_eq!(settings.step_start(1), Tick::from(150));
+        assert_eq!(settings.step_start(2), Tick::from(240));
+    }
+
+    #[test]
+    fn note_length_is_never_zero() {
+        let settings = ArpeggiatorSettings {
+            gate: 0.0,
+            ..Default::default()
+        };
+        assert_eq!(settings.note_length(), Tick::from(1));
+    }
+}
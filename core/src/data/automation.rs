@@ -0,0 +1,229 @@
+use crate::Tick;
+
+use super::MidiMessage;
+
+/// steepness of the exponential curve shape
+const EXPONENTIAL_CURVE: f64 = 4.0;
+
+/// shape of the segment running from a breakpoint to the next one
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CurveShape {
+    /// straight line between the two values
+    #[default]
+    Linear,
+    /// starts slow and speeds up towards the next value
+    Exponential,
+    /// holds the value until the next breakpoint
+    Step,
+    /// eases in and out of the two values
+    SCurve,
+}
+
+impl CurveShape {
+    /// maps a position 0.0-1.0 between two breakpoints on to this curve
+    fn apply(&self, position: f64) -> f64 {
+        match self {
+            CurveShape::Linear => position,
+            CurveShape::Exponential => {
+                ((EXPONENTIAL_CURVE * position).exp() - 1.0) / (EXPONENTIAL_CURVE.exp() - 1.0)
+            }
+            CurveShape::Step => 0.0,
+            CurveShape::SCurve => position * position * (3.0 - 2.0 * position),
+        }
+    }
+}
+
+/// parameter which is controlled by an 'AutomationLane'
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AutomationTarget {
+    /// a midi control change, values are 0-127
+    ControlChange(u8),
+    /// the pitch bend wheel, values are 0-16383 where 8192 is the center
+    PitchBend,
+    /// channel pressure, values are 0-127
+    ChannelPressure,
+    /// the tempo of the project, values are in bpm
+    Tempo,
+}
+
+/// value produced by sampling an 'AutomationLane'
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AutomationValue {
+    /// a midi message to send on the channel of the track
+    Midi(MidiMessage),
+    /// a new tempo for the project in bpm
+    Tempo(f64),
+}
+
+impl AutomationTarget {
+    /// converts a raw lane value to the value sent for this target
+    pub fn to_value(&self, value: f64) -> AutomationValue {
+        match self {
+            AutomationTarget::ControlChange(controller) => {
+                AutomationValue::Midi(MidiMessage::ControlChange {
+                    controller: *controller,
+                    value: value.round().clamp(0.0, 127.0) as u8,
+                })
+            }
+            AutomationTarget::PitchBend => AutomationValue::Midi(MidiMessage::PitchBend {
+                value: value.round().clamp(0.0, 16383.0) as u16,
+            }),
+            AutomationTarget::ChannelPressure => {
+                AutomationValue::Midi(MidiMessage::ChannelPressure {
+                    pressure: value.round().clamp(0.0, 127.0) as u8,
+                })
+            }
+            AutomationTarget::Tempo => AutomationValue::Tempo(value.max(1.0)),
+        }
+    }
+}
+
+/// a point on an automation envelope
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Breakpoint {
+    /// tick of the point, relative to the start of the project
+    pub tick: Tick,
+    /// value at this point, in the units of the lane target
+    pub value: f64,
+    /// shape of the segment towards the next point
+    pub shape: CurveShape,
+}
+
+impl Breakpoint {
+    /// creates a new 'Breakpoint'
+    pub fn new(tick: Tick, value: f64, shape: CurveShape) -> Self {
+        Self { tick, value, shape }
+    }
+}
+
+/// breakpoint envelope controlling a single target of a 'Track'
+#[derive(Debug, Clone, PartialEq)]
+pub struct AutomationLane {
+    /// parameter controlled by this lane
+    pub target: AutomationTarget,
+    /// points of the envelope, sorted by tick
+    points: Vec<Breakpoint>,
+    /// true if the lane should be played back
+    pub is_active: bool,
+}
+
+impl AutomationLane {
+    /// creates a new, empty, 'AutomationLane'
+    pub fn new(target: AutomationTarget) -> Self {
+        Self {
+            target,
+            points: Vec::new(),
+            is_active: true,
+        }
+    }
+
+    /// adds a point, replacing any point at the same tick
+    pub fn add_point(&mut self, point: Breakpoint) {
+        match self.points.binary_search_by_key(&point.tick, |p| p.tick) {
+            Ok(index) => self.points[index] = point,
+            Err(index) => self.points.insert(index, point),
+        }
+    }
+
+    /// removes the point at the given tick, returning it if found
+    pub fn remove_point(&mut self, tick: Tick) -> Option<Breakpoint> {
+        self.points
+            .binary_search_by_key(&tick, |p| p.tick)
+            .ok()
+            .map(|index| self.points.remove(index))
+    }
+
+    /// get the points of this lane, sorted by tick
+    pub fn points(&self) -> &[Breakpoint] {
+        &self.points
+    }
+
+    /// get the raw value of the envelope at the given tick, or 'None' if the lane is empty
+    /// before the first point and after the last point the value of that point is held
+    pub fn value_at(&self, tick: Tick) -> Option<f64> {
+        let index = self.points.partition_point(|p| p.tick <= tick);
+        if index == 0 {
+            return self.points.first().map(|p| p.value);
+        }
+        let from = &self.points[index - 1];
+        let Some(to) = self.points.get(index) else {
+            return Some(from.value);
+        };
+
+        let position = (tick - from.tick).as_f64() / (to.tick - from.tick).as_f64();
+        Some(from.value + (to.value - from.value) * from.shape.apply(position))
+    }
+
+    /// get the value which should be sent for the given tick
+    pub fn sample(&self, tick: Tick) -> Option<AutomationValue> {
+        self.value_at(tick).map(|value| self.target.to_value(value))
+    }
+
+    /// samples the lane every 'resolution' ticks from 'start' up to 'end',
+    /// skipping samples which would send the same value as the previous one
+    pub fn samples(
+        &self,
+        start: Tick,
+        end: Tick,
+        resolution: Tick,
+    ) -> Vec<(Tick, AutomationValue)> {
+        let step = resolution.as_u64().max(1);
+        let mut samples: Vec<(Tick, AutomationValue)> = Vec::new();
+        let mut tick = start.as_u64().div_ceil(step) * step;
+        while tick < end.as_u64() {
+            if let Some(value) = self.sample(Tick::from(tick)) {
+                if samples
+                    .last()
+                    .map(|(_, last)| *last != value)
+                    .unwrap_or(true)
+                {
+                    samples.push((Tick::from(tick), value));
+                }
+            }
+            tick += step;
+        }
+        samples
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// lane going from 0 to 100 over 100 ticks with the given shape
+    fn lane(shape: CurveShape) -> AutomationLane {
+        let mut lane = AutomationLane::new(AutomationTarget::ControlChange(1));
+        lane.add_point(Breakpoint::new(Tick::from(100), 100.0, shape));
+        lane.add_point(Breakpoint::new(Tick::from(0), 0.0, shape));
+        lane
+    }
+
+    #[test]
+    fn interpolates_between_points() {
+        assert_eq!(
+            lane(CurveShape::Linear).value_at(Tick::from(25)),
+            Some(25.0)
+        );
+        assert_eq!(lane(CurveShape::Step).value_at(Tick::from(99)), Some(0.0));
+        assert_eq!(
+            lane(CurveShape::SCurve).value_at(Tick::from(50)),
+            Some(50.0)
+        );
+        let exponential = lane(CurveShape::Exponential)
+            .value_at(Tick::from(50))
+            .unwrap();
+        assert!(exponential < 25.0);
+        assert_eq!(
+            lane(CurveShape::Linear).value_at(Tick::from(500)),
+            Some(100.0)
+        );
+    }
+
+    #[test]
+    fn samples_skip_duplicate_values() {
+        let samples =
+            lane(CurveShape::Step).samples(Tick::from(0), Tick::from(200), Tick::from(10));
+        let ticks: Vec<_> = samples.iter().map(|(tick, _)| *tick).collect();
+        assert_eq!(ticks, vec![Tick::from(0), Tick::from(100)]);
+    }
+}
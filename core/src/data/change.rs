@@ -0,0 +1,75 @@
+use super::{clip::ClipId, track::TrackId};
+
+/// a change made to the data layer, broadcast to subscribers of the 'StorageInterface'
+#[derive(Debug, Clone, PartialEq)]
+pub enum DataChange {
+    /// a clip was added to a track
+    ClipAdded {
+        /// track the clip was added to
+        track_id: TrackId,
+        /// the added clip
+        clip_id: ClipId,
+    },
+    /// a clip was moved, possibly to another track
+    ClipMoved {
+        /// track the clip was on
+        from: TrackId,
+        /// track the clip is on now
+        to: TrackId,
+        /// the moved clip
+        clip_id: ClipId,
+    },
+    /// a clip was removed from a track
+    ClipRemoved {
+        /// track the clip was removed from
+        track_id: TrackId,
+        /// the removed clip
+        clip_id: ClipId,
+    },
+    /// a clip was cut short by a clip placed over it
+    ClipChanged {
+        /// track of the clip
+        track_id: TrackId,
+        /// the changed clip
+        clip_id: ClipId,
+    },
+    /// the events of a clip were edited
+    EventsEdited {
+        /// the edited clip
+        clip_id: ClipId,
+    },
+    /// a track was added to the project
+    TrackAdded(TrackId),
+    /// a track was removed from the project
+    TrackRemoved(TrackId),
+    /// settings of a track changed, like its instrument or arpeggiator
+    TrackChanged(TrackId),
+    /// the bpm of the project changed
+    TempoChanged(f64),
+    /// the sync settings of the project changed
+    SyncChanged,
+    /// the logical midi ports of the project changed
+    PortsChanged,
+    /// the midi inputs of the project changed
+    InputsChanged,
+    /// the session grid of the project changed
+    SessionChanged,
+    /// markers were added, moved or removed
+    MarkersChanged,
+    /// the loop or punch locators changed
+    LocatorsChanged,
+    /// the user selected a track, or cleared the selection
+    TrackSelected(Option<TrackId>),
+    /// more changes were made than could be kept, anything may have changed and everything
+    /// should be read again
+    Resync,
+}
+
+/// a change along with the revision of the data layer it produced
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChangeNotification {
+    /// revision of the data layer after the change was made
+    pub revision: u64,
+    /// the change itself
+    pub change: DataChange,
+}
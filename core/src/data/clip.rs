@@ -1,10 +1,10 @@
 use std::{
     collections::{
         btree_map::{IntoIter, Iter},
-        BTreeMap,
+        BTreeMap, HashMap,
     },
     fmt::Display,
-    ops::{Deref, DerefMut},
+    ops::{Deref, Range},
 };
 
 use tracing::info;
@@ -39,28 +39,57 @@ impl From<&Clip> for ClipKey {
 pub struct ClipCollection {
     /// inner object housing the clips
     inner: BTreeMap<ClipKey, Clip>,
+    /// key of every clip by id
+    ids: HashMap<ClipId, ClipKey>,
 }
 
 impl ClipCollection {
     /// insert a new clip to the collection
-    pub fn insert(&mut self, new_clip: Clip) {
+    pub(crate) fn insert(&mut self, new_clip: Clip) {
         // create a new clip key from the new clip
         self.clip_partial_overlapped_clips_to_the_right(&new_clip);
         let overlapped_outer = self.get_surrounded_overlapped_clips(&new_clip);
         self.split_overlapped_clip(overlapped_outer, &new_clip);
 
         // Insert the new clip
-        self.inner.insert(ClipKey::from(&new_clip), new_clip);
+        self.insert_clip(new_clip);
+    }
+
+    /// inserts a clip at its key, replacing any clip starting at the same tick
+    fn insert_clip(&mut self, clip: Clip) {
+        let key = ClipKey::from(&clip);
+        let id = clip.id;
+        if let Some(replaced) = self.inner.insert(key, clip) {
+            self.ids.remove(&replaced.id);
+        }
+        self.ids.insert(id, key);
+    }
+
+    /// removes the clip with the given key, returning it if found
+    pub(crate) fn remove(&mut self, key: &ClipKey) -> Option<Clip> {
+        let clip = self.inner.remove(key)?;
+        self.ids.remove(&clip.id);
+        Some(clip)
+    }
+
+    /// get a mutable reference to a clip, its start and duration should not be changed
+    pub(crate) fn get_mut(&mut self, key: &ClipKey) -> Option<&mut Clip> {
+        self.inner.get_mut(key)
+    }
+
+    /// get the key of the clip with the given id
+    pub fn key_of(&self, id: ClipId) -> Option<ClipKey> {
+        self.ids.get(&id).copied()
     }
 
     /// splits a clip if it is overlapped by another clip
     fn split_overlapped_clip(&mut self, overlapped_outer: Vec<(ClipKey, Clip)>, new_clip: &Clip) {
         for (overlap_clip_key, mut overlap_clip) in overlapped_outer {
+            // Remove the original clip first, it is split into 2 new clips and the left one
+            // takes its key
+            self.remove(&overlap_clip_key);
             self.insert_left_of_tick(&mut overlap_clip, &new_clip.start);
             self.insert_right_of_tick(&mut overlap_clip, &new_clip.end());
-
-            // Remove the original clip because it has been split into 2 new clips
-            self.inner.remove(&overlap_clip_key);
         }
     }
 
@@ -76,30 +105,21 @@ impl ClipCollection {
 
     /// adds a clip to the right of the overlapping clip, splitting the original
     fn insert_right_of_tick(&mut self, overlapped_clip: &mut Clip, tick: &Tick) {
-        if overlapped_clip.start + overlapped_clip.duration > *tick {
+        if overlapped_clip.end() > *tick {
             // Split the end part
-            let right_duration = overlapped_clip.start + overlapped_clip.duration - *tick;
-            let mut right_clip = overlapped_clip.clone();
-            right_clip.id = ClipId::new();
-            right_clip.start = *tick;
-            right_clip.duration = right_duration;
-            self.inner.insert(ClipKey::from(&right_clip), right_clip);
-            overlapped_clip.duration = *tick - overlapped_clip.start;
+            let right_clip = overlapped_clip.split_off(*tick);
+            self.insert_clip(right_clip);
         }
     }
 
     /// adds a clip to the left of the overlapping clip, splitting the original
     fn insert_left_of_tick(&mut self, overlapped_clip: &mut Clip, tick: &Tick) {
         if overlapped_clip.start < *tick {
-            // Split the beginning part
-            let left_duration = *tick - overlapped_clip.start;
-            let mut left_clip = overlapped_clip.clone();
+            // Split the beginning part, the original continues as the end part
+            let right_clip = overlapped_clip.split_off(*tick);
+            let mut left_clip = std::mem::replace(overlapped_clip, right_clip);
             left_clip.id = ClipId::new();
-            left_clip.duration = left_duration;
-            self.inner.insert(ClipKey::from(&left_clip), left_clip);
-
-            overlapped_clip.start = *tick;
-            overlapped_clip.duration -= left_duration;
+            self.insert_clip(left_clip);
         }
     }
 
@@ -120,7 +140,7 @@ impl ClipCollection {
     fn split_right_clip(&mut self, clip: &mut Clip, new_clip: &Clip, key: ClipKey) {
         // handle partial overlapped clip on the right side
         if clip.start > new_clip.start && clip.end() > new_clip.end() {
-            self.inner.remove(&key);
+            self.remove(&key);
             self.insert_right_of_tick(clip, &new_clip.end());
         }
     }
@@ -128,8 +148,8 @@ impl ClipCollection {
     /// removes a clip if it is fully overlapped
     fn remove_overlapped(&mut self, clip: &Clip, new_clip: &Clip, key: ClipKey) {
         // handle full overlap
-        if clip.start >= new_clip.start && clip.end() < new_clip.end() {
-            self.inner.remove(&key);
+        if clip.start >= new_clip.start && clip.end() <= new_clip.end() {
+            self.remove(&key);
         }
     }
 
@@ -155,6 +175,24 @@ impl ClipCollection {
         self.inner.iter()
     }
 
+    /// get the clips overlapping the given range, sorted by start,
+    /// relies on 'insert' keeping clips from overlapping each other
+    pub fn clips_in(&self, range: Range<Tick>) -> impl Iterator<Item = &Clip> {
+        let start = ClipKey { start: range.start };
+        let end = ClipKey {
+            start: range.end.max(range.start),
+        };
+        let before = self
+            .inner
+            .range(..start)
+            .next_back()
+            .map(|(_, clip)| clip)
+            .filter(|clip| range.start < range.end && clip.end() > range.start);
+        before
+            .into_iter()
+            .chain(self.inner.range(start..end).map(|(_, clip)| clip))
+    }
+
     /// returns an iterator over the clips in this collection
     pub fn into_iter(self) -> IntoIter<ClipKey, Clip> {
         self.inner.into_iter()
@@ -162,33 +200,18 @@ impl ClipCollection {
 
     /// creates a new, empty, 'ClipCollection'
     pub fn new() -> ClipCollection {
-        ClipCollection {
-            inner: BTreeMap::new(),
-        }
+        ClipCollection::default()
     }
 
-    /// returns the clip at the given tick, if any
-    pub fn find_take(&mut self, clip_id: ClipId) -> Option<Clip> {
-        let key_to_remove = self.inner.iter().find_map(|(key, clip)| {
-            if clip.id() == clip_id {
-                Some(*key)
-            } else {
-                None
-            }
-        });
-
-        key_to_remove.and_then(|key| self.inner.remove(&key))
+    /// removes the clip with the given id, returning it if found
+    pub(crate) fn find_take(&mut self, clip_id: ClipId) -> Option<Clip> {
+        let key = self.key_of(clip_id)?;
+        self.remove(&key)
     }
 
-    /// find clip by key
+    /// find clip by id
     pub fn find(&self, id: ClipId) -> Option<&Clip> {
-        for (key, clip) in &self.inner {
-            if clip.id() == id {
-                return Some(clip);
-            }
-        }
-
-        None
+        self.key_of(id).and_then(|key| self.inner.get(&key))
     }
 }
 
@@ -200,12 +223,6 @@ impl Deref for ClipCollection {
     }
 }
 
-impl DerefMut for ClipCollection {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.inner
-    }
-}
-
 impl<'a> IntoIterator for &'a ClipCollection {
     type Item = (&'a ClipKey, &'a Clip);
     type IntoIter = Iter<'a, ClipKey, Clip>;
@@ -235,8 +252,8 @@ pub struct Clip {
     pub id: ClipId,
     /// visual name of the clip
     pub name: Box<String>,
-    /// notes in this clip
-    pub events: EventCollection,
+    /// notes in this clip, stored relative to the start of the clip
+    pub(crate) events: EventCollection,
     /// length of the clip
     pub duration: Tick,
 }
@@ -288,6 +305,57 @@ impl Clip {
             duration,
         }
     }
+
+    /// create a new clip holding 'events', stored relative to 'start'
+    pub fn with_events(start: Tick, name: &str, duration: Tick, events: EventCollection) -> Self {
+        Self {
+            start,
+            id: ClipId::new(),
+            name: Box::new(String::from(name)),
+            events,
+            duration,
+        }
+    }
+
+    /// get the events of this clip, stored relative to its start
+    pub fn events(&self) -> &EventCollection {
+        &self.events
+    }
+
+    /// splits the clip at 'tick', returning the part from 'tick' to the end as a new clip,
+    /// the moved events get new ids and events starting before 'tick' stay in this clip
+    pub(crate) fn split_off(&mut self, tick: Tick) -> Clip {
+        let tick = tick.max(self.start).min(self.end());
+        let offset = tick - self.start;
+        let mut left = EventCollection::new();
+        let mut right = EventCollection::new();
+        for (key, segments) in self.events.iter() {
+            for segment in segments {
+                if segment.start < offset {
+                    left.add_event(*key, *segment);
+                    continue;
+                }
+                let moved = EventSegment {
+                    id: DataId::new(),
+                    start: segment.start - offset,
+                    end: segment.end.saturating_sub(offset),
+                    ..*segment
+                };
+                right.add_event(key.saturating_sub(offset), moved);
+            }
+        }
+        let right_clip = Clip {
+            start: tick,
+            id: ClipId::new(),
+            name: self.name.clone(),
+            events: right,
+            duration: self.end() - tick,
+        };
+        self.events = left;
+        self.duration = offset;
+        right_clip
+    }
+
     /// get this clip's id as a string
     pub fn id_as_string(&self) -> String {
         self.id.to_string()
@@ -348,6 +416,26 @@ mod tests {
         // assert_eq!(clip_key.id, clip.id);
     }
 
+    #[test]
+    fn clips_in_includes_clip_started_before_the_range() {
+        let mut clip_collection = ClipCollection::new();
+        clip_collection.insert(Clip::new(0.into(), "a", 100.into()));
+        clip_collection.insert(Clip::new(100.into(), "b", 100.into()));
+        clip_collection.insert(Clip::new(300.into(), "c", 100.into()));
+
+        let names: Vec<_> = clip_collection
+            .clips_in(Tick::from(150)..Tick::from(301))
+            .map(|clip| clip.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["b", "c"]);
+        assert_eq!(
+            clip_collection
+                .clips_in(Tick::from(200)..Tick::from(300))
+                .count(),
+            0
+        );
+    }
+
     #[test]
     fn can_get_overlapped_clips() {
         // 0-100 overlapped by 50-150
@@ -383,4 +471,40 @@ mod tests {
         let overlapped = clip_collection.get_overlapped_inner(&sample_clip);
         assert_eq!(overlapped.len(), 1);
     }
+
+    #[test]
+    fn splitting_a_clip_moves_events_to_the_right_part_with_new_ids() {
+        let mut clip_collection = ClipCollection::new();
+        let clip = Clip::new(0.into(), "outer", 1920.into());
+        let ids: Vec<_> = clip
+            .events
+            .iter()
+            .flat_map(|(_, segments)| segments.iter().map(|segment| segment.id))
+            .collect();
+        clip_collection.insert(clip);
+        clip_collection.insert(Clip::with_events(
+            480.into(),
+            "inner",
+            240.into(),
+            EventCollection::new(),
+        ));
+
+        let parts: Vec<_> = clip_collection
+            .iter()
+            .map(|(_, clip)| (clip.start.as_u64(), clip.duration.as_u64()))
+            .collect();
+        assert_eq!(parts, vec![(0, 480), (480, 240), (720, 1200)]);
+        let left = &clip_collection[&ClipKey { start: 0.into() }];
+        let right = &clip_collection[&ClipKey { start: 720.into() }];
+        let left_ids: Vec<_> = left
+            .events
+            .iter()
+            .flat_map(|(_, segments)| segments.iter().map(|segment| segment.id))
+            .collect();
+        assert_eq!(left_ids, ids[..2]);
+        // the note at 960 moved to 240 in the right part and got a new id
+        let moved = right.events.get(&Tick::from(240)).unwrap()[0];
+        assert_eq!((moved.start, moved.end), (Tick::from(240), Tick::from(720)));
+        assert!(!ids.contains(&moved.id));
+    }
 }
@@ -3,10 +3,40 @@ use std::fmt::Display;
 /// bits for midi note on message
 pub const NOTE_ON_MSG: u8 = 0x90;
 
-/// bits for midi all notes off message
-pub const ALL_NOTE_ON_MSG: u8 = 0xB0;
 /// bits for midi note off message
 pub const NOTE_OFF_MSG: u8 = 0x80;
+/// bits for midi control change message
+pub const CONTROL_CHANGE_MSG: u8 = 0xB0;
+/// bits for midi time code quarter frame message
+pub const QUARTER_FRAME_MSG: u8 = 0xF1;
+/// bits starting a midi system exclusive message
+pub const SYSEX_START_MSG: u8 = 0xF0;
+/// bits ending a midi system exclusive message
+pub const SYSEX_END_MSG: u8 = 0xF7;
+/// header of a midi time code full frame system exclusive message, after the start byte
+pub const FULL_FRAME_HEADER: [u8; 4] = [0x7F, 0x7F, 0x01, 0x01];
+/// bits for midi song position pointer message
+pub const SONG_POSITION_MSG: u8 = 0xF2;
+/// bits for midi timing clock message
+pub const TIMING_CLOCK_MSG: u8 = 0xF8;
+/// bits for midi start message
+pub const START_MSG: u8 = 0xFA;
+/// bits for midi continue message
+pub const CONTINUE_MSG: u8 = 0xFB;
+/// bits for midi stop message
+pub const STOP_MSG: u8 = 0xFC;
+/// controller number of the all sound off channel mode message
+pub const ALL_SOUND_OFF_CC: u8 = 120;
+/// controller number of the reset all controllers channel mode message
+pub const RESET_ALL_CONTROLLERS_CC: u8 = 121;
+/// controller number of the all notes off channel mode message
+pub const ALL_NOTES_OFF_CC: u8 = 123;
+/// bits for midi program change message
+pub const PROGRAM_CHANGE_MSG: u8 = 0xC0;
+/// bits for midi channel pressure message
+pub const CHANNEL_PRESSURE_MSG: u8 = 0xD0;
+/// bits for midi pitch bend message
+pub const PITCH_BEND_MSG: u8 = 0xE0;
 
 /// id used to identify persistant objects like those stored in a project
 #[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
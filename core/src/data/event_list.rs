@@ -1,54 +1,142 @@
 use crate::{event::EventType, Tick};
-use std::collections::BTreeMap;
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::Range,
+};
 
 use super::{midi_message::MidiMessage, DataId};
 
 /// type used by the eventlist, stores events based on tick
 type EventListType = BTreeMap<Tick, Vec<EventSegment>>;
 
-/// a list of events, keyed by their `Tick`
+/// a list of events, keyed by their start `Tick`
 #[derive(Default, Debug, Clone)]
-pub struct EventCollection(Box<EventListType>);
+pub struct EventCollection {
+    /// events keyed by their start tick
+    inner: Box<EventListType>,
+    /// longest distance between the key of a segment and its end,
+    /// bounds how far back a range query has to look for overlapping notes
+    longest: Tick,
+    /// longest distance between the start of a segment and its key when stored after its start,
+    /// bounds how far ahead a range query has to look
+    lead: Tick,
+    /// key of every event by id
+    ids: HashMap<DataId, Tick>,
+}
 
 impl FromIterator<(Tick, Vec<EventSegment>)> for EventCollection {
     fn from_iter<T: IntoIterator<Item = (Tick, Vec<EventSegment>)>>(iter: T) -> Self {
-        let mut map = BTreeMap::new();
-        for (tick, trig) in iter {
-            map.insert(tick, trig);
+        let mut collection = EventCollection::new();
+        for (tick, segments) in iter {
+            for segment in segments {
+                collection.add_event(tick, segment);
+            }
         }
-        EventCollection(Box::new(map))
+        collection
     }
 }
 
 impl EventCollection {
     /// creates an empty `EventList`
     pub fn new() -> EventCollection {
-        EventCollection(Box::default())
+        EventCollection::default()
     }
 
     /// adds a new event to the 'EventList'
     pub fn add_event(&mut self, tick: Tick, event_entry: EventSegment) {
-        self.0
-            .get_mut(&tick)
-            .map(move |events| events.push(event_entry))
-            .unwrap_or_else(|| {
-                self.0.insert(tick, vec![event_entry]);
-            });
+        self.longest = self.longest.max(event_entry.end.saturating_sub(tick));
+        self.lead = self.lead.max(tick.saturating_sub(event_entry.start));
+        self.ids.insert(event_entry.id, tick);
+        self.inner.entry(tick).or_default().push(event_entry);
     }
 
     /// removes an ['Event'] from the 'EventList'
     pub fn get(&self, tick: &Tick) -> Option<&Vec<EventSegment>> {
-        self.0.get(tick)
+        self.inner.get(tick)
+    }
+
+    /// find an event by id
+    pub fn find(&self, id: DataId) -> Option<&EventSegment> {
+        let tick = self.ids.get(&id)?;
+        self.inner
+            .get(tick)?
+            .iter()
+            .find(|segment| segment.id == id)
+    }
+
+    /// moves the end of the event with the given id, returning false if it was not found
+    pub fn set_end(&mut self, id: DataId, end: Tick) -> bool {
+        let Some(tick) = self.ids.get(&id).copied() else {
+            return false;
+        };
+        let Some(segment) = self
+            .inner
+            .get_mut(&tick)
+            .and_then(|segments| segments.iter_mut().find(|segment| segment.id == id))
+        else {
+            return false;
+        };
+        segment.end = end;
+        self.longest = self.longest.max(end.saturating_sub(tick));
+        true
     }
 
-    /// gets a mutable iterator over the 'EventList', sorted by 'Tick'
+    /// removes the event with the given id, returning it if found
+    pub fn remove_event(&mut self, id: DataId) -> Option<EventSegment> {
+        let tick = self.ids.remove(&id)?;
+        let segments = self.inner.get_mut(&tick)?;
+        let index = segments.iter().position(|segment| segment.id == id)?;
+        let segment = segments.remove(index);
+        if segments.is_empty() {
+            self.inner.remove(&tick);
+        }
+        Some(segment)
+    }
+
+    /// get the key under which the event with the given id is stored
+    pub fn key_of(&self, id: DataId) -> Option<Tick> {
+        self.ids.get(&id).copied()
+    }
+
+    /// gets an iterator over the 'EventList', sorted by 'Tick'
     pub fn iter(&self) -> impl Iterator<Item = (&Tick, &Vec<EventSegment>)> {
-        self.0.iter()
+        self.inner.iter()
     }
 
-    /// gets a mutable iterator over the 'EventList', sorted by 'Tick'
-    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&Tick, &mut Vec<EventSegment>)> {
-        self.0.iter_mut()
+    /// edits every event in place with its key, sorted by 'Tick', the lookups used by range
+    /// queries and 'find' are rebuilt afterwards
+    pub fn for_each_mut(&mut self, mut edit: impl FnMut(&Tick, &mut EventSegment)) {
+        for (tick, segments) in self.inner.iter_mut() {
+            for segment in segments.iter_mut() {
+                edit(tick, segment);
+            }
+        }
+        self.reindex();
+    }
+
+    /// rebuilds the range bounds and the ids from the stored events
+    fn reindex(&mut self) {
+        self.longest = Tick::zero();
+        self.lead = Tick::zero();
+        self.ids.clear();
+        for (tick, segments) in self.inner.iter() {
+            for segment in segments {
+                self.longest = self.longest.max(segment.end.saturating_sub(*tick));
+                self.lead = self.lead.max(tick.saturating_sub(segment.start));
+                self.ids.insert(segment.id, *tick);
+            }
+        }
+    }
+
+    /// get the events overlapping the given range, including notes which started before it
+    pub fn events_in(&self, range: Range<Tick>) -> impl Iterator<Item = &EventSegment> {
+        let end = range.end.max(range.start);
+        let end = Tick::from(end.as_u64().saturating_add(self.lead.as_u64()));
+        let from = range.start.saturating_sub(self.longest);
+        self.inner
+            .range(from..end)
+            .flat_map(|(_, segments)| segments.iter())
+            .filter(move |segment| segment.start < range.end && segment.end > range.start)
     }
 
     // pub(crate) fn split_off(&self, left_duration: _) -> EventCollection {
@@ -119,3 +207,63 @@ impl EventSegment {
         self.end
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_in_includes_notes_started_before_the_range() {
+        let mut events = EventCollection::new();
+        events.add_event(
+            0.into(),
+            EventSegment::new2(0.into(), 960.into(), 40, 64, true),
+        );
+        events.add_event(
+            480.into(),
+            EventSegment::new2(480.into(), 600.into(), 41, 64, true),
+        );
+        events.add_event(
+            960.into(),
+            EventSegment::new2(960.into(), 1000.into(), 42, 64, true),
+        );
+
+        let keys: Vec<_> = events
+            .events_in(Tick::from(700)..Tick::from(961))
+            .map(|segment| segment.get_key())
+            .collect();
+        assert_eq!(keys, vec![40, 42]);
+
+        // events stored under a key after their start are still found
+        events.add_event(
+            2000.into(),
+            EventSegment::new2(1500.into(), 1600.into(), 43, 64, true),
+        );
+        assert_eq!(
+            events.events_in(Tick::from(1550)..Tick::from(1560)).count(),
+            1
+        );
+    }
+
+    #[test]
+    fn edited_events_are_still_found_by_range() {
+        let mut events = EventCollection::new();
+        events.add_event(
+            0.into(),
+            EventSegment::new2(0.into(), 100.into(), 40, 64, true),
+        );
+        events.add_event(
+            960.into(),
+            EventSegment::new2(960.into(), 1000.into(), 42, 64, true),
+        );
+
+        // lengthening a note lets it reach ranges it did not before
+        events.for_each_mut(|_, segment| segment.end = segment.end + Tick::from(400));
+        let keys: Vec<_> = events
+            .events_in(Tick::from(450)..Tick::from(460))
+            .map(|segment| segment.get_key())
+            .collect();
+        assert_eq!(keys, vec![40]);
+        assert_eq!(events.longest, Tick::from(500));
+    }
+}
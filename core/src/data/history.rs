@@ -0,0 +1,106 @@
+use super::{
+    clip::{Clip, ClipId},
+    event_list::EventSegment,
+    track::TrackId,
+};
+
+/// an edit which can be undone and redone, holding only what the edit itself added and removed,
+/// so edits made after it are kept when it is undone
+#[derive(Debug, Clone)]
+pub(crate) struct UndoStep {
+    /// name of the edit, shown to the user
+    pub(crate) name: String,
+    /// clips removed or changed by the edit as they were before it, with their track
+    pub(crate) removed: Vec<(TrackId, Clip)>,
+    /// clips added or changed by the edit as they were after it, with their track
+    pub(crate) added: Vec<(TrackId, Clip)>,
+    /// events the edit added to clips which were there before it
+    pub(crate) events: Vec<(ClipId, EventSegment)>,
+    /// earlier takes the edit kept on tracks
+    pub(crate) takes: Vec<(TrackId, Clip)>,
+}
+
+impl UndoStep {
+    /// creates an empty step called 'name'
+    pub(crate) fn new(name: &str) -> Self {
+        Self {
+            name: String::from(name),
+            removed: Vec::new(),
+            added: Vec::new(),
+            events: Vec::new(),
+            takes: Vec::new(),
+        }
+    }
+
+    /// true if the edit did not change anything
+    pub(crate) fn is_empty(&self) -> bool {
+        self.removed.is_empty()
+            && self.added.is_empty()
+            && self.events.is_empty()
+            && self.takes.is_empty()
+    }
+
+    /// compares the clips of a track before and after the edit, a clip which moved or changed
+    /// its length counts as removed and added
+    pub(crate) fn diff(&mut self, track_id: TrackId, before: Vec<Clip>, after: Vec<Clip>) {
+        let same =
+            |a: &Clip, b: &Clip| a.id == b.id && a.start == b.start && a.duration == b.duration;
+        for clip in &after {
+            if !before.iter().any(|old| same(old, clip)) {
+                self.added.push((track_id, clip.clone()));
+            }
+        }
+        for clip in before {
+            if !after.iter().any(|new| same(new, &clip)) {
+                self.removed.push((track_id, clip));
+            }
+        }
+    }
+}
+
+/// steps which can be undone and redone
+#[derive(Default, Debug)]
+pub(crate) struct History {
+    /// steps to undo, the latest last
+    undo: Vec<UndoStep>,
+    /// undone steps which can be redone, the latest undone last
+    redo: Vec<UndoStep>,
+}
+
+impl History {
+    /// adds a new step, steps which were undone can no longer be redone
+    pub(crate) fn push(&mut self, step: UndoStep) {
+        self.undo.push(step);
+        self.redo.clear();
+    }
+
+    /// takes the step to undo
+    pub(crate) fn pop_undo(&mut self) -> Option<UndoStep> {
+        self.undo.pop()
+    }
+
+    /// takes the step to redo
+    pub(crate) fn pop_redo(&mut self) -> Option<UndoStep> {
+        self.redo.pop()
+    }
+
+    /// adds a step which was undone, so it can be redone
+    pub(crate) fn push_redo(&mut self, step: UndoStep) {
+        self.redo.push(step);
+    }
+
+    /// adds a step which was redone, keeping the steps which can be redone
+    pub(crate) fn push_undo(&mut self, step: UndoStep) {
+        self.undo.push(step);
+    }
+
+    /// get the name of the step which is undone next
+    pub(crate) fn undo_name(&self) -> Option<&str> {
+        self.undo.last().map(|step| step.name.as_str())
+    }
+
+    /// get the name of the step which is redone next
+    pub(crate) fn redo_name(&self) -> Option<&str> {
+        self.redo.last().map(|step| step.name.as_str())
+    }
+}
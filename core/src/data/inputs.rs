@@ -0,0 +1,142 @@
+use super::{ports::insert_sorted, MidiMessage};
+
+/// decides which messages received on an input are used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputFilter {
+    /// one bit per midi channel, messages on channels without their bit set are dropped
+    pub channels: u16,
+    /// let note on and note off messages through
+    pub notes: bool,
+    /// let control change messages through
+    pub controllers: bool,
+    /// let program change messages through
+    pub program_changes: bool,
+    /// let pitch bend messages through
+    pub pitch_bend: bool,
+    /// let channel pressure messages through
+    pub channel_pressure: bool,
+}
+
+impl Default for InputFilter {
+    fn default() -> Self {
+        Self {
+            channels: u16::MAX,
+            notes: true,
+            controllers: true,
+            program_changes: true,
+            pitch_bend: true,
+            channel_pressure: true,
+        }
+    }
+}
+
+impl InputFilter {
+    /// creates a filter letting every message on a single channel through
+    pub fn channel(channel: u8) -> Self {
+        Self {
+            channels: 1 << (channel & 0x0F),
+            ..Self::default()
+        }
+    }
+
+    /// true if a message received on 'channel' passes the filter, messages without a channel,
+    /// like clock and transport messages, never pass
+    pub fn allows(&self, message: &MidiMessage, channel: u8) -> bool {
+        let kind = match message {
+            MidiMessage::NoteOn { .. } | MidiMessage::NoteOff { .. } => self.notes,
+            MidiMessage::ControlChange { .. } | MidiMessage::AllNoteOff => self.controllers,
+            MidiMessage::ProgramChange { .. } => self.program_changes,
+            MidiMessage::PitchBend { .. } => self.pitch_bend,
+            MidiMessage::ChannelPressure { .. } => self.channel_pressure,
+            _ => false,
+        };
+        kind && self.channels & (1 << (channel & 0x0F)) != 0
+    }
+}
+
+/// a midi input of the project, connected to the midi input with the same name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputPort {
+    /// id used by tracks to choose the input they record from
+    pub id: u8,
+    /// name of the midi input the port is connected to
+    pub name: String,
+    /// which received messages are used
+    pub filter: InputFilter,
+    /// true if the received messages are echoed to the instrument of the selected track
+    pub thru: bool,
+}
+
+/// the midi inputs of a project
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct InputMap {
+    /// inputs sorted by id
+    inner: Vec<InputPort>,
+}
+
+impl InputMap {
+    /// connects the input 'id' to the midi input called 'name', letting every message through
+    /// and echoing them, replacing the previous input with that id
+    pub fn assign(&mut self, id: u8, name: &str) {
+        let port = InputPort {
+            id,
+            name: String::from(name),
+            filter: InputFilter::default(),
+            thru: true,
+        };
+        insert_sorted(&mut self.inner, port, |port| port.id);
+    }
+
+    /// removes an input, returning it if found
+    pub fn remove(&mut self, id: u8) -> Option<InputPort> {
+        let index = self.inner.iter().position(|port| port.id == id)?;
+        Some(self.inner.remove(index))
+    }
+
+    /// get an input by id
+    pub fn get(&self, id: u8) -> Option<&InputPort> {
+        self.inner.iter().find(|port| port.id == id)
+    }
+
+    /// get an input by id for editing its filter or thru setting
+    pub fn get_mut(&mut self, id: u8) -> Option<&mut InputPort> {
+        self.inner.iter_mut().find(|port| port.id == id)
+    }
+
+    /// get an iterator over the inputs, sorted by id
+    pub fn iter(&self) -> std::slice::Iter<'_, InputPort> {
+        self.inner.iter()
+    }
+
+    /// true if there are no inputs
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_drop_other_channels_and_message_kinds() {
+        let note = MidiMessage::NoteOn {
+            key: 60,
+            velocity: 100,
+        };
+        let mut filter = InputFilter::channel(2);
+        assert!(filter.allows(&note, 2));
+        assert!(!filter.allows(&note, 3));
+        assert!(!filter.allows(&MidiMessage::TimingClock, 2));
+
+        filter.notes = false;
+        assert!(!filter.allows(&note, 2));
+        assert!(filter.allows(
+            &MidiMessage::ControlChange {
+                controller: 1,
+                value: 64
+            },
+            2
+        ));
+    }
+}
@@ -0,0 +1,169 @@
+use std::ops::Range;
+
+use crate::Tick;
+
+/// a named position in the arrangement, like 'Verse' or 'Chorus'
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Marker {
+    /// visual name of the marker
+    pub name: String,
+    /// position of the marker
+    pub tick: Tick,
+}
+
+impl Marker {
+    /// creates a new 'Marker'
+    pub fn new(name: &str, tick: Tick) -> Self {
+        Self {
+            name: String::from(name),
+            tick,
+        }
+    }
+}
+
+/// a list of markers, sorted by tick
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct MarkerCollection {
+    /// inner list of markers
+    inner: Vec<Marker>,
+}
+
+impl MarkerCollection {
+    /// adds a marker, keeping the collection sorted by tick
+    pub fn add(&mut self, marker: Marker) {
+        let index = self.inner.partition_point(|m| m.tick <= marker.tick);
+        self.inner.insert(index, marker);
+    }
+
+    /// removes the first marker with the given name, returning it if found
+    pub fn remove(&mut self, name: &str) -> Option<Marker> {
+        let index = self.inner.iter().position(|m| m.name == name)?;
+        Some(self.inner.remove(index))
+    }
+
+    /// find the first marker with the given name
+    pub fn find(&self, name: &str) -> Option<&Marker> {
+        self.inner.iter().find(|m| m.name == name)
+    }
+
+    /// get the marker at the given index
+    pub fn get(&self, index: usize) -> Option<&Marker> {
+        self.inner.get(index)
+    }
+
+    /// get the last marker at or before the given tick
+    pub fn at(&self, tick: Tick) -> Option<&Marker> {
+        let index = self.inner.partition_point(|m| m.tick <= tick);
+        index.checked_sub(1).map(|index| &self.inner[index])
+    }
+
+    /// get the first marker after the given tick
+    pub fn next(&self, tick: Tick) -> Option<&Marker> {
+        let index = self.inner.partition_point(|m| m.tick <= tick);
+        self.inner.get(index)
+    }
+
+    /// get the last marker before the given tick
+    pub fn previous(&self, tick: Tick) -> Option<&Marker> {
+        let index = self.inner.partition_point(|m| m.tick < tick);
+        index.checked_sub(1).map(|index| &self.inner[index])
+    }
+
+    /// get an iterator over the markers, sorted by tick
+    pub fn iter(&self) -> std::slice::Iter<'_, Marker> {
+        self.inner.iter()
+    }
+
+    /// get the number of markers
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// true if there are no markers
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+}
+
+impl FromIterator<Marker> for MarkerCollection {
+    fn from_iter<T: IntoIterator<Item = Marker>>(iter: T) -> Self {
+        let mut markers = MarkerCollection::default();
+        for marker in iter {
+            markers.add(marker);
+        }
+        markers
+    }
+}
+
+/// loop and punch locators of a project
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct Locators {
+    /// range which is repeated during playback
+    pub loop_range: Option<Range<Tick>>,
+    /// true if playback should repeat the loop range
+    pub loop_enabled: bool,
+    /// tick at which recording starts when punching in
+    pub punch_in: Option<Tick>,
+    /// tick at which recording stops when punching out
+    pub punch_out: Option<Tick>,
+}
+
+impl Locators {
+    /// get the loop range if looping is enabled and the range is not empty
+    pub fn active_loop(&self) -> Option<Range<Tick>> {
+        self.loop_range
+            .clone()
+            .filter(|range| self.loop_enabled && range.start < range.end)
+    }
+
+    /// repeats 'range' during playback, 'None' disables looping and keeps the range
+    pub fn set_loop(&mut self, range: Option<Range<Tick>>) {
+        self.loop_enabled = range.is_some();
+        if range.is_some() {
+            self.loop_range = range;
+        }
+    }
+
+    /// true if the given tick lies between the punch in and punch out locators,
+    /// a missing locator leaves that side open
+    pub fn is_punched_in(&self, tick: Tick) -> bool {
+        self.punch_in.map(|start| tick >= start).unwrap_or(true)
+            && self.punch_out.map(|end| tick < end).unwrap_or(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn markers_stay_sorted() {
+        let markers: MarkerCollection = [
+            Marker::new("chorus", Tick::from(1920)),
+            Marker::new("verse", Tick::from(0)),
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(markers.get(0).unwrap().name, "verse");
+        assert_eq!(markers.at(Tick::from(2000)).unwrap().name, "chorus");
+        assert!(markers.at(Tick::from(0)).is_some());
+        assert_eq!(markers.next(Tick::from(0)).unwrap().name, "chorus");
+        assert_eq!(markers.previous(Tick::from(1920)).unwrap().name, "verse");
+        assert!(markers.previous(Tick::from(0)).is_none());
+    }
+
+    #[test]
+    fn disabling_the_loop_keeps_its_range() {
+        let mut locators = Locators::default();
+        locators.set_loop(Some(Tick::from(0)..Tick::from(1920)));
+        assert_eq!(
+            locators.active_loop(),
+            Some(Tick::from(0)..Tick::from(1920))
+        );
+
+        locators.set_loop(None);
+        assert_eq!(locators.active_loop(), None);
+        assert_eq!(locators.loop_range, Some(Tick::from(0)..Tick::from(1920)));
+    }
+}
@@ -1,9 +1,17 @@
 use std::fmt::Display;
 
-use super::common::{ALL_NOTE_ON_MSG, NOTE_OFF_MSG, NOTE_ON_MSG};
+use super::{
+    common::{
+        ALL_NOTES_OFF_CC, ALL_SOUND_OFF_CC, CHANNEL_PRESSURE_MSG, CONTINUE_MSG, CONTROL_CHANGE_MSG,
+        FULL_FRAME_HEADER, NOTE_OFF_MSG, NOTE_ON_MSG, PITCH_BEND_MSG, PROGRAM_CHANGE_MSG,
+        QUARTER_FRAME_MSG, RESET_ALL_CONTROLLERS_CC, SONG_POSITION_MSG, START_MSG, STOP_MSG,
+        SYSEX_END_MSG, SYSEX_START_MSG, TIMING_CLOCK_MSG,
+    },
+    timecode::{FrameRate, Timecode},
+};
 
 /// midi message types
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MidiMessage {
     /// note on midi message
     NoteOn {
@@ -21,24 +29,166 @@ pub enum MidiMessage {
     },
     /// all notes off midi message
     AllNoteOff,
+    /// control change midi message
+    ControlChange {
+        /// controller number, 0-127
+        controller: u8,
+        /// new value of the controller, 0-127
+        value: u8,
+    },
+    /// program change midi message, selects a sound from the current bank
+    ProgramChange {
+        /// program number, 0-127
+        program: u8,
+    },
+    /// pitch bend midi message
+    PitchBend {
+        /// 14 bit bend amount, 0-16383 where 8192 is the center
+        value: u16,
+    },
+    /// channel pressure (aftertouch) midi message
+    ChannelPressure {
+        /// pressure amount, 0-127
+        pressure: u8,
+    },
+    /// timing clock system message, sent 24 times per quarter note
+    TimingClock,
+    /// start system message, starts playback from the beginning of the song
+    Start,
+    /// continue system message, resumes playback from the song position
+    Continue,
+    /// stop system message
+    Stop,
+    /// song position pointer system message
+    SongPosition {
+        /// position in sixteenth notes from the beginning of the song, 0-16383
+        position: u16,
+    },
+    /// midi time code quarter frame message, eight of them carry a full timecode
+    QuarterFrame {
+        /// piece number in the high nibble, a nibble of the timecode in the low one
+        data: u8,
+    },
+    /// midi time code full frame message, sent when the position jumps
+    FullFrame(Timecode),
 }
 
 impl MidiMessage {
     /// converts this MidiMessage to bits ready to send to a midi port
-    pub fn to_midi(&self, channel: u8) -> [u8; 3] {
+    pub fn to_midi(&self, channel: u8) -> Vec<u8> {
         match self {
-            MidiMessage::NoteOn { key, velocity } => [NOTE_ON_MSG | channel, *key, *velocity],
-            MidiMessage::NoteOff { key, velocity } => [NOTE_OFF_MSG | channel, *key, *velocity],
-            MidiMessage::AllNoteOff => [ALL_NOTE_ON_MSG, 123, 0],
+            MidiMessage::NoteOn { key, velocity } => vec![NOTE_ON_MSG | channel, *key, *velocity],
+            MidiMessage::NoteOff { key, velocity } => vec![NOTE_OFF_MSG | channel, *key, *velocity],
+            MidiMessage::AllNoteOff => vec![CONTROL_CHANGE_MSG | channel, ALL_NOTES_OFF_CC, 0],
+            MidiMessage::ControlChange { controller, value } => {
+                vec![CONTROL_CHANGE_MSG | channel, *controller, *value]
+            }
+            MidiMessage::ProgramChange { program } => vec![PROGRAM_CHANGE_MSG | channel, *program],
+            MidiMessage::PitchBend { value } => vec![
+                PITCH_BEND_MSG | channel,
+                (*value & 0x7F) as u8,
+                ((*value >> 7) & 0x7F) as u8,
+            ],
+            MidiMessage::ChannelPressure { pressure } => {
+                vec![CHANNEL_PRESSURE_MSG | channel, *pressure]
+            }
+            MidiMessage::TimingClock => vec![TIMING_CLOCK_MSG],
+            MidiMessage::Start => vec![START_MSG],
+            MidiMessage::Continue => vec![CONTINUE_MSG],
+            MidiMessage::Stop => vec![STOP_MSG],
+            MidiMessage::SongPosition { position } => vec![
+                SONG_POSITION_MSG,
+                (*position & 0x7F) as u8,
+                ((*position >> 7) & 0x7F) as u8,
+            ],
+            MidiMessage::QuarterFrame { data } => vec![QUARTER_FRAME_MSG, *data & 0x7F],
+            MidiMessage::FullFrame(timecode) => {
+                let mut bytes = vec![SYSEX_START_MSG];
+                bytes.extend(FULL_FRAME_HEADER);
+                bytes.extend([
+                    timecode.rate.code() << 5 | timecode.hours & 0x1F,
+                    timecode.minutes,
+                    timecode.seconds,
+                    timecode.frames,
+                    SYSEX_END_MSG,
+                ]);
+                bytes
+            }
         }
     }
 
+    /// decodes the bytes of a midi message as (message, channel), system messages are on channel 0,
+    /// returns 'None' for messages which are not supported or incomplete
+    pub fn from_midi(bytes: &[u8]) -> Option<(MidiMessage, u8)> {
+        let status = *bytes.first()?;
+        let data = |index: usize| bytes.get(index).map(|byte| byte & 0x7F);
+        let channel = status & 0x0F;
+        let message = match status {
+            TIMING_CLOCK_MSG => return Some((MidiMessage::TimingClock, 0)),
+            START_MSG => return Some((MidiMessage::Start, 0)),
+            CONTINUE_MSG => return Some((MidiMessage::Continue, 0)),
+            STOP_MSG => return Some((MidiMessage::Stop, 0)),
+            QUARTER_FRAME_MSG => return Some((MidiMessage::QuarterFrame { data: data(1)? }, 0)),
+            SYSEX_START_MSG if bytes.get(1..5) == Some(&FULL_FRAME_HEADER[..]) => {
+                let timecode = Timecode {
+                    hours: data(5)? & 0x1F,
+                    minutes: data(6)?,
+                    seconds: data(7)?,
+                    frames: data(8)?,
+                    rate: FrameRate::from_code(data(5)? >> 5),
+                };
+                return Some((MidiMessage::FullFrame(timecode), 0));
+            }
+            SONG_POSITION_MSG => {
+                let position = data(1)? as u16 | (data(2)? as u16) << 7;
+                return Some((MidiMessage::SongPosition { position }, 0));
+            }
+            _ => match status & 0xF0 {
+                NOTE_OFF_MSG => MidiMessage::NoteOff {
+                    key: data(1)?,
+                    velocity: data(2)?,
+                },
+                NOTE_ON_MSG => MidiMessage::NoteOn {
+                    key: data(1)?,
+                    velocity: data(2)?,
+                },
+                CONTROL_CHANGE_MSG if data(1)? == ALL_NOTES_OFF_CC => MidiMessage::AllNoteOff,
+                CONTROL_CHANGE_MSG => MidiMessage::ControlChange {
+                    controller: data(1)?,
+                    value: data(2)?,
+                },
+                PROGRAM_CHANGE_MSG => MidiMessage::ProgramChange { program: data(1)? },
+                CHANNEL_PRESSURE_MSG => MidiMessage::ChannelPressure { pressure: data(1)? },
+                PITCH_BEND_MSG => MidiMessage::PitchBend {
+                    value: data(1)? as u16 | (data(2)? as u16) << 7,
+                },
+                _ => return None,
+            },
+        };
+        Some((message, channel))
+    }
+
+    /// get the messages which silence a channel, all sound off, reset all controllers and all notes off
+    pub fn panic() -> [MidiMessage; 3] {
+        [
+            MidiMessage::ControlChange {
+                controller: ALL_SOUND_OFF_CC,
+                value: 0,
+            },
+            MidiMessage::ControlChange {
+                controller: RESET_ALL_CONTROLLERS_CC,
+                value: 0,
+            },
+            MidiMessage::AllNoteOff,
+        ]
+    }
+
     /// get the key/note of this message
     pub(crate) fn get_key(&self) -> u8 {
         match self {
             MidiMessage::NoteOn { key, .. } => *key,
             MidiMessage::NoteOff { key, .. } => *key,
-            MidiMessage::AllNoteOff => 0,
+            _ => 0,
         }
     }
 }
@@ -62,6 +212,76 @@ impl Display for MidiMessage {
                 f.write_str(&format!("[note_off]key:{}, velocity:{}", key, velocity))
             }
             MidiMessage::AllNoteOff => f.write_str("[global_note_off]"),
+            MidiMessage::ControlChange { controller, value } => f.write_str(&format!(
+                "[control_change]controller:{}, value:{}",
+                controller, value
+            )),
+            MidiMessage::ProgramChange { program } => {
+                f.write_str(&format!("[program_change]program:{}", program))
+            }
+            MidiMessage::PitchBend { value } => {
+                f.write_str(&format!("[pitch_bend]value:{}", value))
+            }
+            MidiMessage::ChannelPressure { pressure } => {
+                f.write_str(&format!("[channel_pressure]pressure:{}", pressure))
+            }
+            MidiMessage::TimingClock => f.write_str("[timing_clock]"),
+            MidiMessage::Start => f.write_str("[start]"),
+            MidiMessage::Continue => f.write_str("[continue]"),
+            MidiMessage::Stop => f.write_str("[stop]"),
+            MidiMessage::SongPosition { position } => {
+                f.write_str(&format!("[song_position]position:{}", position))
+            }
+            MidiMessage::QuarterFrame { data } => {
+                f.write_str(&format!("[quarter_frame]data:{}", data))
+            }
+            MidiMessage::FullFrame(timecode) => f.write_str(&format!(
+                "[full_frame]{:02}:{:02}:{:02}:{:02}",
+                timecode.hours, timecode.minutes, timecode.seconds, timecode.frames
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channel_mode_messages_use_the_channel() {
+        assert_eq!(MidiMessage::AllNoteOff.to_midi(3), vec![0xB3, 123, 0]);
+        let bytes: Vec<_> = MidiMessage::panic()
+            .iter()
+            .map(|message| message.to_midi(15))
+            .collect();
+        assert_eq!(
+            bytes,
+            vec![vec![0xBF, 120, 0], vec![0xBF, 121, 0], vec![0xBF, 123, 0]]
+        );
+    }
+
+    #[test]
+    fn decodes_what_it_encodes() {
+        let messages = [
+            MidiMessage::NoteOn {
+                key: 60,
+                velocity: 100,
+            },
+            MidiMessage::AllNoteOff,
+            MidiMessage::PitchBend { value: 9000 },
+            MidiMessage::ProgramChange { program: 7 },
+            MidiMessage::SongPosition { position: 300 },
+            MidiMessage::TimingClock,
+            MidiMessage::FullFrame(Timecode::from_frames(1000, FrameRate::Fps2997Drop)),
+        ];
+        for message in messages {
+            let channel = match message.to_midi(0)[0] >= 0xF0 {
+                true => 0,
+                false => 9,
+            };
+            let decoded = MidiMessage::from_midi(&message.to_midi(channel));
+            assert_eq!(decoded, Some((message, channel)));
         }
+        assert_eq!(MidiMessage::from_midi(&[0x90, 60]), None);
     }
 }
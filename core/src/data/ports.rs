@@ -0,0 +1,119 @@
+/// a logical midi port of the project, connected to the midi output with the same name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MidiPort {
+    /// id used by instruments and sync outputs to refer to the port
+    pub id: u8,
+    /// name of the midi output the port is connected to
+    pub name: String,
+    /// true if hexencer creates a port called 'name' which other applications can connect to,
+    /// instead of connecting to an existing one
+    pub is_virtual: bool,
+}
+
+/// the logical midi ports of a project
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct PortMap {
+    /// ports sorted by id
+    inner: Vec<MidiPort>,
+    /// virtual inputs created for other applications to send to, sorted by id
+    virtual_inputs: Vec<MidiPort>,
+}
+
+impl PortMap {
+    /// connects the logical port 'id' to the midi output called 'name', replacing its previous output
+    pub fn assign(&mut self, id: u8, name: &str) {
+        insert(&mut self.inner, id, name, false);
+    }
+
+    /// connects the logical port 'id' to a virtual output called 'name', replacing its previous output
+    pub fn assign_virtual(&mut self, id: u8, name: &str) {
+        insert(&mut self.inner, id, name, true);
+    }
+
+    /// removes a logical port, returning it if found
+    pub fn remove(&mut self, id: u8) -> Option<MidiPort> {
+        let index = self.inner.iter().position(|port| port.id == id)?;
+        Some(self.inner.remove(index))
+    }
+
+    /// get a logical port by id
+    pub fn get(&self, id: u8) -> Option<&MidiPort> {
+        self.inner.iter().find(|port| port.id == id)
+    }
+
+    /// get an iterator over the logical ports, sorted by id
+    pub fn iter(&self) -> std::slice::Iter<'_, MidiPort> {
+        self.inner.iter()
+    }
+
+    /// get the number of logical ports
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// true if there are no logical ports
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// adds a virtual input called 'name', the messages it receives are tagged with 'id' and routed
+    /// like those of the input with that id, replacing the previous virtual input with that id
+    pub fn add_virtual_input(&mut self, id: u8, name: &str) {
+        insert(&mut self.virtual_inputs, id, name, true);
+    }
+
+    /// removes a virtual input, returning it if found
+    pub fn remove_virtual_input(&mut self, id: u8) -> Option<MidiPort> {
+        let index = self.virtual_inputs.iter().position(|port| port.id == id)?;
+        Some(self.virtual_inputs.remove(index))
+    }
+
+    /// get an iterator over the virtual inputs, sorted by id
+    pub fn virtual_inputs(&self) -> std::slice::Iter<'_, MidiPort> {
+        self.virtual_inputs.iter()
+    }
+}
+
+/// inserts a port into a list sorted by id, replacing the port with the same id
+fn insert(ports: &mut Vec<MidiPort>, id: u8, name: &str, is_virtual: bool) {
+    let port = MidiPort {
+        id,
+        name: String::from(name),
+        is_virtual,
+    };
+    insert_sorted(ports, port, |port| port.id);
+}
+
+/// inserts an item into a list sorted by the id 'id_of' gives, replacing the item with the same id
+pub(super) fn insert_sorted<T>(items: &mut Vec<T>, item: T, id_of: impl Fn(&T) -> u8) {
+    let id = id_of(&item);
+    let index = items.partition_point(|existing| id_of(existing) < id);
+    match items.get_mut(index) {
+        Some(existing) if id_of(existing) == id => *existing = item,
+        _ => items.insert(index, item),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assigning_replaces_the_output_of_a_port() {
+        let mut ports = PortMap::default();
+        ports.assign(1, "synth");
+        ports.assign(0, "drums");
+        ports.assign(1, "bass");
+
+        let names: Vec<_> = ports.iter().map(|port| port.name.as_str()).collect();
+        assert_eq!(names, vec!["drums", "bass"]);
+        assert_eq!(ports.remove(0).unwrap().name, "drums");
+        assert!(ports.get(0).is_none());
+
+        ports.assign_virtual(1, "hexencer out");
+        assert!(ports.get(1).unwrap().is_virtual);
+        ports.add_virtual_input(0, "hexencer in");
+        assert_eq!(ports.len(), 1);
+        assert_eq!(ports.virtual_inputs().count(), 1);
+    }
+}
@@ -1,18 +1,119 @@
-use crate::Tick;
+use std::{collections::HashMap, ops::Range};
+
+use crate::{DataId, Tick, PPQN};
 
 use super::{
-    clip::{Clip, ClipId},
-    track::{Track, TrackCollection, TrackId},
-    InstrumentManager,
+    change::DataChange,
+    clip::{Clip, ClipId, ClipKey},
+    event_list::{EventCollection, EventSegment},
+    history::{History, UndoStep},
+    inputs::InputMap,
+    locators::{Locators, MarkerCollection},
+    ports::PortMap,
+    recording::{RecordMode, Take},
+    session::Session,
+    sync::SyncSettings,
+    track::{ArrangedEvent, Track, TrackCollection, TrackId},
+    DataLayerError, InstrumentManager,
 };
 
+/// most changes kept for the 'StorageInterface', a project used without one collapses them
+/// into a single 'DataChange::Resync'
+const MAX_PENDING_CHANGES: usize = 1024;
+
+/// where an event is stored in the project
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EventLocation {
+    /// track of the clip holding the event
+    pub track_id: TrackId,
+    /// clip holding the event
+    pub clip_id: ClipId,
+    /// key of the event in the clip
+    pub tick: Tick,
+}
+
+/// lookup of clips and events by id, kept in sync by the mutating methods of 'Project'
+#[derive(Default, Debug)]
+struct ProjectIndex {
+    /// location of every clip
+    clips: HashMap<ClipId, (TrackId, ClipKey)>,
+    /// location of every event
+    events: HashMap<DataId, EventLocation>,
+}
+
+impl ProjectIndex {
+    /// adds a clip and its events to the index
+    fn add_clip(&mut self, track_id: TrackId, clip: &Clip) {
+        self.clips.insert(clip.id, (track_id, ClipKey::from(clip)));
+        for (tick, segments) in clip.events.iter() {
+            for segment in segments {
+                let location = EventLocation {
+                    track_id,
+                    clip_id: clip.id,
+                    tick: *tick,
+                };
+                self.events.insert(segment.id, location);
+            }
+        }
+    }
+
+    /// removes a clip and its events from the index
+    fn remove_clip(&mut self, clip: &Clip) {
+        self.clips.remove(&clip.id);
+        for (_, segments) in clip.events.iter() {
+            for segment in segments {
+                // a clip split off this one may already own the event
+                if self
+                    .events
+                    .get(&segment.id)
+                    .is_some_and(|location| location.clip_id == clip.id)
+                {
+                    self.events.remove(&segment.id);
+                }
+            }
+        }
+    }
+
+    /// adds all clips of a track to the index
+    fn add_track(&mut self, track: &Track) {
+        for (_, clip) in track.clip_collection.iter() {
+            self.add_clip(track.id, clip);
+        }
+    }
+
+    /// removes all clips of a track from the index
+    fn remove_track(&mut self, track: &Track) {
+        for (_, clip) in track.clip_collection.iter() {
+            self.remove_clip(clip);
+        }
+    }
+}
+
 /// presents a hexencer project
 #[derive(Default, Debug)]
 pub struct Project {
     /// collection of tracks for this project
-    pub track_collection: TrackCollection,
+    track_collection: TrackCollection,
     /// collection of instruments for this project
     pub instrument_manager: InstrumentManager,
+    /// named positions in the arrangement
+    markers: MarkerCollection,
+    /// loop and punch locators
+    locators: Locators,
+    /// session grid used for launching clips live
+    session: Session,
+    /// how other devices are kept in sync
+    sync: SyncSettings,
+    /// logical midi ports used by instruments and sync outputs
+    ports: PortMap,
+    /// midi inputs recorded from by armed tracks
+    inputs: InputMap,
+    /// lookup of clips and events by id
+    index: ProjectIndex,
+    /// edits which can be undone
+    history: History,
+    /// changes made since they were last taken, replaced by a resync when nobody takes them
+    changes: Vec<DataChange>,
 }
 
 impl Project {
@@ -21,9 +122,23 @@ impl Project {
         Self {
             track_collection: TrackCollection::default(),
             instrument_manager: InstrumentManager::default(),
+            markers: MarkerCollection::default(),
+            locators: Locators::default(),
+            session: Session::default(),
+            sync: SyncSettings::default(),
+            ports: PortMap::default(),
+            inputs: InputMap::default(),
+            index: ProjectIndex::default(),
+            history: History::default(),
+            changes: Vec::new(),
         }
     }
 
+    /// get the tracks of the project
+    pub fn tracks(&self) -> &TrackCollection {
+        &self.track_collection
+    }
+
     /// get the current track count
     pub fn track_count(&self) -> usize {
         self.track_collection.len()
@@ -31,6 +146,8 @@ impl Project {
 
     /// add a new track to the collection
     pub fn add_track(&mut self, track: Track) {
+        self.index.add_track(&track);
+        self.record(DataChange::TrackAdded(track.id));
         self.track_collection.push(track);
     }
 
@@ -40,20 +157,433 @@ impl Project {
 
     /// remove a track from the collection
     pub fn remove_track(&mut self) {
-        self.track_collection.pop();
+        if let Some(track) = self.track_collection.pop() {
+            self.index.remove_track(&track);
+            self.record(DataChange::TrackRemoved(track.id));
+        }
     }
 
-    /// returns reference to the clip if found, else 'None'
-    pub fn find_clip(&self, target_clip_id: ClipId) -> Option<Clip> {
-        for track in self.track_collection.iter() {
-            for (_clip_key, clip) in track.clip_collection.iter() {
-                if clip.id == target_clip_id {
-                    let clip = clip.to_owned();
-                    return Some(clip);
+    /// edit the settings of a track, like its instrument or arpeggiator,
+    /// the clips of the track are indexed again afterwards
+    pub fn edit_track(&mut self, track_id: TrackId, edit: impl FnOnce(&mut Track)) -> bool {
+        let Some(track) = self.track_collection.get_mut(track_id) else {
+            return false;
+        };
+        self.index.remove_track(track);
+        edit(track);
+        self.index.add_track(track);
+        self.record(DataChange::TrackChanged(track_id));
+        true
+    }
+
+    /// get the sync settings of the project
+    pub fn sync(&self) -> &SyncSettings {
+        &self.sync
+    }
+
+    /// edit the sync settings of the project, like its midi clock outputs
+    pub fn edit_sync(&mut self, edit: impl FnOnce(&mut SyncSettings)) {
+        edit(&mut self.sync);
+        self.record(DataChange::SyncChanged);
+    }
+
+    /// get the logical midi ports of the project
+    pub fn ports(&self) -> &PortMap {
+        &self.ports
+    }
+
+    /// edit the logical midi ports of the project
+    pub fn edit_ports(&mut self, edit: impl FnOnce(&mut PortMap)) {
+        edit(&mut self.ports);
+        self.record(DataChange::PortsChanged);
+    }
+
+    /// get the session grid of the project
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    /// edit the session grid of the project, like the clips in its slots
+    pub fn edit_session(&mut self, edit: impl FnOnce(&mut Session)) {
+        edit(&mut self.session);
+        self.record(DataChange::SessionChanged);
+    }
+
+    /// get the markers of the project
+    pub fn markers(&self) -> &MarkerCollection {
+        &self.markers
+    }
+
+    /// edit the markers of the project
+    pub fn edit_markers(&mut self, edit: impl FnOnce(&mut MarkerCollection)) {
+        edit(&mut self.markers);
+        self.record(DataChange::MarkersChanged);
+    }
+
+    /// get the loop and punch locators of the project
+    pub fn locators(&self) -> &Locators {
+        &self.locators
+    }
+
+    /// edit the loop and punch locators of the project
+    pub fn edit_locators(&mut self, edit: impl FnOnce(&mut Locators)) {
+        edit(&mut self.locators);
+        self.record(DataChange::LocatorsChanged);
+    }
+
+    /// get the midi inputs of the project
+    pub fn inputs(&self) -> &InputMap {
+        &self.inputs
+    }
+
+    /// edit the midi inputs of the project
+    pub fn edit_inputs(&mut self, edit: impl FnOnce(&mut InputMap)) {
+        edit(&mut self.inputs);
+        self.record(DataChange::InputsChanged);
+    }
+
+    /// edit the events of a clip
+    pub fn edit_events(
+        &mut self,
+        clip_id: ClipId,
+        edit: impl FnOnce(&mut EventCollection),
+    ) -> bool {
+        let Some((track_id, key)) = self.index.clips.get(&clip_id).copied() else {
+            return false;
+        };
+        let Some(clip) = self
+            .track_collection
+            .get_mut(track_id)
+            .and_then(|track| track.clip_collection.get_mut(&key))
+        else {
+            return false;
+        };
+        self.index.remove_clip(clip);
+        edit(&mut clip.events);
+        self.index.add_clip(track_id, clip);
+        self.record(DataChange::EventsEdited { clip_id });
+        true
+    }
+
+    /// adds recorded takes to their tracks as a single undo step, when a track has several takes
+    /// the last one is placed and the earlier ones are kept in the takes of the track
+    pub fn record_takes(&mut self, takes: Vec<Take>, mode: RecordMode) {
+        let mut track_ids: Vec<TrackId> = Vec::new();
+        for take in &takes {
+            if !track_ids.contains(&take.track_id)
+                && self.track_collection.get_by_id(take.track_id).is_some()
+            {
+                track_ids.push(take.track_id);
+            }
+        }
+        let mut step = UndoStep::new("recording");
+        for track_id in track_ids {
+            let mut track_takes: Vec<&Take> = takes
+                .iter()
+                .filter(|take| take.track_id == track_id)
+                .collect();
+            let Some(last) = track_takes.pop() else {
+                continue;
+            };
+            let before = self.clips_of(track_id);
+            self.edit_track(track_id, |track| {
+                for take in track_takes {
+                    let name = format!("take {}", track.takes.len() + 1);
+                    let clip = take.clip(take.range.clone(), &name);
+                    step.takes.push((track_id, clip.clone()));
+                    track.takes.push(clip);
+                }
+            });
+            match mode {
+                RecordMode::Replace => self.replace(last, "recording"),
+                RecordMode::Overdub => step.events.extend(self.overdub(last)),
+            }
+            step.diff(track_id, before, self.clips_of(track_id));
+        }
+        if !step.is_empty() {
+            self.history.push(step);
+        }
+    }
+
+    /// adds a take captured after it was played as a new clip called 'capture' as a single undo step,
+    /// the clips of its track are left alone, when they are in the way the clip moves to the first bar it fits at
+    pub fn add_capture(&mut self, take: Take) {
+        let Some(track) = self.track_collection.get_by_id(take.track_id) else {
+            return;
+        };
+        let length = take.range.end - take.range.start;
+        let bar = PPQN as u64 * 4;
+        let mut start = take.range.start;
+        while let Some(end) = track
+            .clip_collection
+            .clips_in(start..start + length)
+            .map(|clip| clip.end())
+            .max()
+        {
+            start = Tick::from(end.as_u64().div_ceil(bar) * bar);
+        }
+        let mut clip = take.clip(take.range.clone(), "capture");
+        clip.start = start;
+        let mut step = UndoStep::new("capture");
+        step.added.push((take.track_id, clip.clone()));
+        let _ = self.add_clip(take.track_id, clip);
+        self.history.push(step);
+    }
+
+    /// places a take over the clips of its track as a clip called 'name', clips inside its range
+    /// are removed and clips partly overlapping it are cut at its start and end
+    fn replace(&mut self, take: &Take, name: &str) {
+        let _ = self.add_clip(take.track_id, take.clip(take.range.clone(), name));
+    }
+
+    /// adds the events of a take to the clips they fall in, the other events are put in new clips
+    /// filling the gaps between the existing clips within the range of the take,
+    /// returns the events added to existing clips
+    fn overdub(&mut self, take: &Take) -> Vec<(ClipId, EventSegment)> {
+        let Some(track) = self.track_collection.get_by_id(take.track_id) else {
+            return Vec::new();
+        };
+        let clips = &track.clip_collection;
+        let mut merged: Vec<(ClipId, EventSegment)> = Vec::new();
+        let mut gaps: Vec<Range<Tick>> = Vec::new();
+        for event in &take.events {
+            if let Some(clip) = clips
+                .clips_in(event.start..event.start + Tick::from(1))
+                .next()
+            {
+                merged.push((clip.id, event.segment(clip.start)));
+                continue;
+            }
+            // a gap never grows into the clip after it
+            let next = clips
+                .clips_in(event.start..Tick::from(u64::MAX))
+                .map(|clip| clip.start)
+                .next()
+                .unwrap_or(Tick::from(u64::MAX));
+            match gaps.last_mut() {
+                Some(gap) if gap.end > event.start => gap.end = gap.end.max(event.end).min(next),
+                _ => {
+                    let previous = clips
+                        .values()
+                        .map(|clip| clip.end())
+                        .filter(|end| *end <= event.start)
+                        .max();
+                    let start = previous.unwrap_or(take.range.start).max(take.range.start);
+                    let end = event.end.max(take.range.end).min(next);
+                    gaps.push(start..end);
                 }
             }
         }
-        None
+        for (clip_id, segment) in &merged {
+            self.edit_events(*clip_id, |events| events.add_event(segment.start, *segment));
+        }
+        for gap in gaps {
+            let _ = self.add_clip(take.track_id, take.clip(gap, "recording"));
+        }
+        merged
+    }
+
+    /// get a copy of the clips of a track
+    fn clips_of(&self, track_id: TrackId) -> Vec<Clip> {
+        self.track_collection
+            .get_by_id(track_id)
+            .map(|track| track.clip_collection.values().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// takes back what a step added and puts back what it removed, wherever its clips are now
+    fn undo_step(&mut self, step: &UndoStep) {
+        for (_, clip) in &step.added {
+            self.take_clip(clip.id);
+        }
+        for (clip_id, event) in &step.events {
+            self.edit_events(*clip_id, |events| {
+                events.remove_event(event.id);
+            });
+        }
+        for (track_id, take) in &step.takes {
+            self.edit_track(*track_id, |track| {
+                track.takes.retain(|kept| kept.id != take.id)
+            });
+        }
+        for (track_id, clip) in &step.removed {
+            let _ = self.add_clip(*track_id, clip.clone());
+        }
+    }
+
+    /// makes the changes of an undone step again
+    fn redo_step(&mut self, step: &UndoStep) {
+        for (_, clip) in &step.removed {
+            self.take_clip(clip.id);
+        }
+        for (track_id, clip) in &step.added {
+            let _ = self.add_clip(*track_id, clip.clone());
+        }
+        for (clip_id, event) in &step.events {
+            self.edit_events(*clip_id, |events| events.add_event(event.start, *event));
+        }
+        for (track_id, take) in &step.takes {
+            self.edit_track(*track_id, |track| track.takes.push(take.clone()));
+        }
+    }
+
+    /// undo the latest edit which can be undone, returning its name, edits made after it are kept
+    pub fn undo(&mut self) -> Option<String> {
+        let step = self.history.pop_undo()?;
+        self.undo_step(&step);
+        let name = step.name.clone();
+        self.history.push_redo(step);
+        Some(name)
+    }
+
+    /// redo the latest undone edit, returning its name
+    pub fn redo(&mut self) -> Option<String> {
+        let step = self.history.pop_redo()?;
+        self.redo_step(&step);
+        let name = step.name.clone();
+        self.history.push_undo(step);
+        Some(name)
+    }
+
+    /// get the name of the edit which is undone next
+    pub fn undo_name(&self) -> Option<&str> {
+        self.history.undo_name()
+    }
+
+    /// get the name of the edit which is redone next
+    pub fn redo_name(&self) -> Option<&str> {
+        self.history.redo_name()
+    }
+
+    /// records a change, also used for changes made outside of the project like a tempo change
+    pub(crate) fn record(&mut self, change: DataChange) {
+        // after a resync the changes are read again anyway
+        if self.changes.first() == Some(&DataChange::Resync) {
+            return;
+        }
+        if self.changes.len() == MAX_PENDING_CHANGES {
+            self.changes = vec![DataChange::Resync];
+            return;
+        }
+        self.changes.push(change);
+    }
+
+    /// takes the changes made since the last call
+    pub fn take_changes(&mut self) -> Vec<DataChange> {
+        std::mem::take(&mut self.changes)
+    }
+
+    /// returns reference to the clip if found, else 'None'
+    pub fn find_clip(&self, target_clip_id: ClipId) -> Option<&Clip> {
+        let (track_id, key) = self.index.clips.get(&target_clip_id)?;
+        let track = self.track_collection.get_by_id(*track_id)?;
+        track.clip_collection.get(key)
+    }
+
+    /// get the track and key of a clip
+    pub fn clip_location(&self, clip_id: ClipId) -> Option<(TrackId, ClipKey)> {
+        self.index.clips.get(&clip_id).copied()
+    }
+
+    /// returns reference to the event if found, else 'None'
+    pub fn find_event(&self, event_id: DataId) -> Option<&EventSegment> {
+        let location = self.index.events.get(&event_id)?;
+        let clip = self.find_clip(location.clip_id)?;
+        clip.events
+            .get(&location.tick)?
+            .iter()
+            .find(|segment| segment.id == event_id)
+    }
+
+    /// get the track, clip and key of an event
+    pub fn event_location(&self, event_id: DataId) -> Option<EventLocation> {
+        self.index.events.get(&event_id).copied()
+    }
+
+    /// add a clip to a track, clips it overlaps are cut or split
+    pub fn add_clip(&mut self, track_id: TrackId, clip: Clip) -> Result<(), DataLayerError> {
+        let clip_id = clip.id;
+        self.place_clip(track_id, clip)?;
+        self.record(DataChange::ClipAdded { track_id, clip_id });
+        Ok(())
+    }
+
+    /// inserts a clip into a track, recording the changes to the clips it overlaps
+    fn place_clip(&mut self, track_id: TrackId, clip: Clip) -> Result<(), DataLayerError> {
+        let track = self
+            .track_collection
+            .get_mut(track_id)
+            .ok_or(DataLayerError::NoTrack(track_id))?;
+
+        // every clip which may be cut, split or replaced by the new clip is indexed again
+        let clip_id = clip.id;
+        let mut range = clip.start..clip.end();
+        let replaced = track.clip_collection.get(&ClipKey::from(&clip));
+        let mut affected: Vec<_> = track
+            .clip_collection
+            .clips_in(range.clone())
+            .chain(replaced)
+            .cloned()
+            .collect();
+        affected.sort_by_key(|affected| affected.id);
+        affected.dedup_by_key(|affected| affected.id);
+        for affected in &affected {
+            range.start = range.start.min(affected.start);
+            range.end = range.end.max(affected.end());
+            self.index.remove_clip(affected);
+        }
+
+        track.add_clip(clip);
+        let mut changes = Vec::new();
+        for clip in track.clip_collection.clips_in(range) {
+            self.index.add_clip(track_id, clip);
+            if clip.id != clip_id && !affected.iter().any(|affected| affected.id == clip.id) {
+                // a piece split off an overlapped clip
+                let clip_id = clip.id;
+                changes.push(DataChange::ClipAdded { track_id, clip_id });
+            }
+        }
+        if let Some(clip) = track.clip_collection.find(clip_id) {
+            self.index.add_clip(track_id, clip);
+        }
+        for affected in affected {
+            let clip_id = affected.id;
+            let change = match track.clip_collection.find(clip_id) {
+                Some(_) => DataChange::ClipChanged { track_id, clip_id },
+                None => DataChange::ClipRemoved { track_id, clip_id },
+            };
+            changes.push(change);
+        }
+        for change in changes {
+            self.record(change);
+        }
+        Ok(())
+    }
+
+    /// remove a clip from the project, returning it if found
+    pub fn take_clip(&mut self, clip_id: ClipId) -> Option<Clip> {
+        let (track_id, clip) = self.unplace_clip(clip_id)?;
+        self.record(DataChange::ClipRemoved { track_id, clip_id });
+        Some(clip)
+    }
+
+    /// removes a clip from its track and the index, returning it with the id of its track
+    fn unplace_clip(&mut self, clip_id: ClipId) -> Option<(TrackId, Clip)> {
+        let (track_id, key) = self.index.clips.get(&clip_id).copied()?;
+        let clip = self
+            .track_collection
+            .get_mut(track_id)?
+            .clip_collection
+            .remove(&key)?;
+        self.index.remove_clip(&clip);
+        Some((track_id, clip))
+    }
+
+    /// get the events of all tracks overlapping the given range in absolute ticks, grouped by track
+    pub fn events_in(&self, range: Range<Tick>) -> impl Iterator<Item = ArrangedEvent<'_>> {
+        self.track_collection
+            .iter()
+            .flat_map(move |track| track.events_in(range.clone()))
     }
 
     /// add a new track to the end of the collection
@@ -64,13 +594,19 @@ impl Project {
 
     /// moved a clip from one track to another
     pub fn move_clip(&mut self, clip_id: ClipId, track_id: TrackId, tick: Tick) {
-        let _ = track_id;
-        let clip = self.track_collection.take_clip(clip_id);
-        if let Some(mut clip) = clip {
+        if self.track_collection.get_by_id(track_id).is_none() {
+            tracing::info!("track was not found {}", track_id);
+            return;
+        }
+        let clip = self.unplace_clip(clip_id);
+        if let Some((from, mut clip)) = clip {
             clip.start = tick;
-            if let Some(track) = self.track_collection.get_mut(track_id) {
-                track.add_clip(clip);
-            }
+            let _ = self.place_clip(track_id, clip);
+            self.record(DataChange::ClipMoved {
+                from,
+                to: track_id,
+                clip_id,
+            });
         } else {
             tracing::info!("clip was not found {}", clip_id);
         }
@@ -79,6 +615,7 @@ impl Project {
 
 #[cfg(test)]
 mod tests {
+    use crate::data::{MidiMessage, RecordedEvent};
     use crate::Tick;
 
     use super::*;
@@ -94,4 +631,372 @@ mod tests {
 
         // assert!(clip.id() == clip_id);
     }
+
+    #[test]
+    fn index_follows_moved_and_split_clips() {
+        let mut project = Project::new();
+        project.push_track();
+        project.push_track();
+        let first = project.track_collection.get(0).unwrap().id;
+        let second = project.track_collection.get(1).unwrap().id;
+
+        let clip = Clip::new(Tick::from(0), "clip", Tick::from(960));
+        let clip_id = clip.id;
+        let (event_tick, event_id) = clip
+            .events
+            .iter()
+            .map(|(tick, segments)| (*tick, segments[0].id))
+            .next()
+            .unwrap();
+        project.add_clip(first, clip).unwrap();
+        assert_eq!(project.find_clip(clip_id).unwrap().name.as_str(), "clip");
+
+        project.move_clip(clip_id, second, Tick::from(1920));
+        assert_eq!(
+            project.clip_location(clip_id),
+            Some((second, ClipKey::from(project.find_clip(clip_id).unwrap())))
+        );
+        let location = project.event_location(event_id).unwrap();
+        assert_eq!(location.track_id, second);
+        assert_eq!(location.tick, event_tick);
+        assert!(project.find_event(event_id).is_some());
+
+        // splitting the clip replaces it with clips that have new ids
+        let cutter = Clip::new(Tick::from(2000), "cutter", Tick::from(100));
+        let cutter_id = cutter.id;
+        project.add_clip(second, cutter).unwrap();
+        assert!(project.find_clip(cutter_id).is_some());
+        for (_, clip) in project
+            .track_collection
+            .get(1)
+            .unwrap()
+            .clip_collection
+            .iter()
+        {
+            assert_eq!(project.find_clip(clip.id).unwrap().id, clip.id);
+        }
+        let indexed = project.index.clips.len();
+        assert_eq!(
+            indexed,
+            project
+                .track_collection
+                .get(1)
+                .unwrap()
+                .clip_collection
+                .len()
+        );
+
+        assert!(project.take_clip(cutter_id).is_some());
+        assert!(project.find_clip(cutter_id).is_none());
+    }
+
+    #[test]
+    fn changes_which_are_not_taken_collapse_into_a_resync() {
+        let mut project = Project::new();
+        for _ in 0..MAX_PENDING_CHANGES {
+            project.record(DataChange::MarkersChanged);
+        }
+        assert_eq!(project.changes.len(), MAX_PENDING_CHANGES);
+
+        project.record(DataChange::LocatorsChanged);
+        project.record(DataChange::MarkersChanged);
+        assert_eq!(project.take_changes(), vec![DataChange::Resync]);
+
+        project.record(DataChange::LocatorsChanged);
+        assert_eq!(project.take_changes(), vec![DataChange::LocatorsChanged]);
+    }
+
+    #[test]
+    fn events_in_uses_absolute_ticks() {
+        let mut project = Project::new();
+        let mut track = Track::new(TrackId::new(), "track 0");
+        track.add_clip(Clip::new(Tick::from(1920), "clip", Tick::from(960)));
+        project.add_track(track);
+
+        let events: Vec<_> = project
+            .events_in(Tick::from(2100)..Tick::from(2200))
+            .map(|event| (event.start, event.end))
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                (Tick::from(1920), Tick::from(2400)),
+                (Tick::from(2160), Tick::from(2620))
+            ]
+        );
+        assert_eq!(
+            project
+                .events_in(Tick::from(2880)..Tick::from(3000))
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn recording_overdubs_into_clips_and_is_one_undo_step() {
+        let mut project = Project::new();
+        let track_id = TrackId::new();
+        project.add_track(Track::new(track_id, "track 0"));
+        let existing = Clip::with_events(
+            Tick::from(0),
+            "clip",
+            Tick::from(960),
+            EventCollection::new(),
+        );
+        let existing_id = existing.id;
+        project.add_clip(track_id, existing).unwrap();
+        let note = |start: u64, end: u64| RecordedEvent {
+            start: Tick::from(start),
+            end: Tick::from(end),
+            message: MidiMessage::NoteOn {
+                key: 60,
+                velocity: 100,
+            },
+        };
+        let take = Take {
+            track_id,
+            range: Tick::from(0)..Tick::from(1920),
+            events: vec![note(480, 720), note(1440, 1680)],
+        };
+
+        project.record_takes(vec![take.clone()], RecordMode::Overdub);
+        let track = project.track_collection.get_by_id(track_id).unwrap();
+        let clips: Vec<_> = track
+            .clip_collection
+            .values()
+            .map(|clip| (clip.start, clip.end()))
+            .collect();
+        assert_eq!(
+            clips,
+            vec![
+                (Tick::from(0), Tick::from(960)),
+                (Tick::from(960), Tick::from(1920))
+            ]
+        );
+        let merged = project.find_clip(existing_id).unwrap();
+        let (tick, segments) = merged.events.iter().next().unwrap();
+        assert_eq!(*tick, Tick::from(480));
+        assert_eq!(segments[0].end, Tick::from(720));
+        let recorded = track.clip_collection.values().nth(1).unwrap();
+        let (tick, segments) = recorded.events.iter().next().unwrap();
+        assert_eq!(*tick, Tick::from(480));
+        assert_eq!(segments[0].end, Tick::from(720));
+
+        assert_eq!(project.undo().as_deref(), Some("recording"));
+        let track = project.track_collection.get_by_id(track_id).unwrap();
+        assert_eq!(track.clip_collection.len(), 1);
+        assert_eq!(
+            project
+                .find_clip(existing_id)
+                .unwrap()
+                .events
+                .iter()
+                .count(),
+            0
+        );
+        assert_eq!(project.redo().as_deref(), Some("recording"));
+        assert_eq!(
+            project
+                .find_clip(existing_id)
+                .unwrap()
+                .events
+                .iter()
+                .count(),
+            1
+        );
+
+        // replacing puts the take over the existing clip, earlier passes become takes
+        let first_pass = Take {
+            events: vec![note(0, 120)],
+            ..take.clone()
+        };
+        project.record_takes(vec![first_pass, take], RecordMode::Replace);
+        let track = project.track_collection.get_by_id(track_id).unwrap();
+        assert_eq!(track.clip_collection.len(), 1);
+        assert_eq!(track.takes.len(), 1);
+        assert!(project.find_clip(existing_id).is_none());
+        project.undo();
+        let track = project.track_collection.get_by_id(track_id).unwrap();
+        assert!(track.takes.is_empty());
+        assert!(project.find_clip(existing_id).is_some());
+    }
+
+    #[test]
+    fn replacing_cuts_clips_straddling_the_take() {
+        let mut project = Project::new();
+        let track_id = TrackId::new();
+        project.add_track(Track::new(track_id, "track 0"));
+        let note = |start: u64| {
+            EventSegment::new2(Tick::from(start), Tick::from(start + 60), 60, 100, true)
+        };
+        let clip = |start: u64, notes: &[u64]| {
+            let mut events = EventCollection::new();
+            for start in notes {
+                events.add_event(Tick::from(*start), note(*start));
+            }
+            Clip::with_events(Tick::from(start), "clip", Tick::from(960), events)
+        };
+        project.add_clip(track_id, clip(0, &[0, 600])).unwrap();
+        project.add_clip(track_id, clip(1440, &[100, 800])).unwrap();
+        let take = Take {
+            track_id,
+            range: Tick::from(480)..Tick::from(1920),
+            events: Vec::new(),
+        };
+
+        project.record_takes(vec![take], RecordMode::Replace);
+        let track = project.track_collection.get_by_id(track_id).unwrap();
+        let clips: Vec<_> = track
+            .clip_collection
+            .values()
+            .map(|clip| {
+                let starts: Vec<_> = clip.events.iter().map(|(tick, _)| tick.as_u64()).collect();
+                (clip.start.as_u64(), clip.end().as_u64(), starts)
+            })
+            .collect();
+        // the clips keep their events outside of the take, moved to the start of the right part
+        assert_eq!(
+            clips,
+            vec![
+                (0, 480, vec![0]),
+                (480, 1920, vec![]),
+                (1920, 2400, vec![320])
+            ]
+        );
+    }
+
+    #[test]
+    fn overdubbed_gaps_stop_at_the_next_clip() {
+        let mut project = Project::new();
+        let track_id = TrackId::new();
+        project.add_track(Track::new(track_id, "track 0"));
+        let empty = |start: u64| {
+            Clip::with_events(
+                Tick::from(start),
+                "clip",
+                Tick::from(480),
+                EventCollection::new(),
+            )
+        };
+        project.add_clip(track_id, empty(0)).unwrap();
+        let next = empty(1440);
+        let next_id = next.id;
+        project.add_clip(track_id, next).unwrap();
+        let note = |start: u64, end: u64| RecordedEvent {
+            start: Tick::from(start),
+            end: Tick::from(end),
+            message: MidiMessage::NoteOn {
+                key: 60,
+                velocity: 100,
+            },
+        };
+        let take = Take {
+            track_id,
+            range: Tick::from(0)..Tick::from(1920),
+            events: vec![note(600, 700), note(650, 1600)],
+        };
+
+        project.record_takes(vec![take], RecordMode::Overdub);
+        let next = project.find_clip(next_id).unwrap();
+        assert_eq!(
+            (next.start, next.end()),
+            (Tick::from(1440), Tick::from(1920))
+        );
+        let track = project.track_collection.get_by_id(track_id).unwrap();
+        let gap = track.clip_collection.values().nth(1).unwrap();
+        assert_eq!((gap.start, gap.end()), (Tick::from(480), Tick::from(1440)));
+        assert_eq!(gap.events.iter().count(), 2);
+    }
+
+    #[test]
+    fn captures_are_moved_past_the_clips_in_their_way() {
+        let mut project = Project::new();
+        let track_id = TrackId::new();
+        project.add_track(Track::new(track_id, "track 0"));
+        let existing = Clip::new(Tick::from(0), "clip", Tick::from(960));
+        let existing_id = existing.id;
+        project.add_clip(track_id, existing).unwrap();
+        let take = Take {
+            track_id,
+            range: Tick::from(0)..Tick::from(1920),
+            events: vec![RecordedEvent {
+                start: Tick::from(240),
+                end: Tick::from(480),
+                message: MidiMessage::NoteOn {
+                    key: 60,
+                    velocity: 100,
+                },
+            }],
+        };
+
+        project.add_capture(take);
+        let existing = project.find_clip(existing_id).unwrap();
+        assert_eq!(
+            (existing.start, existing.end()),
+            (Tick::from(0), Tick::from(960))
+        );
+        let track = project.track_collection.get_by_id(track_id).unwrap();
+        let capture = track.clip_collection.values().nth(1).unwrap();
+        assert_eq!(capture.name.as_str(), "capture");
+        assert_eq!(
+            (capture.start, capture.end()),
+            (Tick::from(1920), Tick::from(3840))
+        );
+        assert_eq!(capture.events.iter().next().unwrap().0, &Tick::from(240));
+
+        assert_eq!(project.undo().as_deref(), Some("capture"));
+        let track = project.track_collection.get_by_id(track_id).unwrap();
+        assert_eq!(track.clip_collection.len(), 1);
+    }
+
+    #[test]
+    fn undoing_a_recording_keeps_clips_moved_after_it() {
+        let mut project = Project::new();
+        project.push_track();
+        project.push_track();
+        let first = project.track_collection.get(0).unwrap().id;
+        let second = project.track_collection.get(1).unwrap().id;
+        let existing = Clip::new(Tick::from(0), "clip", Tick::from(960));
+        let existing_id = existing.id;
+        project.add_clip(first, existing).unwrap();
+        let take = Take {
+            track_id: first,
+            range: Tick::from(1920)..Tick::from(2880),
+            events: vec![RecordedEvent {
+                start: Tick::from(2000),
+                end: Tick::from(2100),
+                message: MidiMessage::NoteOn {
+                    key: 60,
+                    velocity: 100,
+                },
+            }],
+        };
+
+        project.record_takes(vec![take], RecordMode::Replace);
+        project.move_clip(existing_id, second, Tick::from(960));
+        let later = Clip::new(Tick::from(3840), "later", Tick::from(960));
+        let later_id = later.id;
+        project.add_clip(first, later).unwrap();
+
+        assert_eq!(project.undo().as_deref(), Some("recording"));
+        let clip_count = |project: &Project, track_id: TrackId| {
+            let track = project.track_collection.get_by_id(track_id).unwrap();
+            track.clip_collection.len()
+        };
+        // only the recorded clip is taken back, the moved and later clips stay where they are
+        assert_eq!(
+            (clip_count(&project, first), clip_count(&project, second)),
+            (1, 1)
+        );
+        assert_eq!(project.clip_location(existing_id).unwrap().0, second);
+        assert_eq!(project.clip_location(later_id).unwrap().0, first);
+        assert_eq!(project.index.clips.len(), 2);
+
+        assert_eq!(project.redo().as_deref(), Some("recording"));
+        assert_eq!(
+            (clip_count(&project, first), clip_count(&project, second)),
+            (2, 1)
+        );
+        assert_eq!(project.index.clips.len(), 3);
+    }
 }
@@ -0,0 +1,70 @@
+use std::ops::Range;
+
+use super::{
+    clip::Clip,
+    event_list::{EventCollection, EventSegment},
+    track::TrackId,
+    DataId, MidiMessage,
+};
+use crate::{event::EventType, Tick};
+
+/// how a recording is combined with the clips it overlaps
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RecordMode {
+    /// recorded events are added to the clips they fall in, existing events are kept
+    #[default]
+    Overdub,
+    /// the recording replaces whatever the clips held in the recorded range
+    Replace,
+}
+
+/// a message recorded onto a track, in absolute ticks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecordedEvent {
+    /// tick at which the message was played
+    pub start: Tick,
+    /// tick at which a note was released, one tick after 'start' for other messages
+    pub end: Tick,
+    /// the recorded message, notes are stored as note on
+    pub message: MidiMessage,
+}
+
+impl RecordedEvent {
+    /// turns the event into a segment stored relative to 'origin'
+    pub(crate) fn segment(&self, origin: Tick) -> EventSegment {
+        EventSegment::new(
+            DataId::new(),
+            self.start.saturating_sub(origin),
+            self.end.saturating_sub(origin),
+            EventType::Midi(self.message),
+            true,
+        )
+    }
+}
+
+/// everything recorded onto one track during one pass of the transport
+#[derive(Debug, Clone, PartialEq)]
+pub struct Take {
+    /// track the take was recorded onto
+    pub track_id: TrackId,
+    /// range of the arrangement covered by the take
+    pub range: Range<Tick>,
+    /// recorded events, sorted by start
+    pub events: Vec<RecordedEvent>,
+}
+
+impl Take {
+    /// builds a clip covering 'range', holding the events which start in it
+    pub(crate) fn clip(&self, range: Range<Tick>, name: &str) -> Clip {
+        let events: EventCollection = self
+            .events
+            .iter()
+            .filter(|event| range.contains(&event.start))
+            .map(|event| {
+                let segment = event.segment(range.start);
+                (segment.start, vec![segment])
+            })
+            .collect();
+        Clip::with_events(range.start, name, range.end - range.start, events)
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+
+use super::{clip::Clip, track::TrackId};
+
+/// what happens after a launched clip played a number of loops
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FollowAction {
+    /// launch the next filled slot of the same track, wrapping around to the first scene
+    Next,
+    /// launch a random filled slot of the same track
+    Random,
+    /// stop the track
+    Stop,
+}
+
+/// a clip placed in the session grid
+#[derive(Debug, Clone)]
+pub struct ClipSlot {
+    /// clip played when the slot is launched, its start is ignored
+    pub clip: Clip,
+    /// action taken after 'follow_after' loops, 'None' keeps looping the clip
+    pub follow_action: Option<FollowAction>,
+    /// number of loops played before the follow action is taken
+    pub follow_after: u32,
+}
+
+impl ClipSlot {
+    /// creates a new slot which loops the clip until it is stopped
+    pub fn new(clip: Clip) -> Self {
+        Self {
+            clip,
+            follow_action: None,
+            follow_after: 1,
+        }
+    }
+
+    /// set the follow action of this slot
+    pub fn with_follow_action(mut self, action: FollowAction, after_loops: u32) -> Self {
+        self.follow_action = Some(action);
+        self.follow_after = after_loops.max(1);
+        self
+    }
+}
+
+/// a row of the session grid
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scene {
+    /// visual name of the scene
+    pub name: String,
+}
+
+/// session view of a project, a grid of clip slots per track with scenes as rows
+#[derive(Default, Debug, Clone)]
+pub struct Session {
+    /// rows of the grid
+    scenes: Vec<Scene>,
+    /// slots of every track, indexed by scene
+    slots: HashMap<TrackId, Vec<Option<ClipSlot>>>,
+}
+
+impl Session {
+    /// adds a new scene at the bottom of the grid, returning its index
+    pub fn add_scene(&mut self, name: &str) -> usize {
+        self.scenes.push(Scene {
+            name: String::from(name),
+        });
+        self.scenes.len() - 1
+    }
+
+    /// removes the scene at the given index along with the slots in that row
+    pub fn remove_scene(&mut self, index: usize) -> Option<Scene> {
+        if index >= self.scenes.len() {
+            return None;
+        }
+        for slots in self.slots.values_mut() {
+            if index < slots.len() {
+                slots.remove(index);
+            }
+        }
+        Some(self.scenes.remove(index))
+    }
+
+    /// get the scenes of the grid
+    pub fn scenes(&self) -> &[Scene] {
+        &self.scenes
+    }
+
+    /// get the number of scenes
+    pub fn scene_count(&self) -> usize {
+        self.scenes.len()
+    }
+
+    /// place a clip slot in the grid, adding scenes if needed
+    pub fn set_slot(&mut self, track_id: TrackId, scene: usize, slot: ClipSlot) {
+        while self.scenes.len() <= scene {
+            let name = format!("scene {}", self.scenes.len() + 1);
+            self.add_scene(&name);
+        }
+        let slots = self.slots.entry(track_id).or_default();
+        if slots.len() <= scene {
+            slots.resize_with(scene + 1, || None);
+        }
+        slots[scene] = Some(slot);
+    }
+
+    /// removes a clip slot from the grid, returning it if the slot was filled
+    pub fn take_slot(&mut self, track_id: TrackId, scene: usize) -> Option<ClipSlot> {
+        self.slots
+            .get_mut(&track_id)
+            .and_then(|slots| slots.get_mut(scene))
+            .and_then(Option::take)
+    }
+
+    /// get the clip slot of a track in a scene, or 'None' if it is empty
+    pub fn slot(&self, track_id: TrackId, scene: usize) -> Option<&ClipSlot> {
+        self.slots
+            .get(&track_id)
+            .and_then(|slots| slots.get(scene))
+            .and_then(Option::as_ref)
+    }
+
+    /// get the scene indices of the filled slots of a track
+    pub fn filled_slots(&self, track_id: TrackId) -> Vec<usize> {
+        self.slots
+            .get(&track_id)
+            .map(|slots| {
+                slots
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(index, slot)| slot.as_ref().map(|_| index))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// get the ids of the tracks with a filled slot in the given scene
+    pub fn tracks_in_scene(&self, scene: usize) -> Vec<TrackId> {
+        self.slots
+            .iter()
+            .filter(|(_, slots)| matches!(slots.get(scene), Some(Some(_))))
+            .map(|(track_id, _)| *track_id)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Tick;
+
+    #[test]
+    fn setting_a_slot_adds_scenes() {
+        let mut session = Session::default();
+        let track_id = TrackId::new();
+        let clip = Clip::new(Tick::zero(), "loop", Tick::from(1920));
+        session.set_slot(track_id, 2, ClipSlot::new(clip));
+
+        assert_eq!(session.scene_count(), 3);
+        assert!(session.slot(track_id, 1).is_none());
+        assert!(session.slot(track_id, 2).is_some());
+        assert_eq!(session.filled_slots(track_id), vec![2]);
+        assert_eq!(session.tracks_in_scene(2), vec![track_id]);
+
+        session.remove_scene(0);
+        assert_eq!(session.filled_slots(track_id), vec![1]);
+    }
+}
@@ -0,0 +1,432 @@
+use super::{
+    automation::AutomationValue,
+    locators::{Marker, MarkerCollection},
+    project::Project,
+    track::Track,
+    MidiMessage,
+};
+use crate::{event::EventType, Tick, PPQN};
+use thiserror::Error;
+
+/// meta event type for the name of a track
+const META_TRACK_NAME: u8 = 0x03;
+/// meta event type for a marker
+const META_MARKER: u8 = 0x06;
+/// meta event type for a tempo change
+const META_TEMPO: u8 = 0x51;
+/// meta event type marking the end of a track
+const META_END_OF_TRACK: u8 = 0x2F;
+
+/// an event placed in a track chunk
+#[derive(Debug)]
+struct SmfEvent {
+    /// absolute tick of the event
+    tick: Tick,
+    /// sort order for events on the same tick, lower is written first
+    order: u8,
+    /// encoded event, without the delta time
+    bytes: Vec<u8>,
+}
+
+impl SmfEvent {
+    /// creates a meta event
+    fn meta(tick: Tick, kind: u8, data: &[u8]) -> Self {
+        let mut bytes = vec![0xFF, kind];
+        write_variable_length(&mut bytes, data.len() as u32);
+        bytes.extend_from_slice(data);
+        Self {
+            tick,
+            order: 0,
+            bytes,
+        }
+    }
+
+    /// creates a channel event from a midi message
+    fn midi(tick: Tick, message: &MidiMessage, channel: u8) -> Self {
+        let order = match message {
+            MidiMessage::NoteOff { .. } => 0,
+            MidiMessage::NoteOn { .. } => 2,
+            _ => 1,
+        };
+        Self {
+            tick,
+            order,
+            bytes: message.to_midi(channel),
+        }
+    }
+
+    /// creates a tempo meta event
+    fn tempo(tick: Tick, bpm: f64) -> Self {
+        let micros_per_beat = (60_000_000.0 / bpm).round().clamp(1.0, 0xFF_FFFF as f64) as u32;
+        Self::meta(tick, META_TEMPO, &micros_per_beat.to_be_bytes()[1..])
+    }
+}
+
+/// writes a number as a midi variable length quantity
+fn write_variable_length(out: &mut Vec<u8>, value: u32) {
+    let mut buffer = vec![(value & 0x7F) as u8];
+    let mut value = value >> 7;
+    while value > 0 {
+        buffer.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    buffer.reverse();
+    out.extend(buffer);
+}
+
+/// writes a chunk with the given type and data
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(kind);
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(data);
+}
+
+/// encodes the events into the data of a track chunk, ending it with an end of track event
+fn encode_track(mut events: Vec<SmfEvent>) -> Vec<u8> {
+    events.sort_by_key(|event| (event.tick, event.order));
+    let mut data = Vec::new();
+    let mut previous = Tick::zero();
+    for event in &events {
+        write_variable_length(&mut data, (event.tick - previous).as_u64() as u32);
+        data.extend_from_slice(&event.bytes);
+        previous = event.tick;
+    }
+    data.extend_from_slice(&[0x00, 0xFF, META_END_OF_TRACK, 0x00]);
+    data
+}
+
+/// collects the note and automation events of a track
+fn track_events(track: &Track, resolution: Tick) -> Vec<SmfEvent> {
+    let channel = track.instrument.channel;
+    let mut events = vec![SmfEvent::meta(
+        Tick::zero(),
+        META_TRACK_NAME,
+        track.name.as_bytes(),
+    )];
+
+    for (_, clip) in track.clip_collection.iter() {
+        for (_, segments) in clip.events().iter() {
+            for segment in segments {
+                let end = segment.end.min(clip.duration);
+                if !segment.is_active || segment.start >= end {
+                    continue;
+                }
+                if let EventType::Midi(MidiMessage::NoteOn { key, velocity }) = segment.event_type {
+                    let on = MidiMessage::NoteOn { key, velocity };
+                    let off = MidiMessage::NoteOff { key, velocity: 0 };
+                    events.push(SmfEvent::midi(clip.start + segment.start, &on, channel));
+                    events.push(SmfEvent::midi(clip.start + end, &off, channel));
+                }
+            }
+        }
+    }
+
+    let end = last_tick(track);
+    for lane in track.automation.iter().filter(|lane| lane.is_active) {
+        for (tick, value) in lane.samples(Tick::zero(), end, resolution) {
+            if let AutomationValue::Midi(message) = value {
+                events.push(SmfEvent::midi(tick, &message, channel));
+            }
+        }
+    }
+    events
+}
+
+/// get the last tick at which something happens on a track
+fn last_tick(track: &Track) -> Tick {
+    let clips_end = track
+        .clip_collection
+        .iter()
+        .map(|(_, clip)| clip.end())
+        .max()
+        .unwrap_or_default();
+    let automation_end = track
+        .automation
+        .iter()
+        .filter_map(|lane| lane.points().last().map(|point| point.tick))
+        .max()
+        .unwrap_or_default();
+    clips_end.max(automation_end) + Tick::from(1)
+}
+
+/// writes a project to a format 1 standard midi file,
+/// automation lanes are sampled every 'resolution' ticks
+pub fn write_project(project: &Project, bpm: f64, resolution: Tick) -> Vec<u8> {
+    let mut conductor = vec![SmfEvent::tempo(Tick::zero(), bpm)];
+    for marker in project.markers().iter() {
+        conductor.push(SmfEvent::meta(
+            marker.tick,
+            META_MARKER,
+            marker.name.as_bytes(),
+        ));
+    }
+    for track in project.tracks().iter() {
+        let end = last_tick(track);
+        for lane in track.automation.iter().filter(|lane| lane.is_active) {
+            for (tick, value) in lane.samples(Tick::zero(), end, resolution) {
+                if let AutomationValue::Tempo(bpm) = value {
+                    conductor.push(SmfEvent::tempo(tick, bpm));
+                }
+            }
+        }
+    }
+
+    let mut tracks = vec![encode_track(conductor)];
+    for track in project.tracks().iter() {
+        tracks.push(encode_track(track_events(track, resolution)));
+    }
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&1u16.to_be_bytes());
+    header.extend_from_slice(&(tracks.len() as u16).to_be_bytes());
+    header.extend_from_slice(&(PPQN as u16).to_be_bytes());
+
+    let mut out = Vec::new();
+    write_chunk(&mut out, b"MThd", &header);
+    for track in tracks {
+        write_chunk(&mut out, b"MTrk", &track);
+    }
+    out
+}
+
+/// error type for reading standard midi files
+#[derive(Error, Debug)]
+pub enum SmfError {
+    /// the file ended in the middle of a chunk or event
+    #[error("Unexpected end of file at byte {0}")]
+    UnexpectedEnd(usize),
+    /// the file does not start with a valid header chunk
+    #[error("Not a standard midi file")]
+    InvalidHeader,
+    /// smpte based time divisions are not supported
+    #[error("Unsupported time division {0:#06x}")]
+    UnsupportedDivision(u16),
+    /// a data byte was found where a status byte was expected
+    #[error("Missing status byte at byte {0}")]
+    MissingStatus(usize),
+}
+
+/// an event read from a track chunk
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TrackEvent {
+    /// meta event with its type and data
+    Meta(u8, Vec<u8>),
+    /// channel event with the status byte included
+    Channel(Vec<u8>),
+    /// system exclusive event
+    SysEx(Vec<u8>),
+}
+
+/// reads values from the bytes of a standard midi file
+struct SmfReader<'a> {
+    /// bytes being read
+    data: &'a [u8],
+    /// position of the next byte to read
+    position: usize,
+}
+
+impl<'a> SmfReader<'a> {
+    /// creates a reader starting at the first byte
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    /// true if all bytes have been read
+    fn is_done(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    /// reads the given number of bytes
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], SmfError> {
+        let end = self.position + count;
+        let bytes = self
+            .data
+            .get(self.position..end)
+            .ok_or(SmfError::UnexpectedEnd(self.position))?;
+        self.position = end;
+        Ok(bytes)
+    }
+
+    /// reads a single byte
+    fn byte(&mut self) -> Result<u8, SmfError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    /// reads a big endian 16 bit number
+    fn u16(&mut self) -> Result<u16, SmfError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    /// reads a big endian 32 bit number
+    fn u32(&mut self) -> Result<u32, SmfError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// reads a midi variable length quantity
+    fn variable_length(&mut self) -> Result<u32, SmfError> {
+        let mut value = 0u32;
+        for _ in 0..4 {
+            let byte = self.byte()?;
+            value = (value << 7) | (byte & 0x7F) as u32;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        Ok(value)
+    }
+
+    /// reads a chunk, returning its type and data
+    fn chunk(&mut self) -> Result<(&'a [u8], &'a [u8]), SmfError> {
+        let kind = self.bytes(4)?;
+        let length = self.u32()? as usize;
+        Ok((kind, self.bytes(length)?))
+    }
+}
+
+/// reads the events of a track chunk as (absolute tick, event)
+fn decode_track(data: &[u8]) -> Result<Vec<(u64, TrackEvent)>, SmfError> {
+    let mut reader = SmfReader::new(data);
+    let mut events = Vec::new();
+    let mut tick = 0u64;
+    let mut running_status = None;
+
+    while !reader.is_done() {
+        tick += reader.variable_length()? as u64;
+        let position = reader.position;
+        let first = reader.byte()?;
+        let event = match first {
+            0xFF => {
+                let kind = reader.byte()?;
+                let length = reader.variable_length()? as usize;
+                TrackEvent::Meta(kind, reader.bytes(length)?.to_vec())
+            }
+            0xF0 | 0xF7 => {
+                let length = reader.variable_length()? as usize;
+                TrackEvent::SysEx(reader.bytes(length)?.to_vec())
+            }
+            _ => {
+                let (status, mut bytes) = if first & 0x80 != 0 {
+                    (first, vec![first])
+                } else {
+                    let status = running_status.ok_or(SmfError::MissingStatus(position))?;
+                    (status, vec![status, first])
+                };
+                running_status = Some(status);
+                let length = match status & 0xF0 {
+                    0xC0 | 0xD0 => 2,
+                    _ => 3,
+                };
+                while bytes.len() < length {
+                    bytes.push(reader.byte()?);
+                }
+                TrackEvent::Channel(bytes)
+            }
+        };
+        events.push((tick, event));
+    }
+    Ok(events)
+}
+
+/// reads all tracks of a standard midi file, with ticks converted to the project ppqn
+fn read_tracks(bytes: &[u8]) -> Result<Vec<Vec<(Tick, TrackEvent)>>, SmfError> {
+    let mut reader = SmfReader::new(bytes);
+    let (kind, header) = reader.chunk().map_err(|_| SmfError::InvalidHeader)?;
+    if kind != b"MThd" || header.len() < 6 {
+        return Err(SmfError::InvalidHeader);
+    }
+    let division = u16::from_be_bytes([header[4], header[5]]);
+    if division & 0x8000 != 0 || division == 0 {
+        return Err(SmfError::UnsupportedDivision(division));
+    }
+
+    let mut tracks = Vec::new();
+    while !reader.is_done() {
+        let (kind, data) = reader.chunk()?;
+        if kind != b"MTrk" {
+            // unknown chunks should be skipped
+            continue;
+        }
+        let events = decode_track(data)?
+            .into_iter()
+            .map(|(tick, event)| {
+                let tick = tick as u128 * PPQN as u128 / division as u128;
+                (Tick::from(tick as u64), event)
+            })
+            .collect();
+        tracks.push(events);
+    }
+    Ok(tracks)
+}
+
+/// reads the marker meta events of a standard midi file
+pub fn read_markers(bytes: &[u8]) -> Result<MarkerCollection, SmfError> {
+    let markers = read_tracks(bytes)?
+        .into_iter()
+        .flatten()
+        .filter_map(|(tick, event)| match event {
+            TrackEvent::Meta(META_MARKER, data) => {
+                Some(Marker::new(&String::from_utf8_lossy(&data), tick))
+            }
+            _ => None,
+        })
+        .collect();
+    Ok(markers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{
+        automation::{AutomationLane, AutomationTarget, Breakpoint, CurveShape},
+        track::TrackId,
+    };
+
+    #[test]
+    fn variable_length_quantities() {
+        let mut out = Vec::new();
+        write_variable_length(&mut out, 0);
+        write_variable_length(&mut out, 0x7F);
+        write_variable_length(&mut out, 0x80);
+        write_variable_length(&mut out, 0x0FFF_FFFF);
+        assert_eq!(out, vec![0x00, 0x7F, 0x81, 0x00, 0xFF, 0xFF, 0xFF, 0x7F]);
+    }
+
+    #[test]
+    fn automation_is_exported_as_control_changes() {
+        let mut project = Project::new();
+        let mut track = Track::new(TrackId::new(), "track");
+        track.set_channel(2);
+        let mut lane = AutomationLane::new(AutomationTarget::ControlChange(7));
+        lane.add_point(Breakpoint::new(Tick::from(0), 100.0, CurveShape::Step));
+        track.add_automation_lane(lane);
+        project.add_track(track);
+
+        let smf = write_project(&project, 120.0, Tick::from(24));
+        assert_eq!(&smf[0..4], b"MThd");
+        assert!(smf.windows(3).any(|bytes| bytes == [0xB2, 7, 100]));
+    }
+
+    #[test]
+    fn markers_round_trip() {
+        let mut project = Project::new();
+        project.push_track();
+        project.edit_markers(|markers| {
+            markers.add(Marker::new("verse", Tick::from(0)));
+            markers.add(Marker::new("chorus", Tick::from(7680)));
+        });
+
+        let smf = write_project(&project, 120.0, Tick::from(24));
+        let markers = read_markers(&smf).unwrap();
+        assert_eq!(&markers, project.markers());
+    }
+
+    #[test]
+    fn reads_running_status() {
+        let events = decode_track(&[0x00, 0x90, 60, 100, 0x60, 60, 0, 0x00, 0xC0, 5]).unwrap();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[1], (0x60, TrackEvent::Channel(vec![0x90, 60, 0])));
+        assert_eq!(events[2], (0x60, TrackEvent::Channel(vec![0xC0, 5])));
+    }
+}
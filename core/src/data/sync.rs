@@ -0,0 +1,84 @@
+use super::timecode::FrameRate;
+
+/// midi clock output on a single port
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockOutput {
+    /// midi port the clock is sent to
+    pub port: u8,
+    /// true if the clock is sent
+    pub enabled: bool,
+    /// ticks by which the clock is shifted to make up for latency, positive values delay it
+    pub offset: i64,
+}
+
+impl ClockOutput {
+    /// creates a new, enabled, 'ClockOutput' without offset
+    pub fn new(port: u8) -> Self {
+        Self {
+            port,
+            enabled: true,
+            offset: 0,
+        }
+    }
+}
+
+/// midi time code output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimecodeOutput {
+    /// midi port the timecode is sent to
+    pub port: u8,
+    /// frame rate of the sent timecode
+    pub rate: FrameRate,
+}
+
+/// how the project keeps other devices in sync with its playback
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct SyncSettings {
+    /// midi clock outputs, at most one per port
+    clock_outputs: Vec<ClockOutput>,
+    /// midi time code output, 'None' when no timecode is sent
+    timecode_output: Option<TimecodeOutput>,
+}
+
+impl SyncSettings {
+    /// get the clock output of a port
+    pub fn clock_output(&self, port: u8) -> Option<&ClockOutput> {
+        self.clock_outputs.iter().find(|output| output.port == port)
+    }
+
+    /// get an iterator over the clock outputs which are enabled
+    pub fn enabled_clock_outputs(&self) -> impl Iterator<Item = &ClockOutput> {
+        self.clock_outputs.iter().filter(|output| output.enabled)
+    }
+
+    /// set the clock output of its port, replacing the previous one
+    pub fn set_clock_output(&mut self, output: ClockOutput) {
+        match self
+            .clock_outputs
+            .iter_mut()
+            .find(|existing| existing.port == output.port)
+        {
+            Some(existing) => *existing = output,
+            None => self.clock_outputs.push(output),
+        }
+    }
+
+    /// removes the clock output of a port, returning it if there was one
+    pub fn remove_clock_output(&mut self, port: u8) -> Option<ClockOutput> {
+        let index = self
+            .clock_outputs
+            .iter()
+            .position(|output| output.port == port)?;
+        Some(self.clock_outputs.remove(index))
+    }
+
+    /// get the midi time code output
+    pub fn timecode_output(&self) -> Option<&TimecodeOutput> {
+        self.timecode_output.as_ref()
+    }
+
+    /// set the midi time code output, 'None' stops sending timecode
+    pub fn set_timecode_output(&mut self, output: Option<TimecodeOutput>) {
+        self.timecode_output = output;
+    }
+}
@@ -0,0 +1,145 @@
+use crate::{Tick, PPQN};
+
+use super::{
+    automation::{AutomationTarget, AutomationValue},
+    project::Project,
+};
+
+/// a tempo which is in effect from 'tick' until the next segment
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct TempoSegment {
+    /// tick at which the tempo starts
+    tick: Tick,
+    /// tempo in beats per minute
+    bpm: f64,
+    /// time of 'tick' in seconds from tick zero
+    seconds: f64,
+}
+
+impl TempoSegment {
+    /// get the duration of a single tick in seconds
+    fn tick_seconds(&self) -> f64 {
+        60.0 / (self.bpm * PPQN as f64)
+    }
+}
+
+/// converts between ticks and seconds for a tempo which may change over time
+#[derive(Debug, Clone, PartialEq)]
+pub struct TempoMap {
+    /// tempo segments sorted by tick, the first one always starts at tick zero
+    segments: Vec<TempoSegment>,
+}
+
+impl Default for TempoMap {
+    fn default() -> Self {
+        Self::new(120.0)
+    }
+}
+
+impl TempoMap {
+    /// creates a new 'TempoMap' with a constant tempo
+    pub fn new(bpm: f64) -> Self {
+        Self {
+            segments: vec![TempoSegment {
+                tick: Tick::zero(),
+                bpm: bpm.max(f64::EPSILON),
+                seconds: 0.0,
+            }],
+        }
+    }
+
+    /// creates a tempo map from the bpm of a project and its tempo automation lanes,
+    /// lanes are sampled every 'resolution' ticks, like they are during playback
+    pub fn from_project(project: &Project, bpm: f64, resolution: Tick) -> Self {
+        let mut map = TempoMap::new(bpm);
+        let lanes = project
+            .tracks()
+            .iter()
+            .flat_map(|track| track.automation.iter())
+            .filter(|lane| lane.is_active && lane.target == AutomationTarget::Tempo);
+        for lane in lanes {
+            let Some(last) = lane.points().last() else {
+                continue;
+            };
+            let end = last.tick + Tick::from(1);
+            for (tick, value) in lane.samples(Tick::zero(), end, resolution) {
+                if let AutomationValue::Tempo(bpm) = value {
+                    map.set_tempo(tick, bpm);
+                }
+            }
+        }
+        map
+    }
+
+    /// set the tempo from the given tick onwards, until the next tempo change
+    pub fn set_tempo(&mut self, tick: Tick, bpm: f64) {
+        let bpm = bpm.max(f64::EPSILON);
+        let index = self.segments.partition_point(|segment| segment.tick < tick);
+        match self.segments.get_mut(index) {
+            Some(segment) if segment.tick == tick => segment.bpm = bpm,
+            _ => self.segments.insert(
+                index,
+                TempoSegment {
+                    tick,
+                    bpm,
+                    seconds: 0.0,
+                },
+            ),
+        }
+        for index in index.max(1)..self.segments.len() {
+            let previous = self.segments[index - 1];
+            let ticks = (self.segments[index].tick - previous.tick).as_f64();
+            self.segments[index].seconds = previous.seconds + ticks * previous.tick_seconds();
+        }
+    }
+
+    /// get the tempo at the given tick
+    pub fn bpm_at(&self, tick: Tick) -> f64 {
+        self.segment_at(tick).bpm
+    }
+
+    /// get the time of the given tick in seconds from tick zero
+    pub fn seconds_at(&self, tick: Tick) -> f64 {
+        let segment = self.segment_at(tick);
+        segment.seconds + (tick - segment.tick).as_f64() * segment.tick_seconds()
+    }
+
+    /// get the last tick which starts at or before the given time in seconds
+    pub fn tick_at(&self, seconds: f64) -> Tick {
+        let seconds = seconds.max(0.0);
+        let index = self
+            .segments
+            .partition_point(|segment| segment.seconds <= seconds);
+        let segment = &self.segments[index.saturating_sub(1)];
+        let ticks = (seconds - segment.seconds) / segment.tick_seconds();
+        // guards against rounding putting a tick which starts exactly on 'seconds' one tick early
+        let ticks = (ticks + 1e-9).floor() as u64;
+        segment.tick + Tick::from(ticks)
+    }
+
+    /// get the segment in effect at the given tick
+    fn segment_at(&self, tick: Tick) -> &TempoSegment {
+        let index = self
+            .segments
+            .partition_point(|segment| segment.tick <= tick);
+        &self.segments[index.saturating_sub(1)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_ticks_across_tempo_changes() {
+        let mut map = TempoMap::new(120.0);
+        map.set_tempo(Tick::from(PPQN * 4), 60.0);
+
+        // four beats at 120 bpm take two seconds, the next beat at 60 bpm takes one
+        assert_eq!(map.seconds_at(Tick::from(PPQN * 4)), 2.0);
+        assert_eq!(map.seconds_at(Tick::from(PPQN * 5)), 3.0);
+        assert_eq!(map.tick_at(3.0), Tick::from(PPQN * 5));
+        assert_eq!(map.tick_at(0.5), Tick::from(PPQN));
+        assert_eq!(map.bpm_at(Tick::from(PPQN * 4 - 1)), 120.0);
+    }
+}
@@ -0,0 +1,172 @@
+/// frames in ten minutes of 29.97 drop frame timecode
+const DROP_FRAMES_PER_TEN_MINUTES: u64 = 17982;
+/// frames in a minute of 29.97 drop frame timecode which drops frames
+const DROP_FRAMES_PER_MINUTE: u64 = 1798;
+
+/// frame rate of a timecode
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FrameRate {
+    /// 24 frames per second, film
+    Fps24,
+    /// 25 frames per second, pal video
+    #[default]
+    Fps25,
+    /// 29.97 frames per second with dropped frame numbers, ntsc video
+    Fps2997Drop,
+    /// 30 frames per second
+    Fps30,
+}
+
+impl FrameRate {
+    /// get the number of frames per second
+    pub fn fps(&self) -> f64 {
+        match self {
+            FrameRate::Fps24 => 24.0,
+            FrameRate::Fps25 => 25.0,
+            FrameRate::Fps2997Drop => 30000.0 / 1001.0,
+            FrameRate::Fps30 => 30.0,
+        }
+    }
+
+    /// get the number of frame numbers in a second of timecode
+    fn frames_per_second(&self) -> u64 {
+        match self {
+            FrameRate::Fps24 => 24,
+            FrameRate::Fps25 => 25,
+            FrameRate::Fps2997Drop | FrameRate::Fps30 => 30,
+        }
+    }
+
+    /// get the code of the rate used in midi time code messages
+    pub fn code(&self) -> u8 {
+        match self {
+            FrameRate::Fps24 => 0,
+            FrameRate::Fps25 => 1,
+            FrameRate::Fps2997Drop => 2,
+            FrameRate::Fps30 => 3,
+        }
+    }
+
+    /// get the rate of a code used in midi time code messages
+    pub fn from_code(code: u8) -> Self {
+        match code & 0x03 {
+            0 => FrameRate::Fps24,
+            1 => FrameRate::Fps25,
+            2 => FrameRate::Fps2997Drop,
+            _ => FrameRate::Fps30,
+        }
+    }
+}
+
+/// a position in hours, minutes, seconds and frames, like the ones sent by midi time code
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Timecode {
+    /// hours, 0-23
+    pub hours: u8,
+    /// minutes, 0-59
+    pub minutes: u8,
+    /// seconds, 0-59
+    pub seconds: u8,
+    /// frames, 0 up to the frames per second of the rate
+    pub frames: u8,
+    /// frame rate of the timecode
+    pub rate: FrameRate,
+}
+
+impl Timecode {
+    /// get the timecode of a frame, counting from zero
+    pub fn from_frames(count: u64, rate: FrameRate) -> Self {
+        let count = match rate {
+            // skip the frame numbers which are dropped every minute, except every tenth minute
+            FrameRate::Fps2997Drop => {
+                let tens = count / DROP_FRAMES_PER_TEN_MINUTES;
+                let rest = count % DROP_FRAMES_PER_TEN_MINUTES;
+                let minutes = rest.saturating_sub(2) / DROP_FRAMES_PER_MINUTE;
+                count + 18 * tens + 2 * minutes
+            }
+            _ => count,
+        };
+        let fps = rate.frames_per_second();
+        let seconds = count / fps;
+        Self {
+            hours: (seconds / 3600 % 24) as u8,
+            minutes: (seconds / 60 % 60) as u8,
+            seconds: (seconds % 60) as u8,
+            frames: (count % fps) as u8,
+            rate,
+        }
+    }
+
+    /// get the number of the frame of this timecode, counting from zero
+    pub fn to_frames(&self) -> u64 {
+        let minutes = self.hours as u64 * 60 + self.minutes as u64;
+        let seconds = minutes * 60 + self.seconds as u64;
+        let count = seconds * self.rate.frames_per_second() + self.frames as u64;
+        match self.rate {
+            FrameRate::Fps2997Drop => count - 2 * (minutes - minutes / 10),
+            _ => count,
+        }
+    }
+
+    /// get the timecode of the frame running at the given time in seconds
+    pub fn from_seconds(seconds: f64, rate: FrameRate) -> Self {
+        Self::from_frames((seconds.max(0.0) * rate.fps()) as u64, rate)
+    }
+
+    /// get the time at which this timecode starts in seconds
+    pub fn to_seconds(&self) -> f64 {
+        self.to_frames() as f64 / self.rate.fps()
+    }
+
+    /// get the data byte of a quarter frame message, piece 0-7 each carry a nibble of the timecode
+    pub fn quarter_frame(&self, piece: u8) -> u8 {
+        let piece = piece & 0x07;
+        let nibble = match piece {
+            0 => self.frames & 0x0F,
+            1 => self.frames >> 4 & 0x01,
+            2 => self.seconds & 0x0F,
+            3 => self.seconds >> 4 & 0x03,
+            4 => self.minutes & 0x0F,
+            5 => self.minutes >> 4 & 0x03,
+            6 => self.hours & 0x0F,
+            _ => self.rate.code() << 1 | (self.hours >> 4 & 0x01),
+        };
+        piece << 4 | nibble
+    }
+
+    /// assembles a timecode from the data bytes of all eight quarter frame pieces, in piece order
+    pub fn from_quarter_frames(data: &[u8; 8]) -> Self {
+        let nibble = |piece: usize| data[piece] & 0x0F;
+        Self {
+            frames: nibble(0) | (nibble(1) & 0x01) << 4,
+            seconds: nibble(2) | (nibble(3) & 0x03) << 4,
+            minutes: nibble(4) | (nibble(5) & 0x03) << 4,
+            hours: nibble(6) | (nibble(7) & 0x01) << 4,
+            rate: FrameRate::from_code(nibble(7) >> 1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drop_frame_skips_frame_numbers() {
+        let rate = FrameRate::Fps2997Drop;
+        let minute = Timecode::from_frames(1800, rate);
+        assert_eq!((minute.minutes, minute.seconds, minute.frames), (1, 0, 2));
+        assert_eq!(minute.to_frames(), 1800);
+        let ten_minutes = Timecode::from_frames(DROP_FRAMES_PER_TEN_MINUTES, rate);
+        assert_eq!((ten_minutes.minutes, ten_minutes.frames), (10, 0));
+
+        let timecode = Timecode::from_seconds(3723.5, FrameRate::Fps25);
+        let pieces: Vec<u8> = (0..8).map(|piece| timecode.quarter_frame(piece)).collect();
+        assert_eq!(
+            Timecode::from_quarter_frames(&pieces.try_into().unwrap()),
+            timecode
+        );
+        // the time is rounded down to the start of its frame
+        assert_eq!(timecode.to_seconds(), 3723.48);
+    }
+}
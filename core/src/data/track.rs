@@ -1,7 +1,16 @@
 #![deny(missing_docs)]
-use super::clip::{Clip, ClipCollection, ClipId, ClipKey};
-use crate::{instrument::Instrument, DataId};
-use std::{fmt::Display, ops::Deref};
+use super::{
+    arpeggiator::ArpeggiatorSettings,
+    automation::AutomationLane,
+    clip::{Clip, ClipCollection, ClipId, ClipKey},
+    event_list::EventSegment,
+};
+use crate::{instrument::Instrument, DataId, Tick};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    ops::{Deref, Range},
+};
 use thiserror::Error;
 
 /// error type for track collection
@@ -17,6 +26,8 @@ pub enum TrackCollectionError {
 pub struct TrackCollection {
     /// inner vector of tracks
     inner: Vec<Track>,
+    /// index of every track by id
+    positions: HashMap<TrackId, usize>,
 }
 
 impl TrackCollection {
@@ -30,7 +41,7 @@ impl TrackCollection {
 
     /// add a new track to the collection
     pub fn add(&mut self, new_track: Track) {
-        self.inner.push(new_track);
+        self.push(new_track);
     }
 
     /// get the port used by this track
@@ -50,7 +61,14 @@ impl TrackCollection {
 
     /// get a reference to a track at a given index, or 'None' if it doesn't exist
     pub fn get_by_id(&self, index: TrackId) -> Option<&Track> {
-        self.inner.iter().find(|t| t.id == index)
+        self.positions
+            .get(&index)
+            .map(|position| &self.inner[*position])
+    }
+
+    /// get the index of the track with the given id
+    pub fn position(&self, id: TrackId) -> Option<usize> {
+        self.positions.get(&id).copied()
     }
 
     /// get the number of tracks in the collection
@@ -60,17 +78,21 @@ impl TrackCollection {
 
     /// push a new track onto the collection
     pub fn push(&mut self, track: Track) {
+        self.positions.insert(track.id, self.inner.len());
         self.inner.push(track);
     }
 
     /// pops the last track from the collection'
     pub(crate) fn pop(&mut self) -> Option<Track> {
-        self.inner.pop()
+        let track = self.inner.pop()?;
+        self.positions.remove(&track.id);
+        Some(track)
     }
 
     /// gets a mutable reference to the track with the given id
     pub fn get_mut(&mut self, id: TrackId) -> Option<&mut Track> {
-        self.inner.iter_mut().find(|t| t.id == id)
+        let position = *self.positions.get(&id)?;
+        self.inner.get_mut(position)
     }
 
     /// get an iterator over references to the tracks
@@ -82,16 +104,6 @@ impl TrackCollection {
     pub fn iter_mut(&mut self) -> std::slice::IterMut<Track> {
         self.inner.iter_mut()
     }
-
-    /// take the clip out of any track if found, removing it from the track
-    pub fn take_clip(&mut self, clip_id: ClipId) -> Option<Clip> {
-        for track in &mut self.inner {
-            if let result @ Some(_) = track.clip_collection.find_take(clip_id) {
-                return result;
-            }
-        }
-        None
-    }
 }
 
 /// data identifier of a track
@@ -118,6 +130,21 @@ impl Deref for TrackId {
     }
 }
 
+/// an event of a clip, positioned on the timeline of the project
+#[derive(Debug, Clone, Copy)]
+pub struct ArrangedEvent<'a> {
+    /// track the event is on
+    pub track_id: TrackId,
+    /// clip the event is in
+    pub clip_id: ClipId,
+    /// absolute start tick
+    pub start: Tick,
+    /// absolute end tick, cut off at the end of the clip
+    pub end: Tick,
+    /// the event itself, with ticks relative to the clip
+    pub segment: &'a EventSegment,
+}
+
 /// track object
 #[derive(Default, Debug)]
 pub struct Track {
@@ -129,6 +156,16 @@ pub struct Track {
     pub instrument: Instrument,
     /// clips in this track
     pub clip_collection: ClipCollection,
+    /// arpeggiator applied to the notes of this track during playback, 'None' when disabled
+    pub arpeggiator: Option<ArpeggiatorSettings>,
+    /// automation lanes of this track
+    pub automation: Vec<AutomationLane>,
+    /// true if the messages received on the track's input are recorded onto it
+    pub record_arm: bool,
+    /// id of the midi input the track records from, 'None' for every input
+    pub input: Option<u8>,
+    /// earlier takes of loop recordings, kept so they can be brought back, not played
+    pub takes: Vec<Clip>,
 }
 
 impl Display for Track {
@@ -146,6 +183,11 @@ impl Track {
             name: String::from(name),
             instrument: Instrument::new("port0", 0, 0),
             clip_collection: ClipCollection::new(),
+            arpeggiator: None,
+            automation: Vec::new(),
+            record_arm: false,
+            input: None,
+            takes: Vec::new(),
         }
     }
 
@@ -159,13 +201,53 @@ impl Track {
         self.instrument.channel = channel;
     }
 
-    /// add a new clip to the track
-    pub fn add_clip(&mut self, clip: Clip) {
+    /// arm the track for recording the messages of its input, or disarm it
+    pub fn set_record_arm(&mut self, armed: bool) {
+        self.record_arm = armed;
+    }
+
+    /// enable the arpeggiator on this track, or disable it by passing 'None'
+    pub fn set_arpeggiator(&mut self, settings: Option<ArpeggiatorSettings>) {
+        self.arpeggiator = settings;
+    }
+
+    /// add a new automation lane to the track
+    pub fn add_automation_lane(&mut self, lane: AutomationLane) {
+        self.automation.push(lane);
+    }
+
+    /// add a new clip to the track, use 'Project::add_clip' once the track is in a project
+    pub(crate) fn add_clip(&mut self, clip: Clip) {
         self.clip_collection.insert(clip);
     }
 
+    /// get the events overlapping the given range in absolute ticks, sorted by clip
+    pub fn events_in(&self, range: Range<Tick>) -> impl Iterator<Item = ArrangedEvent<'_>> {
+        self.clip_collection
+            .clips_in(range.clone())
+            .flat_map(move |clip| {
+                let local =
+                    range.start.saturating_sub(clip.start)..range.end.saturating_sub(clip.start);
+                let range = range.clone();
+                clip.events
+                    .events_in(local)
+                    .map(move |segment| ArrangedEvent {
+                        track_id: self.id,
+                        clip_id: clip.id,
+                        start: clip.start + segment.start,
+                        end: clip.start + segment.end.min(clip.duration),
+                        segment,
+                    })
+                    .filter(move |event| {
+                        event.start < event.end
+                            && event.start < range.end
+                            && event.end > range.start
+                    })
+            })
+    }
+
     /// removes a clip from the track by its key
-    pub fn remove_clip(&mut self, key: &ClipKey) {
+    pub(crate) fn remove_clip(&mut self, key: &ClipKey) {
         self.clip_collection.remove(key);
     }
 }
@@ -6,7 +6,7 @@ use std::fmt::Display;
 pub struct Instrument {
     /// the name of the instrument
     pub name: String,
-    /// midi port used for the instrument
+    /// id of the logical midi port of the project used for the instrument
     pub port: u8,
     /// midi channel used for the instrument
     pub channel: u8,
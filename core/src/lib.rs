@@ -22,6 +22,9 @@ use std::ops::Sub;
 use std::ops::SubAssign;
 use std::time::Duration;
 
+/// parts per quarter note, the number of ticks in a beat
+pub const PPQN: u32 = 480;
+
 /// represents a moment in time
 /// events are sent every tick
 #[derive(Default, PartialEq, PartialOrd, Ord, Eq, Clone, Debug, Copy)]
@@ -120,6 +123,16 @@ impl Tick {
     pub fn as_f64(&self) -> f64 {
         self.0 as f64
     }
+
+    /// subtracts 'rhs', stopping at zero instead of underflowing
+    pub fn saturating_sub(self, rhs: Tick) -> Tick {
+        Self(self.0.saturating_sub(rhs.0))
+    }
+
+    /// returns this 'Tick' as an 'u64'
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
 }
 
 impl Display for Tick {
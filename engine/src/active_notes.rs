@@ -0,0 +1,61 @@
+use std::collections::BTreeSet;
+
+use hexencer_core::data::MidiMessage;
+
+/// keeps track of the notes which are sounding on every port and channel
+#[derive(Default, Debug, Clone)]
+pub struct ActiveNotes {
+    /// sounding notes as (port, channel, key)
+    inner: BTreeSet<(u8, u8, u8)>,
+}
+
+impl ActiveNotes {
+    /// creates a new, empty, 'ActiveNotes'
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// updates the sounding notes with an outgoing message,
+    /// returns false if the message is a note off for a note which is not sounding
+    pub fn track(&mut self, message: &MidiMessage, port: u8, channel: u8) -> bool {
+        match message {
+            MidiMessage::NoteOn { key, velocity: 0 } | MidiMessage::NoteOff { key, .. } => {
+                self.inner.remove(&(port, channel, *key))
+            }
+            MidiMessage::NoteOn { key, .. } => {
+                self.inner.insert((port, channel, *key));
+                true
+            }
+            MidiMessage::AllNoteOff => {
+                self.inner.retain(|(note_port, note_channel, _)| {
+                    (*note_port, *note_channel) != (port, channel)
+                });
+                true
+            }
+            _ => true,
+        }
+    }
+
+    /// true if the given note is sounding
+    pub fn contains(&self, port: u8, channel: u8, key: u8) -> bool {
+        self.inner.contains(&(port, channel, key))
+    }
+
+    /// get the number of sounding notes
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// true if no notes are sounding
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// removes all sounding notes, returning the note offs which release them
+    pub fn release_all(&mut self) -> Vec<(MidiMessage, u8, u8)> {
+        std::mem::take(&mut self.inner)
+            .into_iter()
+            .map(|(port, channel, key)| (MidiMessage::NoteOff { key, velocity: 0 }, port, channel))
+            .collect()
+    }
+}
@@ -0,0 +1,227 @@
+use hexencer_core::{
+    data::{ArpMode, ArpeggiatorSettings, MidiMessage},
+    Tick,
+};
+
+use crate::random::Random;
+
+/// runtime state of an arpeggiator, turns held notes into a pattern driven by the sequencer tick
+#[derive(Debug, Clone)]
+pub struct Arpeggiator {
+    /// settings used to generate the pattern
+    settings: ArpeggiatorSettings,
+    /// notes feeding the pattern as (key, velocity), in the order they were pressed
+    held: Vec<(u8, u8)>,
+    /// keys which are physically pressed right now, used to decide when a latched chord ends
+    pressed: Vec<u8>,
+    /// number of steps played since the last reset, used to walk the pattern
+    step: u64,
+    /// note currently sounding and the tick at which it should be released
+    sounding: Option<(u8, Tick)>,
+    /// random generator used by 'ArpMode::Random'
+    random: Random,
+}
+
+impl Arpeggiator {
+    /// creates a new 'Arpeggiator' using the given settings
+    pub fn new(settings: ArpeggiatorSettings) -> Self {
+        Self {
+            settings,
+            held: Vec::new(),
+            pressed: Vec::new(),
+            step: 0,
+            sounding: None,
+            random: Random::default(),
+        }
+    }
+
+    /// get the settings of this arpeggiator
+    pub fn settings(&self) -> &ArpeggiatorSettings {
+        &self.settings
+    }
+
+    /// replace the settings, the held notes are kept
+    pub fn set_settings(&mut self, settings: ArpeggiatorSettings) {
+        if !settings.latch && self.settings.latch {
+            self.held.retain(|(key, _)| self.pressed.contains(key));
+        }
+        self.settings = settings;
+    }
+
+    /// add a note to the held notes
+    pub fn note_on(&mut self, key: u8, velocity: u8) {
+        if self.settings.latch && self.pressed.is_empty() {
+            // a new chord replaces the latched one
+            self.held.clear();
+        }
+        self.pressed.push(key);
+        if !self.held.iter().any(|(held_key, _)| *held_key == key) {
+            self.held.push((key, velocity));
+        }
+    }
+
+    /// release a held note, latched notes keep playing until a new chord starts
+    pub fn note_off(&mut self, key: u8) {
+        self.pressed.retain(|pressed| *pressed != key);
+        if !self.settings.latch {
+            self.held.retain(|(held_key, _)| *held_key != key);
+        }
+    }
+
+    /// true if there are notes feeding the pattern
+    pub fn is_active(&self) -> bool {
+        !self.held.is_empty()
+    }
+
+    /// process the given tick, returning the messages which should be sent
+    pub fn process(&mut self, tick: Tick) -> Vec<MidiMessage> {
+        let mut messages = Vec::new();
+
+        if let Some((key, end)) = self.sounding {
+            if tick >= end {
+                messages.push(MidiMessage::NoteOff { key, velocity: 0 });
+                self.sounding = None;
+            }
+        }
+
+        let rate = self.settings.rate.as_u64();
+        if rate == 0 || self.held.is_empty() {
+            return messages;
+        }
+
+        let step = tick.as_u64() / rate;
+        if self.settings.step_start(step) != tick {
+            return messages;
+        }
+
+        let pattern = self.pattern();
+        let index = match self.settings.mode {
+            ArpMode::Random => self.random.index(pattern.len()),
+            _ => (self.step % pattern.len() as u64) as usize,
+        };
+        let (key, velocity) = pattern[index];
+
+        if let Some((sounding_key, _)) = self.sounding.take() {
+            messages.push(MidiMessage::NoteOff {
+                key: sounding_key,
+                velocity: 0,
+            });
+        }
+        messages.push(MidiMessage::NoteOn { key, velocity });
+        self.sounding = Some((key, tick + self.settings.note_length()));
+        self.step += 1;
+
+        messages
+    }
+
+    /// clear all notes and restart the pattern, returns the note off for a sounding note
+    pub fn reset(&mut self) -> Option<MidiMessage> {
+        self.held.clear();
+        self.pressed.clear();
+        self.step = 0;
+        self.random = Random::default();
+        self.sounding
+            .take()
+            .map(|(key, _)| MidiMessage::NoteOff { key, velocity: 0 })
+    }
+
+    /// builds the full pattern of (key, velocity) for the held notes, spread over the octave range
+    fn pattern(&self) -> Vec<(u8, u8)> {
+        let mut notes = self.held.clone();
+        if self.settings.mode != ArpMode::AsPlayed {
+            notes.sort_by_key(|(key, _)| *key);
+        }
+
+        let mut pattern = Vec::new();
+        for octave in 0..self.settings.octaves.max(1) {
+            for (key, velocity) in &notes {
+                let transposed = *key as u16 + octave as u16 * 12;
+                if transposed <= 127 {
+                    pattern.push((transposed as u8, *velocity));
+                }
+            }
+        }
+
+        match self.settings.mode {
+            ArpMode::Down => pattern.reverse(),
+            ArpMode::UpDown if pattern.len() > 2 => {
+                let down: Vec<_> = pattern[1..pattern.len() - 1]
+                    .iter()
+                    .rev()
+                    .copied()
+                    .collect();
+                pattern.extend(down);
+            }
+            _ => {}
+        }
+
+        if pattern.is_empty() {
+            // every note was transposed out of range, fall back to the held notes
+            pattern = notes;
+        }
+        pattern
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// collects the keys of all note on messages produced between 0 and 'end'
+    fn played_keys(arpeggiator: &mut Arpeggiator, end: u64) -> Vec<u8> {
+        let mut keys = Vec::new();
+        for tick in 0..end {
+            for message in arpeggiator.process(Tick::from(tick)) {
+                if let MidiMessage::NoteOn { key, .. } = message {
+                    keys.push(key);
+                }
+            }
+        }
+        keys
+    }
+
+    #[test]
+    fn up_down_over_two_octaves() {
+        let mut settings = ArpeggiatorSettings::new(ArpMode::UpDown, Tick::from(10));
+        settings.octaves = 2;
+        let mut arpeggiator = Arpeggiator::new(settings);
+        arpeggiator.note_on(64, 100);
+        arpeggiator.note_on(60, 100);
+
+        assert_eq!(
+            played_keys(&mut arpeggiator, 60),
+            vec![60, 64, 72, 76, 72, 64]
+        );
+    }
+
+    #[test]
+    fn latch_keeps_notes_until_new_chord() {
+        let mut settings = ArpeggiatorSettings::new(ArpMode::AsPlayed, Tick::from(10));
+        settings.latch = true;
+        let mut arpeggiator = Arpeggiator::new(settings);
+        arpeggiator.note_on(67, 100);
+        arpeggiator.note_on(60, 100);
+        arpeggiator.note_off(67);
+        arpeggiator.note_off(60);
+        assert_eq!(played_keys(&mut arpeggiator, 20), vec![67, 60]);
+
+        arpeggiator.note_on(50, 100);
+        assert!(arpeggiator.is_active());
+        assert_eq!(played_keys(&mut arpeggiator, 20), vec![50, 50]);
+    }
+
+    #[test]
+    fn gate_releases_note_before_next_step() {
+        let mut arpeggiator =
+            Arpeggiator::new(ArpeggiatorSettings::new(ArpMode::Up, Tick::from(10)));
+        arpeggiator.note_on(60, 100);
+
+        assert_eq!(arpeggiator.process(Tick::from(0)).len(), 1);
+        assert!(arpeggiator.process(Tick::from(4)).is_empty());
+        let released = arpeggiator.process(Tick::from(5));
+        assert!(matches!(
+            released[..],
+            [MidiMessage::NoteOff { key: 60, .. }]
+        ));
+    }
+}
@@ -0,0 +1,308 @@
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use midir::{MidiOutput, MidiOutputConnection};
+
+use crate::midi_engine::{list_outputs, MidiEngineError, CLIENT_NAME};
+
+/// destination of the messages sent by the 'MidiEngine', outputs are opened per logical port
+pub trait OutputBackend: Send {
+    /// get the names of the outputs which can be opened
+    fn outputs(&mut self) -> Result<Vec<String>, MidiEngineError>;
+    /// connect the logical port 'id' to the output with the exact name 'name', replacing its previous output
+    fn connect(&mut self, id: u8, name: &str) -> Result<(), MidiEngineError>;
+    /// disconnect the output of a logical port, if it has one
+    fn disconnect(&mut self, id: u8);
+    /// send encoded midi bytes to the output of a logical port, 'time' is when the message
+    /// is due on the sequencer's clock, the 'MidiEngine' only sends messages once they are due
+    fn send(&mut self, id: u8, bytes: &[u8], time: Duration) -> Result<(), MidiEngineError>;
+    /// create an output called 'name' which other applications can connect to, for the logical port 'id'
+    fn create_virtual(&mut self, id: u8, name: &str) -> Result<(), MidiEngineError> {
+        let _ = (id, name);
+        Err(MidiEngineError::VirtualUnsupported)
+    }
+}
+
+/// backend sending to the midi outputs of the platform
+#[derive(Default)]
+pub struct MidirBackend {
+    /// open midi output connections by logical port id
+    connections: HashMap<u8, MidiOutputConnection>,
+}
+
+impl MidirBackend {
+    /// create a new 'MidirBackend' without any open outputs
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl OutputBackend for MidirBackend {
+    fn outputs(&mut self) -> Result<Vec<String>, MidiEngineError> {
+        list_outputs()
+    }
+
+    fn connect(&mut self, id: u8, name: &str) -> Result<(), MidiEngineError> {
+        let midi_out = MidiOutput::new(CLIENT_NAME)?;
+        let port = midi_out
+            .ports()
+            .into_iter()
+            .find(|port| {
+                midi_out
+                    .port_name(port)
+                    .is_ok_and(|port_name| port_name == name)
+            })
+            .ok_or_else(|| MidiEngineError::NoOutput(String::from(name)))?;
+        let connection = midi_out
+            .connect(&port, &format!("{CLIENT_NAME}-{id}"))
+            .map_err(|error| MidiEngineError::Connect {
+                name: String::from(name),
+                reason: error.to_string(),
+            })?;
+        if let Some(previous) = self.connections.insert(id, connection) {
+            previous.close();
+        }
+        Ok(())
+    }
+
+    fn disconnect(&mut self, id: u8) {
+        if let Some(connection) = self.connections.remove(&id) {
+            connection.close();
+        }
+    }
+
+    fn send(&mut self, id: u8, bytes: &[u8], _time: Duration) -> Result<(), MidiEngineError> {
+        let connection = self
+            .connections
+            .get_mut(&id)
+            .ok_or(MidiEngineError::NotConnected(id))?;
+        connection
+            .send(bytes)
+            .map_err(|error| MidiEngineError::Send {
+                port: id,
+                reason: error.to_string(),
+            })
+    }
+
+    #[cfg(unix)]
+    fn create_virtual(&mut self, id: u8, name: &str) -> Result<(), MidiEngineError> {
+        use midir::os::unix::VirtualOutput;
+
+        let connection = MidiOutput::new(CLIENT_NAME)?
+            .create_virtual(name)
+            .map_err(|error| MidiEngineError::Connect {
+                name: String::from(name),
+                reason: error.to_string(),
+            })?;
+        if let Some(previous) = self.connections.insert(id, connection) {
+            previous.close();
+        }
+        Ok(())
+    }
+}
+
+/// a message captured by the 'MemoryBackend'
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SentMessage {
+    /// logical port the message was sent to
+    pub port: u8,
+    /// name of the output the port was connected to
+    pub output: String,
+    /// encoded midi message
+    pub bytes: Vec<u8>,
+    /// time at which the message was due
+    pub time: Duration,
+}
+
+/// state shared between the clones of a 'MemoryBackend'
+#[derive(Debug, Default)]
+struct MemoryState {
+    /// names of the pretend outputs
+    outputs: Vec<String>,
+    /// output names by logical port id
+    connections: HashMap<u8, String>,
+    /// every message sent so far
+    sent: Vec<SentMessage>,
+}
+
+/// backend capturing the sent messages in memory, its outputs can be added and removed
+/// to pretend devices are plugged in or out, clones share the same outputs and messages
+#[derive(Debug, Clone, Default)]
+pub struct MemoryBackend {
+    /// shared outputs and captured messages
+    state: Arc<Mutex<MemoryState>>,
+}
+
+impl MemoryBackend {
+    /// create a new 'MemoryBackend' with outputs called 'outputs'
+    pub fn new(outputs: &[&str]) -> Self {
+        let backend = Self::default();
+        for output in outputs {
+            backend.add_output(output);
+        }
+        backend
+    }
+
+    /// add an output, as if a device was plugged in
+    pub fn add_output(&self, name: &str) {
+        self.state.lock().unwrap().outputs.push(String::from(name));
+    }
+
+    /// remove an output, as if a device was unplugged, sending to it fails from now on
+    pub fn remove_output(&self, name: &str) {
+        self.state
+            .lock()
+            .unwrap()
+            .outputs
+            .retain(|output| output != name);
+    }
+
+    /// get every message sent so far
+    pub fn sent(&self) -> Vec<SentMessage> {
+        self.state.lock().unwrap().sent.clone()
+    }
+
+    /// remove and return every message sent so far
+    pub fn take_sent(&self) -> Vec<SentMessage> {
+        std::mem::take(&mut self.state.lock().unwrap().sent)
+    }
+}
+
+impl OutputBackend for MemoryBackend {
+    fn outputs(&mut self) -> Result<Vec<String>, MidiEngineError> {
+        Ok(self.state.lock().unwrap().outputs.clone())
+    }
+
+    fn connect(&mut self, id: u8, name: &str) -> Result<(), MidiEngineError> {
+        let mut state = self.state.lock().unwrap();
+        if !state.outputs.iter().any(|output| output == name) {
+            return Err(MidiEngineError::NoOutput(String::from(name)));
+        }
+        state.connections.insert(id, String::from(name));
+        Ok(())
+    }
+
+    fn disconnect(&mut self, id: u8) {
+        self.state.lock().unwrap().connections.remove(&id);
+    }
+
+    fn send(&mut self, id: u8, bytes: &[u8], time: Duration) -> Result<(), MidiEngineError> {
+        let mut state = self.state.lock().unwrap();
+        let output = state
+            .connections
+            .get(&id)
+            .cloned()
+            .ok_or(MidiEngineError::NotConnected(id))?;
+        if !state.outputs.contains(&output) {
+            return Err(MidiEngineError::Send {
+                port: id,
+                reason: format!("{output} was removed"),
+            });
+        }
+        state.sent.push(SentMessage {
+            port: id,
+            output,
+            bytes: bytes.to_vec(),
+            time,
+        });
+        Ok(())
+    }
+
+    fn create_virtual(&mut self, id: u8, name: &str) -> Result<(), MidiEngineError> {
+        let mut state = self.state.lock().unwrap();
+        // recreating an output, like after a rescan, keeps it listed once
+        if !state.outputs.iter().any(|output| output == name) {
+            state.outputs.push(String::from(name));
+        }
+        state.connections.insert(id, String::from(name));
+        Ok(())
+    }
+}
+
+/// backend writing every message to a log file, one line per message with the time in seconds
+/// at which it was due, the logical port and the bytes in hex, like '1.250000 0 90 2e 40'
+pub struct FileBackend {
+    /// the log file
+    file: BufWriter<File>,
+    /// names of the pretend outputs
+    outputs: Vec<String>,
+    /// output names by logical port id
+    connections: HashMap<u8, String>,
+}
+
+impl FileBackend {
+    /// create a new 'FileBackend' with outputs called 'outputs', logging to the file at 'path',
+    /// replacing it if it exists
+    pub fn create(path: impl AsRef<Path>, outputs: &[&str]) -> Result<Self, MidiEngineError> {
+        Ok(Self {
+            file: BufWriter::new(File::create(path)?),
+            outputs: outputs.iter().map(|output| String::from(*output)).collect(),
+            connections: HashMap::new(),
+        })
+    }
+}
+
+impl OutputBackend for FileBackend {
+    fn outputs(&mut self) -> Result<Vec<String>, MidiEngineError> {
+        Ok(self.outputs.clone())
+    }
+
+    fn connect(&mut self, id: u8, name: &str) -> Result<(), MidiEngineError> {
+        if !self.outputs.iter().any(|output| output == name) {
+            return Err(MidiEngineError::NoOutput(String::from(name)));
+        }
+        self.connections.insert(id, String::from(name));
+        Ok(())
+    }
+
+    fn disconnect(&mut self, id: u8) {
+        self.connections.remove(&id);
+        let _ = self.file.flush();
+    }
+
+    fn send(&mut self, id: u8, bytes: &[u8], time: Duration) -> Result<(), MidiEngineError> {
+        if !self.connections.contains_key(&id) {
+            return Err(MidiEngineError::NotConnected(id));
+        }
+        let mut line = format!("{:.6} {}", time.as_secs_f64(), id);
+        for byte in bytes {
+            let _ = write!(line, " {byte:02x}");
+        }
+        writeln!(self.file, "{line}")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_backend_logs_one_line_per_message() {
+        let path = std::env::temp_dir().join(format!("hexencer-log-{}.txt", std::process::id()));
+        let mut backend = FileBackend::create(&path, &["Synth"]).unwrap();
+        assert!(backend.send(0, &[0x90, 46, 64], Duration::ZERO).is_err());
+        backend.connect(0, "Synth").unwrap();
+        assert_eq!(backend.outputs().unwrap(), vec![String::from("Synth")]);
+        backend.send(0, &[0x90, 46, 64], Duration::ZERO).unwrap();
+        backend
+            .send(0, &[0x80, 46, 0], Duration::from_millis(1250))
+            .unwrap();
+        drop(backend);
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 2);
+        // messages are logged with the time they were due
+        assert_eq!(lines[0], "0.000000 0 90 2e 40");
+        assert_eq!(lines[1], "1.250000 0 80 2e 00");
+    }
+}
@@ -0,0 +1,223 @@
+use std::{collections::VecDeque, time::Duration};
+
+use hexencer_core::{
+    data::{MidiMessage, Take, TempoMap},
+    Tick, TrackId, PPQN,
+};
+
+use crate::recorder::Recorder;
+
+/// most events kept by the capture buffer, older events are dropped first
+pub const CAPTURE_CAPACITY: usize = 4096;
+/// silence after which playing again starts a new phrase, only the latest phrase is captured
+pub const PHRASE_GAP: Duration = Duration::from_secs(4);
+/// lowest tempo considered when estimating the tempo of free time playing, the highest is twice this
+const MIN_ESTIMATED_BPM: f64 = 80.0;
+/// step between the tempos tried when estimating
+const BPM_STEP: f64 = 0.25;
+/// number of ticks in a bar, captured phrases start and end on a bar
+const BAR: u64 = PPQN as u64 * 4;
+
+/// a message kept by the capture buffer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CapturedEvent {
+    /// time the message was received, measured on the sequencer's clock
+    pub time: Duration,
+    /// tick at which the message was played, 'None' if the transport was stopped
+    pub tick: Option<Tick>,
+    /// the received message
+    pub message: MidiMessage,
+}
+
+/// what was captured from the buffer
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capture {
+    /// the captured phrase, ready to be added to a track
+    pub take: Take,
+    /// tempo estimated from the phrase when it was played with the transport stopped
+    pub bpm: Option<f64>,
+}
+
+/// always on buffer of the most recent input, kept whether recording or not
+#[derive(Debug, Default)]
+pub(crate) struct CaptureBuffer {
+    /// captured messages, oldest first
+    events: VecDeque<CapturedEvent>,
+}
+
+impl CaptureBuffer {
+    /// adds a received message, dropping the oldest one when the buffer is full
+    pub(crate) fn push(&mut self, event: CapturedEvent) {
+        if self.events.len() == CAPTURE_CAPACITY {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    /// true if nothing was captured
+    pub(crate) fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// empties the buffer, returning the latest phrase, the messages played without a long silence
+    /// and all either with the transport running or stopped, a jump back of the playhead, like
+    /// the wrap of a loop, starts a new phrase
+    pub(crate) fn take_phrase(&mut self) -> Vec<CapturedEvent> {
+        let events: Vec<_> = self.events.drain(..).collect();
+        let Some(last) = events.last() else {
+            return events;
+        };
+        let running = last.tick.is_some();
+        let start = events
+            .windows(2)
+            .rposition(|pair| {
+                pair[1].time.saturating_sub(pair[0].time) >= PHRASE_GAP
+                    || pair[0].tick.is_some() != running
+                    || matches!((pair[0].tick, pair[1].tick), (Some(before), Some(after)) if after < before)
+            })
+            .map(|index| index + 1)
+            .unwrap_or(0);
+        events[start..].to_vec()
+    }
+
+    /// turns the latest phrase into a take for 'track_id', emptying the buffer, a phrase played
+    /// with the transport stopped is placed at 'position' using a tempo estimated from it
+    pub(crate) fn capture(&mut self, track_id: TrackId, position: Tick) -> Option<Capture> {
+        let phrase = self.take_phrase();
+        let first = phrase.first()?;
+        let (ticks, bpm): (Vec<Tick>, _) = match first.tick {
+            Some(_) => (phrase.iter().filter_map(|event| event.tick).collect(), None),
+            None => {
+                let onsets: Vec<Duration> = phrase
+                    .iter()
+                    .filter(|event| is_note_on(&event.message))
+                    .map(|event| event.time)
+                    .collect();
+                let bpm = estimate_tempo(&onsets).unwrap_or(120.0);
+                let tempo_map = TempoMap::new(bpm);
+                let ticks = phrase
+                    .iter()
+                    .map(|event| {
+                        let seconds = (event.time - first.time).as_secs_f64();
+                        position + tempo_map.tick_at(seconds)
+                    })
+                    .collect();
+                (ticks, Some(bpm))
+            }
+        };
+        let start = Tick::from(ticks.iter().min()?.as_u64() / BAR * BAR);
+        let end = Tick::from((ticks.iter().max()?.as_u64() + 1).div_ceil(BAR) * BAR);
+        // the recorder pairs the notes, notes still held end with the phrase
+        let mut recorder = Recorder::default();
+        recorder.start(start);
+        for (event, tick) in phrase.iter().zip(ticks) {
+            recorder.input(track_id, tick, event.message);
+        }
+        let mut take = recorder.finish(end).pop()?;
+        take.range = start..end;
+        Some(Capture { take, bpm })
+    }
+}
+
+/// true if the message starts a note
+fn is_note_on(message: &MidiMessage) -> bool {
+    matches!(message, MidiMessage::NoteOn { velocity, .. } if *velocity > 0)
+}
+
+/// estimates the tempo of notes played at 'onsets' by finding the tempo whose sixteenth note grid
+/// they fit best, choosing the slowest of equally good tempos, 'None' with fewer than three notes
+pub(crate) fn estimate_tempo(onsets: &[Duration]) -> Option<f64> {
+    let first = *onsets.first()?;
+    if onsets.len() < 3 {
+        return None;
+    }
+    let offsets: Vec<f64> = onsets
+        .iter()
+        .map(|onset| (*onset - first).as_secs_f64())
+        .collect();
+    let steps = (MIN_ESTIMATED_BPM / BPM_STEP) as usize;
+    (0..steps)
+        .map(|step| MIN_ESTIMATED_BPM + step as f64 * BPM_STEP)
+        .map(|bpm| {
+            let sixteenth = 15.0 / bpm;
+            let error: f64 = offsets
+                .iter()
+                .map(|offset| {
+                    let phase = offset / sixteenth;
+                    (phase - phase.round()).powi(2)
+                })
+                .sum();
+            (bpm, error)
+        })
+        .fold(None, |best: Option<(f64, f64)>, (bpm, error)| match best {
+            Some((_, best_error)) if best_error <= error + 1e-9 => best,
+            _ => Some((bpm, error)),
+        })
+        .map(|(bpm, _)| bpm)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn free_time_phrases_get_an_estimated_tempo() {
+        let note = |key, velocity| MidiMessage::NoteOn { key, velocity };
+        let free = |millis, message| CapturedEvent {
+            time: Duration::from_millis(millis),
+            tick: None,
+            message,
+        };
+        let mut buffer = CaptureBuffer::default();
+        // an earlier phrase, separated by a long silence
+        buffer.push(free(0, note(40, 100)));
+        // eighth notes at 100 bpm are 300ms apart
+        for (index, key) in [60, 62, 64, 65, 67].into_iter().enumerate() {
+            let time = 10_000 + index as u64 * 300;
+            buffer.push(free(time, note(key, 100)));
+            buffer.push(free(time + 150, note(key, 0)));
+        }
+
+        let capture = buffer
+            .capture(TrackId::default(), Tick::from(1920))
+            .unwrap();
+        assert_eq!(capture.bpm, Some(100.0));
+        assert!(buffer.is_empty());
+        let take = capture.take;
+        assert_eq!(take.range, Tick::from(1920)..Tick::from(3840));
+        let starts: Vec<_> = take
+            .events
+            .iter()
+            .map(|event| event.start.as_u64())
+            .collect();
+        assert_eq!(starts, vec![1920, 2160, 2400, 2640, 2880]);
+        assert_eq!(take.events[0].end, Tick::from(2040));
+    }
+
+    #[test]
+    fn a_loop_wrap_starts_a_new_phrase() {
+        let note = |key, velocity| MidiMessage::NoteOn { key, velocity };
+        let running = |millis, tick: u64, message| CapturedEvent {
+            time: Duration::from_millis(millis),
+            tick: Some(Tick::from(tick)),
+            message,
+        };
+        let mut buffer = CaptureBuffer::default();
+        buffer.push(running(0, 3850, note(60, 100)));
+        buffer.push(running(100, 3950, note(60, 0)));
+        // the loop wrapped back to its start
+        buffer.push(running(200, 60, note(62, 100)));
+        buffer.push(running(300, 160, note(62, 0)));
+
+        let capture = buffer.capture(TrackId::default(), Tick::zero()).unwrap();
+        assert_eq!(capture.bpm, None);
+        let take = capture.take;
+        assert_eq!(take.range, Tick::zero()..Tick::from(1920));
+        let notes: Vec<_> = take
+            .events
+            .iter()
+            .map(|event| (event.start.as_u64(), event.end.as_u64()))
+            .collect();
+        assert_eq!(notes, vec![(60, 160)]);
+    }
+}
@@ -0,0 +1,108 @@
+use std::collections::BTreeMap;
+
+use hexencer_core::data::MidiMessage;
+
+/// controller number of the bank select msb
+const BANK_SELECT_MSB: u8 = 0;
+/// controller number of the bank select lsb
+const BANK_SELECT_LSB: u8 = 32;
+
+/// latest controller values of a midi channel, used to restore the state of a synth
+/// when playback starts somewhere in the middle of a song
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct ChannelState {
+    /// latest value of each controller
+    controllers: BTreeMap<u8, u8>,
+    /// latest program change
+    program: Option<u8>,
+    /// latest pitch bend
+    pitch_bend: Option<u16>,
+    /// latest channel pressure
+    pressure: Option<u8>,
+}
+
+impl ChannelState {
+    /// creates a new 'ChannelState' without any values
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// remembers the value set by the message, notes are ignored
+    pub fn apply(&mut self, message: &MidiMessage) {
+        match *message {
+            MidiMessage::ControlChange { controller, value } => {
+                self.controllers.insert(controller, value);
+            }
+            MidiMessage::ProgramChange { program } => self.program = Some(program),
+            MidiMessage::PitchBend { value } => self.pitch_bend = Some(value),
+            MidiMessage::ChannelPressure { pressure } => self.pressure = Some(pressure),
+            // notes are chased separately, system messages carry no channel state
+            _ => {}
+        }
+    }
+
+    /// get the messages which restore this state, bank select is sent before the program change
+    /// so the program is picked from the right bank
+    pub fn messages(&self) -> Vec<MidiMessage> {
+        let control = |controller| {
+            self.controllers
+                .get(&controller)
+                .map(|value| MidiMessage::ControlChange {
+                    controller,
+                    value: *value,
+                })
+        };
+        let bank = [control(BANK_SELECT_MSB), control(BANK_SELECT_LSB)];
+        let program = self
+            .program
+            .map(|program| MidiMessage::ProgramChange { program });
+        let controllers = self
+            .controllers
+            .keys()
+            .filter(|controller| ![BANK_SELECT_MSB, BANK_SELECT_LSB].contains(controller))
+            .filter_map(|controller| control(*controller));
+        let pitch_bend = self
+            .pitch_bend
+            .map(|value| MidiMessage::PitchBend { value });
+        let pressure = self
+            .pressure
+            .map(|pressure| MidiMessage::ChannelPressure { pressure });
+
+        bank.into_iter()
+            .chain([program])
+            .flatten()
+            .chain(controllers)
+            .chain(pitch_bend)
+            .chain(pressure)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bank_select_comes_before_the_program() {
+        let mut state = ChannelState::new();
+        let cc = |controller, value| MidiMessage::ControlChange { controller, value };
+        for message in [
+            cc(7, 100),
+            MidiMessage::ProgramChange { program: 4 },
+            cc(BANK_SELECT_MSB, 1),
+            cc(7, 90),
+            MidiMessage::ProgramChange { program: 5 },
+        ] {
+            state.apply(&message);
+        }
+
+        assert_eq!(
+            state.messages(),
+            vec![
+                cc(BANK_SELECT_MSB, 1),
+                MidiMessage::ProgramChange { program: 5 },
+                cc(7, 90),
+            ]
+        );
+    }
+}
@@ -0,0 +1,69 @@
+use std::{
+    fmt::Debug,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// source of time for the 'Sequencer'
+pub trait Clock: Debug + Send + Sync {
+    /// get the time passed since the clock started
+    fn now(&self) -> Duration;
+}
+
+/// clock following wall time
+#[derive(Debug, Clone, Copy)]
+pub struct RealClock {
+    /// moment the clock started
+    start: Instant,
+}
+
+impl RealClock {
+    /// creates a new 'RealClock' starting now
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for RealClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for RealClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+/// clock which only moves when advanced, clones share the same time
+#[derive(Default, Debug, Clone)]
+pub struct VirtualClock {
+    /// current time of the clock
+    now: Arc<Mutex<Duration>>,
+}
+
+impl VirtualClock {
+    /// creates a new 'VirtualClock' at time zero
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// moves the clock forward by the given duration
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+
+    /// set the time of the clock
+    pub fn set(&self, now: Duration) {
+        *self.now.lock().unwrap() = now;
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Duration {
+        *self.now.lock().unwrap()
+    }
+}
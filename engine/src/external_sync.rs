@@ -0,0 +1,132 @@
+use std::time::Duration;
+
+use crate::midi_clock::{CLOCKS_PER_BEAT, TICKS_PER_CLOCK};
+
+/// weight of a new pulse interval in the smoothed interval
+const SMOOTHING: f64 = 0.1;
+/// time without clock pulses after which the external clock is considered gone
+pub const DROPOUT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// where the sequencer takes its tempo and transport from
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncSource {
+    /// the sequencer follows the tempo map of the project
+    #[default]
+    Internal,
+    /// the sequencer follows midi clock and transport messages from an input port
+    External {
+        /// input port the clock is received on
+        port: u8,
+    },
+    /// the sequencer locates to and follows midi time code from an input port
+    Timecode {
+        /// input port the timecode is received on
+        port: u8,
+        /// timecode at which the project starts
+        offset: Duration,
+    },
+}
+
+/// follows an incoming 24 ppqn midi clock, estimating its tempo and limiting
+/// how far the playhead may run ahead of the received pulses
+#[derive(Debug, Clone)]
+pub struct ClockFollower {
+    /// input port the clock is received on
+    port: u8,
+    /// smoothed time between two pulses in seconds
+    interval: Option<f64>,
+    /// time at which the last pulse was received
+    last_pulse: Option<Duration>,
+    /// number of ticks the playhead may still advance before the next pulse
+    budget: u64,
+}
+
+impl ClockFollower {
+    /// creates a new 'ClockFollower' for the clock of an input port
+    pub fn new(port: u8) -> Self {
+        Self {
+            port,
+            interval: None,
+            last_pulse: None,
+            budget: 0,
+        }
+    }
+
+    /// get the input port the clock is received on
+    pub fn port(&self) -> u8 {
+        self.port
+    }
+
+    /// get the estimated tempo of the clock, 'None' until two pulses were received
+    pub fn bpm(&self) -> Option<f64> {
+        self.interval
+            .map(|interval| 60.0 / (interval * CLOCKS_PER_BEAT as f64))
+    }
+
+    /// registers a pulse received at 'now', when 'running' the playhead may advance another pulse,
+    /// returns the number of ticks the playhead is behind the pulse
+    pub fn pulse(&mut self, now: Duration, running: bool) -> u64 {
+        if let Some(last) = self.last_pulse.filter(|last| now > *last) {
+            let interval = now - last;
+            // the first pulse after a dropout says nothing about the tempo
+            if interval < DROPOUT_TIMEOUT {
+                let interval = interval.as_secs_f64();
+                self.interval = Some(match self.interval {
+                    Some(smoothed) => smoothed + (interval - smoothed) * SMOOTHING,
+                    None => interval,
+                });
+            }
+        }
+        self.last_pulse = Some(now);
+        if !running {
+            return 0;
+        }
+        let behind = self.budget;
+        self.budget += TICKS_PER_CLOCK;
+        behind
+    }
+
+    /// true if the playhead may advance to the next tick
+    pub fn can_advance(&self) -> bool {
+        self.budget > 0
+    }
+
+    /// uses up a tick of the budget, after the playhead advanced
+    pub fn advance(&mut self) {
+        self.budget = self.budget.saturating_sub(1);
+    }
+
+    /// true if no pulse was received for too long while the clock was expected to run
+    pub fn timed_out(&self, now: Duration) -> bool {
+        self.last_pulse
+            .is_some_and(|last| now.saturating_sub(last) > DROPOUT_TIMEOUT)
+    }
+
+    /// waits for the next pulse before the playhead advances again, used when the transport
+    /// starts, stops or jumps
+    pub fn reset(&mut self) {
+        self.budget = 0;
+        self.last_pulse = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimates_tempo_and_limits_the_playhead() {
+        let mut follower = ClockFollower::new(0);
+        let interval = Duration::from_secs_f64(1.0 / 48.0);
+        assert_eq!(follower.pulse(Duration::ZERO, true), 0);
+        assert!(follower.bpm().is_none());
+        assert_eq!(follower.pulse(interval, true), TICKS_PER_CLOCK);
+        assert!((follower.bpm().unwrap() - 120.0).abs() < 1e-3);
+
+        for _ in 0..TICKS_PER_CLOCK * 2 {
+            follower.advance();
+        }
+        assert!(!follower.can_advance());
+        assert!(follower.timed_out(interval + DROPOUT_TIMEOUT * 2));
+    }
+}
@@ -0,0 +1,197 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::midi_engine::stable_name;
+
+/// time between two automatic rescans of the midi outputs
+pub const RESCAN_INTERVAL: Duration = Duration::from_secs(2);
+
+/// whether the output of a logical port is usable
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortState {
+    /// the output is open and messages are sent to it
+    Connected,
+    /// the device is gone, messages for the port are dropped until it returns
+    Missing,
+}
+
+/// state of a logical port, as shown to the user
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortStatus {
+    /// logical port id
+    pub id: u8,
+    /// name of the output the port wants to use
+    pub name: String,
+    /// whether the output is usable
+    pub state: PortState,
+}
+
+/// a difference between the watched ports and the outputs which exist
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum PortChange {
+    /// the output of a connected port no longer exists
+    Lost(u8),
+    /// an output for a missing port exists again, with its full name
+    Returned(u8, String),
+}
+
+/// a logical port watched for its output to disappear or return
+#[derive(Debug)]
+struct WatchedPort {
+    /// name of the wanted output
+    name: String,
+    /// full name of the output while connected, which includes the address on some platforms
+    connected_to: Option<String>,
+    /// messages dropped since the port went missing
+    dropped: u64,
+    /// true for virtual ports, which are created by hexencer and never disappear
+    is_virtual: bool,
+}
+
+/// keeps track of the wanted outputs of the logical ports and which of them are connected
+#[derive(Debug, Default)]
+pub(crate) struct PortWatcher {
+    /// watched ports by logical port id
+    ports: BTreeMap<u8, WatchedPort>,
+}
+
+impl PortWatcher {
+    /// watch the output called 'name' for the logical port 'id', the port starts out missing
+    pub(crate) fn watch(&mut self, id: u8, name: &str, is_virtual: bool) {
+        self.ports.insert(
+            id,
+            WatchedPort {
+                name: String::from(name),
+                connected_to: None,
+                dropped: 0,
+                is_virtual,
+            },
+        );
+    }
+
+    /// stop watching a logical port
+    pub(crate) fn unwatch(&mut self, id: u8) {
+        self.ports.remove(&id);
+    }
+
+    /// stop watching every port
+    pub(crate) fn clear(&mut self) {
+        self.ports.clear();
+    }
+
+    /// get the name of the wanted output of a port
+    pub(crate) fn name(&self, id: u8) -> Option<&str> {
+        self.ports.get(&id).map(|port| port.name.as_str())
+    }
+
+    /// true if the port is connected to its output
+    pub(crate) fn is_connected(&self, id: u8) -> bool {
+        self.ports
+            .get(&id)
+            .is_some_and(|port| port.connected_to.is_some())
+    }
+
+    /// mark a port as connected to the output with the full name 'output',
+    /// returns the number of messages dropped while it was missing
+    pub(crate) fn connected(&mut self, id: u8, output: &str) -> u64 {
+        let Some(port) = self.ports.get_mut(&id) else {
+            return 0;
+        };
+        port.connected_to = Some(String::from(output));
+        std::mem::take(&mut port.dropped)
+    }
+
+    /// mark a port as missing
+    pub(crate) fn lost(&mut self, id: u8) {
+        if let Some(port) = self.ports.get_mut(&id) {
+            port.connected_to = None;
+        }
+    }
+
+    /// count a message dropped because the port is missing, true for the first dropped message
+    pub(crate) fn drop_message(&mut self, id: u8) -> bool {
+        match self.ports.get_mut(&id) {
+            Some(port) => {
+                port.dropped += 1;
+                port.dropped == 1
+            }
+            None => false,
+        }
+    }
+
+    /// compare the watched ports with the outputs which exist, a port whose output was replaced
+    /// by a new one with the same stable name, like a device replugged between two scans,
+    /// is reported as lost and returned, virtual ports are skipped
+    pub(crate) fn changes(&self, outputs: &[String]) -> Vec<PortChange> {
+        let mut changes = Vec::new();
+        for (&id, port) in self.ports.iter().filter(|(_, port)| !port.is_virtual) {
+            if let Some(connected_to) = &port.connected_to {
+                if outputs.contains(connected_to) {
+                    continue;
+                }
+                changes.push(PortChange::Lost(id));
+            }
+            let wanted = stable_name(&port.name);
+            if let Some(output) = outputs.iter().find(|output| stable_name(output) == wanted) {
+                changes.push(PortChange::Returned(id, output.clone()));
+            }
+        }
+        changes
+    }
+
+    /// get the status of every watched port
+    pub(crate) fn statuses(&self) -> Vec<PortStatus> {
+        self.ports
+            .iter()
+            .map(|(&id, port)| PortStatus {
+                id,
+                name: port.name.clone(),
+                state: match port.connected_to {
+                    Some(_) => PortState::Connected,
+                    None => PortState::Missing,
+                },
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ports_are_lost_and_return_with_their_device() {
+        let mut watcher = PortWatcher::default();
+        watcher.watch(0, "Synth MIDI 1", false);
+        watcher.watch(1, "Drums", false);
+        watcher.watch(2, "hexencer out", true);
+        watcher.connected(0, "Synth MIDI 1 20:0");
+        watcher.connected(1, "Drums");
+        watcher.connected(2, "hexencer out");
+
+        let outputs = vec![String::from("Drums")];
+        assert_eq!(watcher.changes(&outputs), vec![PortChange::Lost(0)]);
+        watcher.lost(0);
+        assert!(watcher.drop_message(0));
+        assert!(!watcher.drop_message(0));
+        assert_eq!(watcher.statuses()[0].state, PortState::Missing);
+
+        // replugged under a new address
+        let outputs = vec![String::from("Synth MIDI 1 24:0"), String::from("Drums")];
+        assert_eq!(
+            watcher.changes(&outputs),
+            vec![PortChange::Returned(0, String::from("Synth MIDI 1 24:0"))]
+        );
+        assert_eq!(watcher.connected(0, "Synth MIDI 1 24:0"), 2);
+
+        // replugged again before a scan noticed it was gone
+        let outputs = vec![String::from("Synth MIDI 1 28:0"), String::from("Drums")];
+        assert_eq!(
+            watcher.changes(&outputs),
+            vec![
+                PortChange::Lost(0),
+                PortChange::Returned(0, String::from("Synth MIDI 1 28:0"))
+            ]
+        );
+    }
+}
@@ -0,0 +1,475 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use hexencer_core::{
+    data::{InputMap, MidiMessage, PortMap},
+    Tick, TrackId,
+};
+use midir::{Ignore, MidiInput};
+use tokio::sync::watch;
+
+use crate::{
+    capture::{Capture, CaptureBuffer, CapturedEvent},
+    midi_engine::{find_input, InputConnection, MidiEngineError, CLIENT_NAME},
+    playback::{PlaybackSnapshot, SharedSnapshot},
+    sequencer::{SequencerCommand, SequencerSender, TimedMessage, TransportPosition},
+    Clock,
+};
+
+/// sender type used to send input events to the recorder of armed tracks
+pub type TrackInputSender = tokio::sync::mpsc::UnboundedSender<TrackInput>;
+/// receiver type used to receive the input events of armed tracks
+pub type TrackInputReceiver = tokio::sync::mpsc::UnboundedReceiver<TrackInput>;
+
+/// port and channel each sounding echoed note went to, by the input, channel and key it was played on
+type EchoedNotes = HashMap<(u8, u8, u8), (u8, u8)>;
+
+/// a channel message received on a midi input
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputEvent {
+    /// id of the input the message was received on
+    pub input: u8,
+    /// time the message was received, measured on the sequencer's clock
+    pub time: Duration,
+    /// the received message
+    pub message: MidiMessage,
+    /// channel the message was received on
+    pub channel: u8,
+}
+
+/// an input event routed to a record armed track
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackInput {
+    /// the armed track
+    pub track_id: TrackId,
+    /// the received message
+    pub event: InputEvent,
+}
+
+/// decodes the messages received on midi inputs, filters them and routes them to the armed tracks,
+/// echoing them to the instrument of the selected track when the input has thru enabled and
+/// keeping them in the capture buffer, routing only reads the playback snapshot so it never waits on the editor,
+/// echoes are played by the sequencer so the notes they sound are released with the others
+#[derive(Debug, Clone)]
+pub struct InputRouter {
+    /// inputs, armed tracks and the selected track
+    snapshot: SharedSnapshot,
+    /// clock used to timestamp the received messages
+    clock: Arc<dyn Clock>,
+    /// position of the transport, used to find the tick at which a message was played
+    transport: watch::Receiver<TransportPosition>,
+    /// recent input, kept whether recording or not
+    capture: Arc<Mutex<CaptureBuffer>>,
+    /// used to echo messages to the instrument of the selected track
+    sequencer: SequencerSender,
+    /// where the echoed notes went, so their note offs follow them
+    echoed: Arc<Mutex<EchoedNotes>>,
+    /// receives the messages routed to armed tracks
+    track_sender: TrackInputSender,
+}
+
+impl InputRouter {
+    /// creates a new 'InputRouter', timestamping on 'clock' and 'transport', which should come from the sequencer
+    pub fn new(
+        snapshot: SharedSnapshot,
+        clock: Arc<dyn Clock>,
+        transport: watch::Receiver<TransportPosition>,
+        sequencer: SequencerSender,
+        track_sender: TrackInputSender,
+    ) -> Self {
+        Self {
+            snapshot,
+            clock,
+            transport,
+            capture: Arc::new(Mutex::new(CaptureBuffer::default())),
+            sequencer,
+            echoed: Arc::new(Mutex::new(HashMap::new())),
+            track_sender,
+        }
+    }
+
+    /// decodes and routes the bytes of a message received on the input 'input'
+    pub fn receive(&self, input: u8, bytes: &[u8]) {
+        let time = self.clock.now();
+        let Some((message, channel)) = MidiMessage::from_midi(bytes) else {
+            return;
+        };
+        let snapshot = self.snapshot.load();
+        let Some(port) = snapshot.inputs.get(input) else {
+            return;
+        };
+        if !port.filter.allows(&message, channel) {
+            return;
+        }
+        let position = *self.transport.borrow();
+        let tick = match position.running {
+            true => Some(position.tick_at(time, &snapshot.tempo_map)),
+            false => None,
+        };
+        if let Ok(mut capture) = self.capture.lock() {
+            capture.push(CapturedEvent {
+                time,
+                tick,
+                message,
+            });
+        }
+        // the echo goes out first, it is what the player hears
+        if port.thru {
+            if let Some((port, channel)) = self.echo_target(input, channel, &message, &snapshot) {
+                let _ = self.sequencer.send(SequencerCommand::Thru(TimedMessage {
+                    tick: position.tick,
+                    time,
+                    message,
+                    port,
+                    channel,
+                }));
+            }
+        }
+        let event = InputEvent {
+            input,
+            time,
+            message,
+            channel,
+        };
+        for track in snapshot.tracks() {
+            if track.record_arm && track.input.is_none_or(|id| id == input) {
+                let _ = self.track_sender.send(TrackInput {
+                    track_id: track.id,
+                    event: event.clone(),
+                });
+            }
+        }
+    }
+
+    /// get the port and channel a message received on 'input' is echoed to, the instrument of the selected
+    /// track, note offs go where their note on went even when another track was selected since
+    fn echo_target(
+        &self,
+        input: u8,
+        channel: u8,
+        message: &MidiMessage,
+        snapshot: &PlaybackSnapshot,
+    ) -> Option<(u8, u8)> {
+        let selected = snapshot
+            .selected_track
+            .and_then(|id| snapshot.track(id))
+            .map(|track| (track.port, track.channel));
+        let Ok(mut echoed) = self.echoed.lock() else {
+            return selected;
+        };
+        match message {
+            MidiMessage::NoteOn { key, velocity: 0 } | MidiMessage::NoteOff { key, .. } => {
+                echoed.remove(&(input, channel, *key))
+            }
+            MidiMessage::NoteOn { key, .. } => {
+                let target = selected?;
+                echoed.insert((input, channel, *key), target);
+                Some(target)
+            }
+            _ => selected,
+        }
+    }
+
+    /// turns the latest phrase of the capture buffer into a take for 'track_id', emptying the buffer,
+    /// a phrase played with the transport stopped is placed at 'position'
+    pub fn capture(&self, track_id: TrackId, position: Tick) -> Option<Capture> {
+        self.capture.lock().ok()?.capture(track_id, position)
+    }
+}
+
+/// opens the midi inputs of the project and passes what they receive to an 'InputRouter'
+pub struct MidiInputEngine {
+    /// routes the received messages
+    router: InputRouter,
+    /// open midi input connections by input id
+    connections: HashMap<u8, InputConnection>,
+}
+
+impl std::fmt::Debug for MidiInputEngine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut open: Vec<_> = self.connections.keys().collect();
+        open.sort();
+        f.debug_struct("MidiInputEngine")
+            .field("router", &self.router)
+            .field("open", &open)
+            .finish()
+    }
+}
+
+impl MidiInputEngine {
+    /// create a new 'MidiInputEngine' without any open inputs
+    pub fn new(router: InputRouter) -> Self {
+        Self {
+            router,
+            connections: HashMap::new(),
+        }
+    }
+
+    /// get the router the received messages are passed to
+    pub fn router(&self) -> &InputRouter {
+        &self.router
+    }
+
+    /// opens the midi input called 'name' for the input 'id', replacing its previous input,
+    /// names are compared by their stable part
+    pub fn open(&mut self, id: u8, name: &str) -> Result<(), MidiEngineError> {
+        let midi_in = MidiInput::new(CLIENT_NAME)?;
+        let port = find_input(&midi_in, name)?;
+        let router = self.router.clone();
+        let connection = midi_in
+            .connect(
+                &port,
+                &format!("{CLIENT_NAME}-in-{id}"),
+                move |_, bytes, _| router.receive(id, bytes),
+                (),
+            )
+            .map_err(|error| MidiEngineError::Connect {
+                name: String::from(name),
+                reason: error.to_string(),
+            })?;
+        // the previous input is closed when dropped
+        self.connections.insert(id, connection);
+        tracing::info!("opened midi input {} as input {}", name, id);
+        Ok(())
+    }
+
+    /// opens every input, returning the errors of the inputs which could not be opened
+    pub fn open_all(&mut self, inputs: &InputMap) -> Vec<MidiEngineError> {
+        inputs
+            .iter()
+            .filter_map(|input| self.open(input.id, &input.name).err())
+            .collect()
+    }
+
+    /// true if the input has an open midi input
+    pub fn is_open(&self, id: u8) -> bool {
+        self.connections.contains_key(&id)
+    }
+
+    /// close the midi input of an input
+    pub fn close(&mut self, id: u8) {
+        if let Some(connection) = self.connections.remove(&id) {
+            connection.close();
+        }
+    }
+}
+
+/// forwards the clock, transport and timecode messages received on an input to the sequencer, tagged with 'port',
+/// other messages are passed to 'router' as received on the input 'port'
+fn forwarder(
+    port: u8,
+    sequencer: SequencerSender,
+    router: Option<InputRouter>,
+) -> impl FnMut(u64, &[u8], &mut ()) + Send + 'static {
+    move |_: u64, bytes: &[u8], _: &mut ()| {
+        let Some((message, _)) = MidiMessage::from_midi(bytes) else {
+            return;
+        };
+        if matches!(
+            message,
+            MidiMessage::TimingClock
+                | MidiMessage::Start
+                | MidiMessage::Continue
+                | MidiMessage::Stop
+                | MidiMessage::SongPosition { .. }
+                | MidiMessage::QuarterFrame { .. }
+                | MidiMessage::FullFrame(_)
+        ) {
+            let _ = sequencer.send(SequencerCommand::ExternalSync { port, message });
+        } else if let Some(router) = &router {
+            router.receive(port, bytes);
+        }
+    }
+}
+
+/// opens the midi input called 'name' and forwards the clock, transport and timecode messages it receives
+/// to the sequencer, tagged with 'port', names are compared by their stable part,
+/// messages are forwarded as long as the connection is kept
+pub fn start_sync_input(
+    name: &str,
+    port: u8,
+    sequencer: SequencerSender,
+) -> Result<InputConnection, MidiEngineError> {
+    let mut midi_in = MidiInput::new(CLIENT_NAME)?;
+    // clock and transport messages are ignored by default
+    midi_in.ignore(Ignore::None);
+    let input = find_input(&midi_in, name)?;
+    midi_in
+        .connect(
+            &input,
+            &format!("{CLIENT_NAME}-sync-{port}"),
+            forwarder(port, sequencer, None),
+            (),
+        )
+        .map_err(|error| MidiEngineError::Connect {
+            name: String::from(name),
+            reason: error.to_string(),
+        })
+}
+
+/// creates a virtual input called 'name' which other applications can send to, the clock, transport
+/// and timecode messages it receives are forwarded to the sequencer, tagged with 'port', and the others
+/// are routed by 'router' like those of the input with the id 'port'
+#[cfg(unix)]
+pub fn start_virtual_input(
+    port: u8,
+    name: &str,
+    sequencer: SequencerSender,
+    router: InputRouter,
+) -> Result<InputConnection, MidiEngineError> {
+    use midir::os::unix::VirtualInput;
+
+    let mut midi_in = MidiInput::new(CLIENT_NAME)?;
+    midi_in.ignore(Ignore::None);
+    midi_in
+        .create_virtual(name, forwarder(port, sequencer, Some(router)), ())
+        .map_err(|error| MidiEngineError::Connect {
+            name: String::from(name),
+            reason: error.to_string(),
+        })
+}
+
+/// virtual inputs can not be created on this platform
+#[cfg(not(unix))]
+pub fn start_virtual_input(
+    _port: u8,
+    _name: &str,
+    _sequencer: SequencerSender,
+    _router: InputRouter,
+) -> Result<InputConnection, MidiEngineError> {
+    Err(MidiEngineError::VirtualUnsupported)
+}
+
+/// the virtual inputs of a project, the inputs are closed when this is dropped
+pub struct VirtualInputs {
+    /// connections of the created inputs
+    connections: Vec<InputConnection>,
+}
+
+impl VirtualInputs {
+    /// get the number of created inputs
+    pub fn len(&self) -> usize {
+        self.connections.len()
+    }
+
+    /// true if no inputs were created
+    pub fn is_empty(&self) -> bool {
+        self.connections.is_empty()
+    }
+}
+
+impl std::fmt::Debug for VirtualInputs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VirtualInputs")
+            .field("len", &self.connections.len())
+            .finish()
+    }
+}
+
+/// creates the virtual inputs of 'ports', which exist for as long as the returned 'VirtualInputs' is kept,
+/// their messages are routed by 'router', inputs which could not be created are logged and skipped
+pub fn start_virtual_inputs(
+    ports: &PortMap,
+    sequencer: &SequencerSender,
+    router: &InputRouter,
+) -> VirtualInputs {
+    let connections = ports
+        .virtual_inputs()
+        .filter_map(|port| {
+            match start_virtual_input(port.id, &port.name, sequencer.clone(), router.clone()) {
+                Ok(connection) => Some(connection),
+                Err(error) => {
+                    tracing::warn!("{}", error);
+                    None
+                }
+            }
+        })
+        .collect();
+    VirtualInputs { connections }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SnapshotPublisher, VirtualClock};
+    use hexencer_core::data::{DataLayer, InputFilter, StorageInterface};
+
+    #[test]
+    fn routes_filtered_messages_to_armed_tracks_and_the_selected_instrument() {
+        let storage = StorageInterface::from_data_layer(DataLayer::default());
+        let (armed, selected) = {
+            let mut data = storage.write().unwrap();
+            data.project_manager.push_track();
+            data.project_manager.push_track();
+            let armed = data.project_manager.tracks().get(0).unwrap().id;
+            let selected = data.project_manager.tracks().get(1).unwrap().id;
+            data.project_manager
+                .edit_track(armed, |track| track.set_record_arm(true));
+            data.project_manager.edit_track(selected, |track| {
+                track.set_port(2);
+                track.set_channel(5);
+            });
+            data.select_track(Some(selected));
+            data.project_manager.edit_inputs(|inputs| {
+                inputs.assign(0, "keys");
+                inputs.get_mut(0).unwrap().filter = InputFilter::channel(0);
+            });
+            (armed, selected)
+        };
+        let publisher = SnapshotPublisher::new(storage.clone(), Tick::from(24));
+        let clock = VirtualClock::new();
+        clock.set(Duration::from_millis(250));
+        let (sequencer_sender, mut sequencer_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let (track_sender, mut track_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let (_transport_sender, transport) = watch::channel(TransportPosition::default());
+        let router = InputRouter::new(
+            publisher.snapshot(),
+            Arc::new(clock),
+            transport,
+            sequencer_sender,
+            track_sender,
+        );
+
+        router.receive(0, &[0x90, 60, 100]);
+        // other channels, inputs and non channel messages are dropped
+        router.receive(0, &[0x91, 60, 100]);
+        router.receive(1, &[0x90, 60, 100]);
+        router.receive(0, &[0xF8]);
+
+        let note = MidiMessage::NoteOn {
+            key: 60,
+            velocity: 100,
+        };
+        let mut echoes = move || {
+            std::iter::from_fn(|| sequencer_receiver.try_recv().ok())
+                .filter_map(|command| match command {
+                    SequencerCommand::Thru(echo) => Some(echo),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        let echo = echoes().pop().unwrap();
+        assert_eq!((echo.message, echo.port, echo.channel), (note, 2, 5));
+        assert_eq!(echo.time, Duration::from_millis(250));
+        let input = track_receiver.try_recv().unwrap();
+        assert_eq!(input.track_id, armed);
+        assert_ne!(input.track_id, selected);
+        assert_eq!(input.event.time, Duration::from_millis(250));
+        assert_eq!(input.event.message, note);
+        assert!(track_receiver.try_recv().is_err());
+
+        // a note off follows its note on when another track was selected in between
+        storage.write().unwrap().select_track(Some(armed));
+        publisher.publish();
+        router.receive(0, &[0x80, 60, 0]);
+        router.receive(0, &[0x90, 62, 100]);
+        let targets: Vec<_> = echoes()
+            .into_iter()
+            .map(|echo| (echo.port, echo.channel))
+            .collect();
+        assert_eq!(targets, vec![(2, 5), (0, 0)]);
+    }
+}
@@ -0,0 +1,398 @@
+use std::collections::{BTreeSet, HashMap};
+
+use hexencer_core::{
+    data::{event_list::EventSegment, ClipSlot, FollowAction, MidiMessage, Session},
+    event::EventType,
+    Tick, TrackId, PPQN,
+};
+
+use crate::{random::Random, sequencer::NoteChanges};
+
+/// number of beats in a bar, used when quantizing launches to the next bar
+const BEATS_PER_BAR: u64 = 4;
+
+/// moment at which a launch takes effect
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LaunchQuantize {
+    /// on the next tick
+    Immediate,
+    /// on the next beat
+    Beat,
+    /// on the next bar
+    #[default]
+    Bar,
+}
+
+impl LaunchQuantize {
+    /// get the first tick at or after 'tick' on which a launch may happen
+    pub fn next_boundary(&self, tick: Tick) -> Tick {
+        let grid = match self {
+            LaunchQuantize::Immediate => return tick,
+            LaunchQuantize::Beat => PPQN as u64,
+            LaunchQuantize::Bar => PPQN as u64 * BEATS_PER_BAR,
+        };
+        Tick::from(tick.as_u64().div_ceil(grid) * grid)
+    }
+}
+
+/// what should be launched
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LaunchTarget {
+    /// a single slot of the session grid
+    Slot {
+        /// track of the slot
+        track_id: TrackId,
+        /// scene of the slot
+        scene: usize,
+    },
+    /// every filled slot of a scene, tracks without a clip in the scene keep playing
+    Scene(usize),
+    /// stop the clip playing on a track
+    StopTrack(TrackId),
+    /// stop all playing clips
+    StopAll,
+}
+
+/// a slot which is playing on a track
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlayingSlot {
+    /// scene of the playing slot
+    pub scene: usize,
+    /// number of ticks played since the slot was launched
+    pub elapsed: u64,
+    /// number of completed loops
+    pub loops: u32,
+}
+
+/// a launch waiting for its quantize boundary
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueuedLaunch {
+    /// scene to launch, 'None' stops the track
+    pub scene: Option<usize>,
+    /// tick at which the launch happens
+    pub at: Tick,
+    /// quantize used to compute 'at'
+    pub quantize: LaunchQuantize,
+}
+
+/// playing and queued slots of every track, visible through the 'SequencerState'
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct LauncherState {
+    /// slot playing on each track
+    pub playing: HashMap<TrackId, PlayingSlot>,
+    /// launches waiting for their quantize boundary
+    pub queued: HashMap<TrackId, QueuedLaunch>,
+}
+
+/// plays the clips of the session grid, at most one per track
+#[derive(Default, Debug)]
+pub struct ClipLauncher {
+    /// playing and queued slots
+    state: LauncherState,
+    /// notes started by the launcher on each track
+    sounding: HashMap<TrackId, BTreeSet<u8>>,
+    /// random generator used by 'FollowAction::Random'
+    random: Random,
+    /// true if the state changed since it was last taken
+    changed: bool,
+}
+
+impl ClipLauncher {
+    /// creates a new 'ClipLauncher' with nothing playing
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// get the playing and queued slots
+    pub fn state(&self) -> &LauncherState {
+        &self.state
+    }
+
+    /// returns the state if it changed since the last call
+    pub fn take_changed(&mut self) -> Option<LauncherState> {
+        std::mem::take(&mut self.changed).then(|| self.state.clone())
+    }
+
+    /// true if a slot is playing on the track
+    pub fn is_playing(&self, track_id: TrackId) -> bool {
+        self.state.playing.contains_key(&track_id)
+    }
+
+    /// queue a launch, it takes effect on the first quantize boundary at or after 'now'
+    pub fn launch(
+        &mut self,
+        session: &Session,
+        target: LaunchTarget,
+        quantize: LaunchQuantize,
+        now: Tick,
+    ) {
+        let at = quantize.next_boundary(now);
+        let mut queue = |track_id, scene| {
+            let launch = QueuedLaunch {
+                scene,
+                at,
+                quantize,
+            };
+            self.state.queued.insert(track_id, launch);
+        };
+
+        match target {
+            LaunchTarget::Slot { track_id, scene } => {
+                if session.slot(track_id, scene).is_some() {
+                    queue(track_id, Some(scene));
+                }
+            }
+            LaunchTarget::Scene(scene) => {
+                for track_id in session.tracks_in_scene(scene) {
+                    queue(track_id, Some(scene));
+                }
+            }
+            LaunchTarget::StopTrack(track_id) => queue(track_id, None),
+            LaunchTarget::StopAll => {
+                let playing: Vec<_> = self.state.playing.keys().copied().collect();
+                for track_id in playing {
+                    queue(track_id, None);
+                }
+            }
+        }
+        self.changed = true;
+    }
+
+    /// re-quantizes queued launches after the playhead jumped to 'tick'
+    pub fn relocate(&mut self, tick: Tick) {
+        for launch in self.state.queued.values_mut() {
+            launch.at = launch.quantize.next_boundary(tick);
+        }
+        self.changed = true;
+    }
+
+    /// stops all slots right away, returning the notes which should be released
+    pub fn stop_all(&mut self) -> HashMap<TrackId, NoteChanges> {
+        let mut changes: HashMap<TrackId, NoteChanges> = HashMap::new();
+        let tracks: Vec<_> = self.state.playing.keys().copied().collect();
+        for track_id in tracks {
+            self.switch(track_id, None, changes.entry(track_id).or_default());
+        }
+        self.state.queued.clear();
+        self.changed = true;
+        changes
+    }
+
+    /// process the given tick, returning the notes to stop and start on each track
+    pub fn process(&mut self, session: &Session, tick: Tick) -> HashMap<TrackId, NoteChanges> {
+        let mut changes: HashMap<TrackId, NoteChanges> = HashMap::new();
+
+        let due: Vec<_> = self
+            .state
+            .queued
+            .iter()
+            .filter(|(_, launch)| launch.at <= tick)
+            .map(|(track_id, launch)| (*track_id, launch.scene))
+            .collect();
+        for (track_id, scene) in due {
+            self.state.queued.remove(&track_id);
+            self.switch(track_id, scene, changes.entry(track_id).or_default());
+        }
+
+        let playing: Vec<_> = self
+            .state
+            .playing
+            .iter()
+            .map(|(track_id, playing)| (*track_id, *playing))
+            .collect();
+        for (track_id, mut playing) in playing {
+            let track_changes = changes.entry(track_id).or_default();
+            let mut slot = session.slot(track_id, playing.scene);
+            let duration = slot.map(|slot| slot.clip.duration.as_u64()).unwrap_or(0);
+            if duration == 0 {
+                self.switch(track_id, None, track_changes);
+                continue;
+            }
+
+            if playing.elapsed > 0 && playing.elapsed % duration == 0 {
+                playing.loops += 1;
+                self.changed = true;
+                let follow = slot.and_then(|slot| {
+                    let action = slot.follow_action?;
+                    (playing.loops >= slot.follow_after).then_some(action)
+                });
+                if let Some(action) = follow {
+                    let next = self.follow(session, track_id, playing.scene, action);
+                    self.switch(track_id, next, track_changes);
+                    let Some(next) = next else {
+                        continue;
+                    };
+                    playing = self.state.playing[&track_id];
+                    slot = session.slot(track_id, next);
+                }
+            }
+
+            if let Some(slot) = slot {
+                self.play_position(track_id, slot, playing.elapsed, track_changes);
+            }
+            playing.elapsed += 1;
+            self.state.playing.insert(track_id, playing);
+        }
+
+        changes.retain(|_, changes| !changes.is_empty());
+        changes
+    }
+
+    /// picks the scene launched by a follow action, 'None' stops the track
+    fn follow(
+        &mut self,
+        session: &Session,
+        track_id: TrackId,
+        scene: usize,
+        action: FollowAction,
+    ) -> Option<usize> {
+        let filled = session.filled_slots(track_id);
+        if filled.is_empty() {
+            return None;
+        }
+        match action {
+            FollowAction::Next => filled
+                .iter()
+                .copied()
+                .find(|filled| *filled > scene)
+                .or(filled.first().copied()),
+            FollowAction::Random => Some(filled[self.random.index(filled.len())]),
+            FollowAction::Stop => None,
+        }
+    }
+
+    /// releases the notes of a track and starts playing a new scene, or stops when 'None'
+    fn switch(&mut self, track_id: TrackId, scene: Option<usize>, changes: &mut NoteChanges) {
+        if let Some(sounding) = self.sounding.remove(&track_id) {
+            changes.offs.extend(sounding);
+        }
+        match scene {
+            Some(scene) => {
+                let playing = PlayingSlot {
+                    scene,
+                    elapsed: 0,
+                    loops: 0,
+                };
+                self.state.playing.insert(track_id, playing);
+            }
+            None => {
+                self.state.playing.remove(&track_id);
+            }
+        }
+        self.changed = true;
+    }
+
+    /// collects the notes ending and starting at the looped position of a slot
+    fn play_position(
+        &mut self,
+        track_id: TrackId,
+        slot: &ClipSlot,
+        elapsed: u64,
+        changes: &mut NoteChanges,
+    ) {
+        let clip = &slot.clip;
+        let duration = clip.duration.as_u64();
+        let position = elapsed % duration;
+        let sounding = self.sounding.entry(track_id).or_default();
+
+        // notes ending at the end of the clip are released at the start of the next loop
+        let ending = match position {
+            0 => duration - 1..duration,
+            _ => position - 1..position + 1,
+        };
+        for segment in clip
+            .events()
+            .events_in(Tick::from(ending.start)..Tick::from(ending.end))
+        {
+            let end = segment.end.as_u64().min(duration);
+            if let Some((key, _)) = Self::playable_note(segment, duration) {
+                if end % duration == position && sounding.remove(&key) {
+                    changes.offs.push(key);
+                }
+            }
+        }
+
+        for segment in clip
+            .events()
+            .events_in(Tick::from(position)..Tick::from(position + 1))
+        {
+            if let Some((key, velocity)) = Self::playable_note(segment, duration) {
+                if segment.start.as_u64() == position {
+                    sounding.insert(key);
+                    changes.ons.push((key, velocity));
+                }
+            }
+        }
+    }
+
+    /// get the key and velocity of an active note which starts before the end of the clip
+    fn playable_note(segment: &EventSegment, duration: u64) -> Option<(u8, u8)> {
+        let start = segment.start.as_u64();
+        let end = segment.end.as_u64().min(duration);
+        if !segment.is_active || start >= end {
+            return None;
+        }
+        match segment.event_type {
+            EventType::Midi(MidiMessage::NoteOn { key, velocity }) => Some((key, velocity)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hexencer_core::data::Clip;
+
+    use super::*;
+
+    #[test]
+    fn launches_are_quantized() {
+        assert_eq!(
+            LaunchQuantize::Beat.next_boundary(Tick::from(1)),
+            Tick::from(480)
+        );
+        assert_eq!(
+            LaunchQuantize::Bar.next_boundary(Tick::from(1920)),
+            Tick::from(1920)
+        );
+        assert_eq!(
+            LaunchQuantize::Immediate.next_boundary(Tick::from(7)),
+            Tick::from(7)
+        );
+    }
+
+    #[test]
+    fn follow_action_moves_to_next_slot() {
+        let mut data = hexencer_core::data::DataLayer::default();
+        data.project_manager.push_track();
+        let track_id = data.project_manager.tracks().get(0).unwrap().id;
+        let clip = Clip::new(Tick::zero(), "a", Tick::from(960));
+        data.project_manager.edit_session(|session| {
+            session.set_slot(
+                track_id,
+                0,
+                ClipSlot::new(clip.clone()).with_follow_action(FollowAction::Next, 2),
+            );
+            session.set_slot(track_id, 1, ClipSlot::new(clip));
+        });
+        let session = data.project_manager.session();
+
+        let mut launcher = ClipLauncher::new();
+        let target = LaunchTarget::Slot { track_id, scene: 0 };
+        launcher.launch(session, target, LaunchQuantize::Beat, Tick::from(10));
+
+        launcher.process(session, Tick::from(479));
+        assert!(!launcher.is_playing(track_id));
+
+        for tick in 480..480 + 960 * 2 {
+            launcher.process(session, Tick::from(tick));
+        }
+        assert_eq!(launcher.state().playing[&track_id].scene, 0);
+        assert_eq!(launcher.state().playing[&track_id].loops, 1);
+
+        let changes = launcher.process(session, Tick::from(480 + 960 * 2));
+        assert_eq!(launcher.state().playing[&track_id].scene, 1);
+        assert_eq!(launcher.state().playing[&track_id].loops, 0);
+        assert_eq!(changes[&track_id].ons, vec![(46, 64)]);
+    }
+}
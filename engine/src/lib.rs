@@ -5,13 +5,92 @@
 
 //! houses the midi engine
 
+/// tracking of sounding notes
+mod active_notes;
+/// arpeggiator processor
+pub mod arpeggiator;
+/// destinations for the messages of the midi engine
+mod backend;
+/// always on buffer of recent input which can be turned into a clip
+mod capture;
+/// restoring the channel state when playback starts mid song
+mod chase;
+/// sources of time for the sequencer
+mod clock;
+/// following an external midi clock
+mod external_sync;
+/// noticing midi outputs which disappear and return
+mod hotplug;
+/// receiving messages from midi inputs
+mod input;
+/// session clip launcher
+mod launcher;
+/// midi clock and transport messages sent to other devices
+mod midi_clock;
 /// midi engine
 pub mod midi_engine;
+/// compiled snapshots of the data layer used during playback
+mod playback;
+/// deterministic random numbers
+mod random;
+/// recording of incoming messages into takes
+mod recorder;
+/// rendering of the playback without waiting for real time
+mod render;
 /// sequencer engine
 mod sequencer;
+/// entering notes one step at a time
+mod step_input;
+/// midi time code sent to and received from other devices
+mod timecode;
 
 // pub use sequencer::start_sequencer_engine;
+pub use backend::FileBackend;
+pub use backend::MemoryBackend;
+pub use backend::MidirBackend;
+pub use backend::OutputBackend;
+pub use backend::SentMessage;
+pub use capture::Capture;
+pub use capture::CapturedEvent;
+pub use capture::CAPTURE_CAPACITY;
+pub use capture::PHRASE_GAP;
+pub use clock::Clock;
+pub use clock::RealClock;
+pub use clock::VirtualClock;
+pub use external_sync::SyncSource;
+pub use hotplug::PortState;
+pub use hotplug::PortStatus;
+pub use hotplug::RESCAN_INTERVAL;
+pub use input::start_sync_input;
+pub use input::start_virtual_input;
+pub use input::start_virtual_inputs;
+pub use input::InputEvent;
+pub use input::InputRouter;
+pub use input::MidiInputEngine;
+pub use input::TrackInput;
+pub use input::TrackInputReceiver;
+pub use input::TrackInputSender;
+pub use input::VirtualInputs;
+pub use launcher::LaunchQuantize;
+pub use launcher::LaunchTarget;
+pub use launcher::LauncherState;
+pub use launcher::PlayingSlot;
+pub use launcher::QueuedLaunch;
+pub use playback::PlaybackSnapshot;
+pub use playback::PlaybackTrack;
+pub use playback::SharedSnapshot;
+pub use playback::SnapshotPublisher;
+pub use recorder::RecordSettings;
+pub use render::render;
+pub use render::RenderSettings;
+pub use sequencer::ProjectEdit;
+pub use sequencer::ProjectEditReceiver;
+pub use sequencer::ProjectEditSender;
 pub use sequencer::Sequencer;
 pub use sequencer::SequencerCommand;
 pub use sequencer::SequencerHandle;
 pub use sequencer::SequencerSender;
+pub use sequencer::TimedMessage;
+pub use sequencer::TransportPosition;
+pub use step_input::StepEdit;
+pub use step_input::StepSettings;
@@ -0,0 +1,104 @@
+use hexencer_core::{
+    data::{MidiMessage, SyncSettings},
+    Tick, PPQN,
+};
+
+/// number of midi clock pulses per quarter note
+pub const CLOCKS_PER_BEAT: u64 = 24;
+/// number of ticks between two midi clock pulses
+pub(crate) const TICKS_PER_CLOCK: u64 = PPQN as u64 / CLOCKS_PER_BEAT;
+/// number of ticks in a midi beat, the unit of the song position pointer, a sixteenth note
+const TICKS_PER_MIDI_BEAT: u64 = PPQN as u64 / 4;
+/// highest song position which fits in a song position pointer message
+const MAX_SONG_POSITION: u64 = 0x3FFF;
+
+/// get the song position pointer of a tick, rounded down to the sixteenth note
+pub fn song_position(tick: Tick) -> MidiMessage {
+    let position = (tick.as_u64() / TICKS_PER_MIDI_BEAT).min(MAX_SONG_POSITION);
+    MidiMessage::SongPosition {
+        position: position as u16,
+    }
+}
+
+/// get the tick of a song position pointer value
+pub fn song_position_tick(position: u16) -> Tick {
+    Tick::from(position as u64 * TICKS_PER_MIDI_BEAT)
+}
+
+/// get the clock pulses due on a tick as (message, port), the offset of each output shifts its pulses
+pub fn clock_messages(sync: &SyncSettings, tick: Tick) -> Vec<(MidiMessage, u8)> {
+    sync.enabled_clock_outputs()
+        .filter(|output| {
+            let shifted = tick.as_u64() as i64 - output.offset;
+            shifted >= 0 && (shifted as u64).is_multiple_of(TICKS_PER_CLOCK)
+        })
+        .map(|output| (MidiMessage::TimingClock, output.port))
+        .collect()
+}
+
+/// get the messages which start the clock outputs from a tick, devices are told to start
+/// from the beginning or continue from the song position
+pub fn start_messages(sync: &SyncSettings, tick: Tick) -> Vec<(MidiMessage, u8)> {
+    let messages = match tick == Tick::zero() {
+        true => vec![MidiMessage::Start],
+        false => vec![song_position(tick), MidiMessage::Continue],
+    };
+    to_outputs(sync, &messages)
+}
+
+/// get the messages which stop the clock outputs
+pub fn stop_messages(sync: &SyncSettings) -> Vec<(MidiMessage, u8)> {
+    to_outputs(sync, &[MidiMessage::Stop])
+}
+
+/// get the messages which move the clock outputs to a tick, while running the devices
+/// are stopped and continued around the new song position
+pub fn locate_messages(sync: &SyncSettings, tick: Tick, running: bool) -> Vec<(MidiMessage, u8)> {
+    let messages = match running {
+        true => vec![
+            MidiMessage::Stop,
+            song_position(tick),
+            MidiMessage::Continue,
+        ],
+        false => vec![song_position(tick)],
+    };
+    to_outputs(sync, &messages)
+}
+
+/// sends each message to every enabled clock output
+fn to_outputs(sync: &SyncSettings, messages: &[MidiMessage]) -> Vec<(MidiMessage, u8)> {
+    sync.enabled_clock_outputs()
+        .flat_map(|output| messages.iter().map(|message| (*message, output.port)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use hexencer_core::data::ClockOutput;
+
+    use super::*;
+
+    #[test]
+    fn offsets_shift_the_clock_pulses() {
+        let mut sync = SyncSettings::default();
+        sync.set_clock_output(ClockOutput::new(0));
+        sync.set_clock_output(ClockOutput {
+            port: 1,
+            enabled: true,
+            offset: 5,
+        });
+
+        assert_eq!(
+            clock_messages(&sync, Tick::from(20)),
+            vec![(MidiMessage::TimingClock, 0)]
+        );
+        assert_eq!(
+            clock_messages(&sync, Tick::from(25)),
+            vec![(MidiMessage::TimingClock, 1)]
+        );
+        assert_eq!(
+            song_position(Tick::from(PPQN as u64 * 4 + 60)),
+            MidiMessage::SongPosition { position: 16 }
+        );
+    }
+}
@@ -1,98 +1,524 @@
-use hexencer_core::data::MidiMessage;
-use midir::MidiOutput;
+use std::{collections::VecDeque, sync::Arc, time::Duration};
+
+use hexencer_core::data::PortMap;
+use midir::{MidiInput, MidiInputConnection, MidiInputPort, MidiOutput};
+use thiserror::Error;
+use tokio::sync::watch;
 use tokio::task;
+use tokio::time::{self, MissedTickBehavior};
+
+use crate::hotplug::{PortChange, PortWatcher, RESCAN_INTERVAL};
+use crate::{Clock, OutputBackend, PortStatus, TimedMessage};
+
+/// sender type used to send messages to the midi engine, they are played once their time comes
+pub type MidiEngineSender = tokio::sync::mpsc::UnboundedSender<TimedMessage>;
+/// receiver type used to receive messages on the midi engine
+pub type MidiEngineReceiver = tokio::sync::mpsc::UnboundedReceiver<TimedMessage>;
+
+/// connection of a midi input which forwards its messages, the input is closed when it is dropped
+pub type InputConnection = MidiInputConnection<()>;
+
+/// number of channels on a midi port
+pub const CHANNEL_COUNT: u8 = 16;
+/// name of the midi client used for the outputs of hexencer
+pub(crate) const CLIENT_NAME: &str = "hexencer";
+
+/// error type for the midi engine
+#[derive(Error, Debug)]
+pub enum MidiEngineError {
+    /// when the midi system of the platform could not be used
+    #[error("Unable to initialize midi: {0}")]
+    Init(#[from] midir::InitError),
+    /// when no midi output with the given name exists
+    #[error("No midi output named {0}")]
+    NoOutput(String),
+    /// when no midi input with the given name exists
+    #[error("No midi input named {0}")]
+    NoInput(String),
+    /// when a midi output exists, but could not be opened
+    #[error("Unable to open midi output {name}: {reason}")]
+    Connect {
+        /// name of the output
+        name: String,
+        /// why the output could not be opened
+        reason: String,
+    },
+    /// when a message is sent to a logical port without an output
+    #[error("Port {0} has no open output")]
+    NotConnected(u8),
+    /// when sending a message to an open output failed
+    #[error("Unable to send to port {port}: {reason}")]
+    Send {
+        /// logical port id
+        port: u8,
+        /// why the message could not be sent
+        reason: String,
+    },
+    /// when virtual ports can not be created on this platform or backend
+    #[error("Virtual midi ports are not supported")]
+    VirtualUnsupported,
+    /// when reading or writing a file failed
+    #[error("Io error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// get the part of a port name which stays the same between sessions, some platforms end
+/// port names with a client and port number like ' 20:0', which may change when devices reconnect
+pub fn stable_name(name: &str) -> &str {
+    let Some((rest, address)) = name.rsplit_once(' ') else {
+        return name;
+    };
+    let is_number = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit());
+    match address.split_once(':') {
+        Some((client, port)) if is_number(client) && is_number(port) => rest,
+        _ => name,
+    }
+}
 
-/// sender type used to send commands to the midi engine
-pub type MidiEngineSender = tokio::sync::mpsc::UnboundedSender<(MidiMessage, u8, u8)>;
-/// receiver type used to receive commands on the midi engine
-pub type MidiEngineReceiver = tokio::sync::mpsc::UnboundedReceiver<(MidiMessage, u8, u8)>;
+/// get the names of the midi outputs which can be opened
+pub fn list_outputs() -> Result<Vec<String>, MidiEngineError> {
+    let midi_out = MidiOutput::new(CLIENT_NAME)?;
+    Ok(midi_out
+        .ports()
+        .iter()
+        .filter_map(|port| midi_out.port_name(port).ok())
+        .collect())
+}
+
+/// commands which change the outputs of a running midi engine
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PortCommand {
+    /// look for outputs which disappeared or returned right away
+    Rescan,
+    /// open the output called 'name' for the logical port 'id'
+    Open {
+        /// logical port id
+        id: u8,
+        /// name of the output
+        name: String,
+    },
+    /// create a virtual output called 'name' for the logical port 'id'
+    OpenVirtual {
+        /// logical port id
+        id: u8,
+        /// name of the virtual output
+        name: String,
+    },
+    /// close the output of a logical port
+    Close(u8),
+}
+
+/// sender type used to send port commands to the midi engine
+pub type PortCommandSender = tokio::sync::mpsc::UnboundedSender<PortCommand>;
+/// receiver type used to receive port commands on the midi engine
+pub type PortCommandReceiver = tokio::sync::mpsc::UnboundedReceiver<PortCommand>;
+
+/// get the names of the midi inputs which can be opened
+pub fn list_inputs() -> Result<Vec<String>, MidiEngineError> {
+    let midi_in = MidiInput::new(CLIENT_NAME)?;
+    Ok(midi_in
+        .ports()
+        .iter()
+        .filter_map(|port| midi_in.port_name(port).ok())
+        .collect())
+}
+
+/// finds the midi input called 'name', names are compared by their stable part
+pub(crate) fn find_input(
+    midi_in: &MidiInput,
+    name: &str,
+) -> Result<MidiInputPort, MidiEngineError> {
+    midi_in
+        .ports()
+        .into_iter()
+        .find(|port| {
+            midi_in
+                .port_name(port)
+                .is_ok_and(|port_name| stable_name(&port_name) == stable_name(name))
+        })
+        .ok_or_else(|| MidiEngineError::NoInput(String::from(name)))
+}
 
 /// reponsible for setting up midi connections, and sending, receiving, midi requests from them
-#[derive(Default)]
 pub struct MidiEngine {
-    /// midi output connection 1
-    conn_out: Option<midir::MidiOutputConnection>,
-    /// midi output connection 2
-    conn_out2: Option<midir::MidiOutputConnection>,
+    /// destination of the sent messages
+    backend: Box<dyn OutputBackend>,
+    /// the wanted outputs of the logical ports, used to reconnect devices which return
+    watcher: PortWatcher,
+    /// publishes the status of the logical ports whenever it changes
+    status: watch::Sender<Vec<PortStatus>>,
+    /// clock the times of the messages are measured on, the clock of the sequencer
+    clock: Arc<dyn Clock>,
+    /// messages which are not due yet, sorted by time
+    pending: VecDeque<TimedMessage>,
 }
 
 impl MidiEngine {
-    /// create a new 'MidiEngine'
-    pub fn new() -> Self {
-        let midi_out = MidiOutput::new("Test Output").unwrap();
-        let midi_out2 = MidiOutput::new("Test Output2").unwrap();
+    /// create a new 'MidiEngine' without any open outputs, sending to 'backend' at the times
+    /// of the messages measured on 'clock'
+    pub fn new(backend: impl OutputBackend + 'static, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            backend: Box::new(backend),
+            watcher: PortWatcher::default(),
+            status: watch::channel(Vec::new()).0,
+            clock,
+            pending: VecDeque::new(),
+        }
+    }
 
-        let mut con1 = None;
-        let mut con2 = None;
+    /// opens the midi output called 'name' for the logical port 'id', replacing its previous output,
+    /// names are compared by their stable part, a port which could not be opened is reconnected
+    /// as soon as its output shows up
+    pub fn open(&mut self, id: u8, name: &str) -> Result<(), MidiEngineError> {
+        self.backend.disconnect(id);
+        self.watcher.watch(id, name, false);
+        let result = self.connect(id, name);
+        self.publish_status();
+        result
+    }
 
-        let out_ports = midi_out.ports();
-        let out_ports2 = midi_out2.ports();
+    /// creates a virtual output called 'name' for the logical port 'id', replacing its previous output,
+    /// other applications can connect to it to receive the messages of the port
+    pub fn open_virtual(&mut self, id: u8, name: &str) -> Result<(), MidiEngineError> {
+        self.backend.disconnect(id);
+        self.watcher.watch(id, name, true);
+        let result = self.backend.create_virtual(id, name);
+        if result.is_ok() {
+            self.watcher.connected(id, name);
+            tracing::info!("created virtual midi output {} as port {}", name, id);
+        }
+        self.publish_status();
+        result
+    }
 
-        let port = out_ports.get(2);
-        let port2 = out_ports2.get(3);
+    /// connects the logical port 'id' to the output called 'name'
+    fn connect(&mut self, id: u8, name: &str) -> Result<(), MidiEngineError> {
+        let output = self
+            .backend
+            .outputs()?
+            .into_iter()
+            .find(|output| stable_name(output) == stable_name(name))
+            .ok_or_else(|| MidiEngineError::NoOutput(String::from(name)))?;
+        self.backend.connect(id, &output)?;
+        let dropped = self.watcher.connected(id, &output);
+        if dropped > 0 {
+            tracing::info!("dropped {} messages while port {} was missing", dropped, id);
+        }
+        tracing::info!("opened midi output {} as port {}", output, id);
+        Ok(())
+    }
 
-        if port.is_some() && port2.is_some() {
-            tracing::info!("opening midi connections");
-            let conn_out = midi_out.connect(port.unwrap(), "midir-test");
-            con1 = match conn_out {
-                Ok(conn) => Some(conn),
+    /// opens the outputs of every logical port, returning the errors of the ports which could not be opened
+    pub fn open_all(&mut self, ports: &PortMap) -> Vec<MidiEngineError> {
+        ports
+            .iter()
+            .filter_map(|port| match port.is_virtual {
+                true => self.open_virtual(port.id, &port.name).err(),
+                false => self.open(port.id, &port.name).err(),
+            })
+            .collect()
+    }
 
-                Err(_) => None,
-            };
-            let conn_out2 = midi_out2.connect(port2.unwrap(), "midir-test2");
+    /// true if the logical port has an open output
+    pub fn is_open(&self, id: u8) -> bool {
+        self.watcher.is_connected(id)
+    }
 
-            con2 = match conn_out2 {
-                Ok(conn) => Some(conn),
+    /// get a receiver for the status of the logical ports, updated when ports are opened, lost or return
+    pub fn subscribe_ports(&self) -> watch::Receiver<Vec<PortStatus>> {
+        self.status.subscribe()
+    }
 
-                Err(_) => None,
-            };
+    /// publishes the current status of the logical ports
+    fn publish_status(&self) {
+        self.status.send_replace(self.watcher.statuses());
+    }
+
+    /// closes the outputs whose device disappeared and reconnects the ports whose device returned
+    pub fn rescan(&mut self) -> Result<(), MidiEngineError> {
+        let outputs = self.backend.outputs()?;
+        let changes = self.watcher.changes(&outputs);
+        if changes.is_empty() {
+            return Ok(());
+        }
+        for change in changes {
+            match change {
+                PortChange::Lost(id) => self.lose(id),
+                PortChange::Returned(id, output) => {
+                    if let Err(error) = self.connect(id, &output) {
+                        tracing::warn!("unable to reconnect port {}: {}", id, error);
+                    }
+                }
+            }
         }
+        self.publish_status();
+        Ok(())
+    }
 
-        Self {
-            conn_out: con1,
-            conn_out2: con2,
+    /// closes the output of a port whose device is gone, the port stays watched
+    fn lose(&mut self, id: u8) {
+        self.backend.disconnect(id);
+        self.watcher.lost(id);
+        tracing::warn!(
+            "midi output {} of port {} is gone",
+            self.watcher.name(id).unwrap_or_default(),
+            id
+        );
+    }
+
+    /// queues a message until its time comes, messages which are due are played right away
+    async fn schedule(&mut self, message: TimedMessage) {
+        // messages due at the same time keep the order they were sent in
+        let index = self
+            .pending
+            .partition_point(|pending| pending.time <= message.time);
+        self.pending.insert(index, message);
+        self.play_due().await;
+    }
+
+    /// plays the queued messages which are due, the sequencer sends messages ahead of their time
+    async fn play_due(&mut self) {
+        let now = self.clock.now();
+        while self
+            .pending
+            .front()
+            .is_some_and(|message| message.time <= now)
+        {
+            if let Some(message) = self.pending.pop_front() {
+                self.play(&message).await;
+            }
+        }
+    }
+
+    /// get the time until the next queued message is due, 'None' if nothing is queued
+    fn next_due(&self) -> Option<Duration> {
+        let message = self.pending.front()?;
+        Some(message.time.saturating_sub(self.clock.now()))
+    }
+
+    /// sends a midi message to the midi port, messages for missing ports are dropped
+    async fn play(&mut self, message: &TimedMessage) {
+        let port = message.port;
+        if !self.watcher.is_connected(port) {
+            if self.watcher.drop_message(port) {
+                tracing::debug!("port {} is missing, dropping its messages", port);
+            }
+            return;
+        }
+        let bytes = message.message.to_midi(message.channel);
+        if let Err(error) = self.backend.send(port, &bytes, message.time) {
+            tracing::warn!("{}", error);
+            self.lose(port);
+            self.publish_status();
         }
     }
 
-    /// sends a midi message to the midi port
-    async fn play(&mut self, message: &MidiMessage, port: u8, channel: u8) {
-        match port {
-            0 => {
-                let _ = self
-                    .conn_out
-                    .as_mut()
-                    .map(|s| s.send(&message.to_midi(channel)));
+    /// handles a command which changes the outputs
+    fn handle_port_command(&mut self, command: PortCommand) {
+        match command {
+            PortCommand::Rescan => {
+                if let Err(error) = self.rescan() {
+                    tracing::warn!("unable to rescan midi outputs: {}", error);
+                }
             }
-            1 => {
-                let _ = self
-                    .conn_out2
-                    .as_mut()
-                    .map(|s| s.send(&message.to_midi(channel)));
+            PortCommand::Open { id, name } => {
+                if let Err(error) = self.open(id, &name) {
+                    tracing::warn!("{}", error);
+                }
             }
-            _ => {}
+            PortCommand::OpenVirtual { id, name } => {
+                if let Err(error) = self.open_virtual(id, &name) {
+                    tracing::warn!("{}", error);
+                }
+            }
+            PortCommand::Close(id) => self.close_port(id),
         }
     }
 
+    /// close the output of a logical port
+    pub fn close_port(&mut self, id: u8) {
+        self.backend.disconnect(id);
+        self.watcher.unwatch(id);
+        self.publish_status();
+    }
+
     /// close the midi connections
     pub fn close(&mut self) {
         tracing::info!("closing midi connections");
-        self.conn_out.take().map(|c| c.close());
-        tracing::info!("connection closed");
+        for status in self.watcher.statuses() {
+            self.backend.disconnect(status.id);
+        }
+        self.watcher.clear();
+        self.publish_status();
+        tracing::info!("connections closed");
     }
 
-    /// start listening and processing midi engine commands
-    pub async fn listen(mut self, mut midi_command_receiver: MidiEngineReceiver) {
+    /// start listening and processing midi engine commands, rescanning the outputs every 'RESCAN_INTERVAL',
+    /// messages are held back until they are due
+    pub async fn listen(
+        mut self,
+        mut midi_command_receiver: MidiEngineReceiver,
+        mut port_command_receiver: PortCommandReceiver,
+    ) {
         tracing::info!("running midiio");
-        while let Some((midi_message, port, channel)) = midi_command_receiver.recv().await {
-            self.play(&midi_message, port, channel).await;
+        let mut rescan = time::interval(RESCAN_INTERVAL);
+        rescan.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            let due = self.next_due();
+            tokio::select! {
+                message = midi_command_receiver.recv() => {
+                    let Some(message) = message else {
+                        break;
+                    };
+                    self.schedule(message).await;
+                }
+                _ = time::sleep(due.unwrap_or_default()), if due.is_some() => {
+                    self.play_due().await;
+                }
+                Some(command) = port_command_receiver.recv() => {
+                    self.handle_port_command(command);
+                }
+                _ = rescan.tick() => {
+                    self.handle_port_command(PortCommand::Rescan);
+                }
+            }
         }
     }
 }
 
-/// starts up the midi engine and listens for commands, return the sender to send commands to the midi engine
-pub fn start_midi_engine() -> MidiEngineSender {
+/// handle to a running midi engine
+#[derive(Debug, Clone)]
+pub struct MidiEngineHandle {
+    /// sends midi messages to the outputs
+    pub sender: MidiEngineSender,
+    /// sends commands which change the outputs
+    pub port_commands: PortCommandSender,
+    /// status of the logical ports, changes when ports are lost or return
+    pub ports: watch::Receiver<Vec<PortStatus>>,
+}
+
+/// starts up a midi engine sending to 'backend' and listens for commands, the outputs of 'ports' are
+/// opened first, ports which could not be opened are reconnected when their output shows up,
+/// 'clock' has to be the clock of the sequencer, return the handle to send commands to the midi engine
+pub fn start_midi_engine(
+    backend: impl OutputBackend + 'static,
+    ports: &PortMap,
+    clock: Arc<dyn Clock>,
+) -> MidiEngineHandle {
+    let mut midi_engine = MidiEngine::new(backend, clock);
+    for error in midi_engine.open_all(ports) {
+        tracing::warn!("{}", error);
+    }
     let (midi_sender, midi_receiver) = tokio::sync::mpsc::unbounded_channel();
-    let midi_engine = MidiEngine::new();
-    task::spawn(midi_engine.listen(midi_receiver));
-    midi_sender
+    let (port_sender, port_receiver) = tokio::sync::mpsc::unbounded_channel();
+    let ports = midi_engine.subscribe_ports();
+    task::spawn(midi_engine.listen(midi_receiver, port_receiver));
+    MidiEngineHandle {
+        sender: midi_sender,
+        port_commands: port_sender,
+        ports,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hexencer_core::{data::MidiMessage, Tick};
+
+    use super::*;
+    use crate::{FileBackend, MemoryBackend, PortState, SentMessage, VirtualClock};
+
+    /// a note on for port 0 due at 'millis'
+    fn note_at(millis: u64) -> TimedMessage {
+        TimedMessage {
+            tick: Tick::zero(),
+            time: Duration::from_millis(millis),
+            message: MidiMessage::NoteOn {
+                key: 46,
+                velocity: 64,
+            },
+            port: 0,
+            channel: 0,
+        }
+    }
+
+    #[test]
+    fn stable_names_drop_the_port_address() {
+        assert_eq!(stable_name("Synth:Synth MIDI 1 20:0"), "Synth:Synth MIDI 1");
+        assert_eq!(stable_name("Synth MIDI 1"), "Synth MIDI 1");
+        assert_eq!(stable_name("Port 2"), "Port 2");
+    }
+
+    #[tokio::test]
+    async fn messages_for_a_missing_port_are_dropped_until_it_returns() {
+        let backend = MemoryBackend::new(&["Synth 20:0"]);
+        let mut engine = MidiEngine::new(backend.clone(), Arc::new(VirtualClock::new()));
+        let ports = engine.subscribe_ports();
+        engine.open(0, "Synth 20:0").unwrap();
+        let note = note_at(0);
+
+        engine.play(&note).await;
+        backend.remove_output("Synth 20:0");
+        engine.handle_port_command(PortCommand::Rescan);
+        assert_eq!(ports.borrow()[0].state, PortState::Missing);
+        engine.play(&note).await;
+
+        backend.add_output("Synth 24:0");
+        engine.handle_port_command(PortCommand::Rescan);
+        assert_eq!(ports.borrow()[0].state, PortState::Connected);
+        engine.play(&note).await;
+
+        let outputs: Vec<String> = backend.sent().into_iter().map(|sent| sent.output).collect();
+        assert_eq!(outputs, vec!["Synth 20:0", "Synth 24:0"]);
+    }
+
+    #[test]
+    fn virtual_outputs_are_created_from_the_port_map() {
+        let backend = MemoryBackend::new(&["Synth"]);
+        let mut engine = MidiEngine::new(backend.clone(), Arc::new(VirtualClock::new()));
+        let mut ports = PortMap::default();
+        ports.assign(0, "Synth");
+        ports.assign_virtual(1, "hexencer out");
+
+        assert!(engine.open_all(&ports).is_empty());
+        assert!(engine.is_open(1));
+        engine.rescan().unwrap();
+        assert!(engine.is_open(1));
+        engine.open_virtual(1, "hexencer out").unwrap();
+        assert_eq!(
+            backend.clone().outputs().unwrap(),
+            vec![String::from("Synth"), String::from("hexencer out")]
+        );
+
+        let path =
+            std::env::temp_dir().join(format!("hexencer-virtual-{}.txt", std::process::id()));
+        let backend = FileBackend::create(&path, &[]).unwrap();
+        let mut engine = MidiEngine::new(backend, Arc::new(VirtualClock::new()));
+        assert!(matches!(
+            engine.open_virtual(1, "hexencer out"),
+            Err(MidiEngineError::VirtualUnsupported)
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn messages_are_held_back_until_they_are_due() {
+        let backend = MemoryBackend::new(&["Synth"]);
+        let clock = VirtualClock::new();
+        let mut engine = MidiEngine::new(backend.clone(), Arc::new(clock.clone()));
+        engine.open(0, "Synth").unwrap();
+
+        engine.schedule(note_at(20)).await;
+        engine.schedule(note_at(10)).await;
+        engine.schedule(note_at(0)).await;
+        let times = |sent: Vec<SentMessage>| -> Vec<_> {
+            sent.into_iter().map(|sent| sent.time.as_millis()).collect()
+        };
+        assert_eq!(times(backend.take_sent()), vec![0]);
+        assert_eq!(engine.next_due(), Some(Duration::from_millis(10)));
+
+        clock.set(Duration::from_millis(20));
+        engine.play_due().await;
+        assert_eq!(times(backend.take_sent()), vec![10, 20]);
+        assert_eq!(engine.next_due(), None);
+    }
 }
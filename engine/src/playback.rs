@@ -0,0 +1,307 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    thread,
+};
+
+use arc_swap::ArcSwap;
+use hexencer_core::{
+    data::{
+        ArpeggiatorSettings, AutomationLane, ClipId, DataLayer, InputMap, Locators,
+        MarkerCollection, MidiMessage, PortMap, Session, StorageInterface, SyncSettings, TempoMap,
+        Track,
+    },
+    event::EventType,
+    Tick, TrackId,
+};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::sequencer::NoteChanges;
+
+/// a track compiled for playback, notes are stored by the absolute tick on which they change
+#[derive(Debug, Clone)]
+pub struct PlaybackTrack {
+    /// id of the track
+    pub id: TrackId,
+    /// midi port of the track's instrument
+    pub port: u8,
+    /// midi channel of the track's instrument
+    pub channel: u8,
+    /// arpeggiator settings of the track, 'None' when disabled
+    pub arpeggiator: Option<ArpeggiatorSettings>,
+    /// automation lanes of the track
+    pub automation: Vec<AutomationLane>,
+    /// true if the track records the messages of its input
+    pub record_arm: bool,
+    /// id of the midi input the track records from, 'None' for every input
+    pub input: Option<u8>,
+    /// duration of every clip of the track by id
+    clips: HashMap<ClipId, Tick>,
+    /// notes released and started on each tick
+    notes: BTreeMap<Tick, NoteChanges>,
+    /// every note as (start, end, key, velocity), sorted by start
+    spans: Vec<(Tick, Tick, u8, u8)>,
+    /// messages other than notes sent on each tick, like control and program changes
+    messages: BTreeMap<Tick, Vec<MidiMessage>>,
+}
+
+impl PlaybackTrack {
+    /// compiles a track for playback
+    pub fn compile(track: &Track) -> Self {
+        let mut notes: BTreeMap<Tick, NoteChanges> = BTreeMap::new();
+        let mut spans = Vec::new();
+        let mut messages: BTreeMap<Tick, Vec<MidiMessage>> = BTreeMap::new();
+        for event in track.events_in(Tick::zero()..Tick::from(u64::MAX)) {
+            if !event.segment.is_active {
+                continue;
+            }
+            match event.segment.event_type {
+                EventType::Midi(MidiMessage::NoteOn { key, velocity }) => {
+                    notes.entry(event.end).or_default().offs.push(key);
+                    notes
+                        .entry(event.start)
+                        .or_default()
+                        .ons
+                        .push((key, velocity));
+                    spans.push((event.start, event.end, key, velocity));
+                }
+                EventType::Midi(MidiMessage::NoteOff { .. } | MidiMessage::AllNoteOff) => {}
+                EventType::Midi(message) => {
+                    messages.entry(event.start).or_default().push(message);
+                }
+            }
+        }
+        spans.sort_by_key(|(start, ..)| *start);
+
+        Self {
+            id: track.id,
+            port: track.instrument.port,
+            channel: track.instrument.channel,
+            arpeggiator: track.arpeggiator,
+            automation: track.automation.clone(),
+            record_arm: track.record_arm,
+            input: track.input,
+            clips: track
+                .clip_collection
+                .values()
+                .map(|clip| (clip.id, clip.duration))
+                .collect(),
+            notes,
+            spans,
+            messages,
+        }
+    }
+
+    /// get the duration of a clip of the track, 'None' if the track does not hold the clip
+    pub fn clip_duration(&self, clip_id: ClipId) -> Option<Tick> {
+        self.clips.get(&clip_id).copied()
+    }
+
+    /// get the notes released and started on the given tick
+    pub fn notes_at(&self, tick: Tick) -> Option<&NoteChanges> {
+        self.notes.get(&tick)
+    }
+
+    /// get the messages other than notes sent on the given tick
+    pub fn messages_at(&self, tick: Tick) -> &[MidiMessage] {
+        self.messages
+            .get(&tick)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// get the messages other than notes sent before the given tick, in playback order
+    pub fn messages_before(&self, tick: Tick) -> impl Iterator<Item = &MidiMessage> {
+        self.messages
+            .range(..tick)
+            .flat_map(|(_, messages)| messages)
+    }
+
+    /// get the notes as (key, velocity) which started before the given tick and are still sounding on it
+    pub fn notes_sounding_at(&self, tick: Tick) -> impl Iterator<Item = (u8, u8)> + '_ {
+        let started = self.spans.partition_point(|(start, ..)| *start < tick);
+        self.spans[..started]
+            .iter()
+            .filter(move |(_, end, ..)| *end > tick)
+            .map(|(_, _, key, velocity)| (*key, *velocity))
+    }
+}
+
+/// an immutable view of the project, compiled for playback
+#[derive(Default, Debug, Clone)]
+pub struct PlaybackSnapshot {
+    /// revision of the data this snapshot was compiled from
+    pub revision: u64,
+    /// bpm of the project
+    pub bpm: f64,
+    /// tempo of the project over time, including tempo automation
+    pub tempo_map: TempoMap,
+    /// session grid, used by the clip launcher
+    pub session: Session,
+    /// markers of the project, used to locate by name
+    pub markers: MarkerCollection,
+    /// loop and punch locators of the project
+    pub locators: Locators,
+    /// how other devices are kept in sync, like the midi clock outputs
+    pub sync: SyncSettings,
+    /// logical midi ports of the project
+    pub ports: PortMap,
+    /// midi inputs of the project
+    pub inputs: InputMap,
+    /// track selected by the user, the midi inputs are echoed to its instrument
+    pub selected_track: Option<TrackId>,
+    /// compiled tracks, in project order
+    tracks: Vec<PlaybackTrack>,
+    /// index of every track by id
+    positions: HashMap<TrackId, usize>,
+}
+
+impl PlaybackSnapshot {
+    /// compiles a snapshot of the given data, tempo lanes are sampled every 'automation_resolution' ticks
+    pub fn compile(data: &DataLayer, revision: u64, automation_resolution: Tick) -> Self {
+        let tracks: Vec<_> = data
+            .project_manager
+            .tracks()
+            .iter()
+            .map(PlaybackTrack::compile)
+            .collect();
+        let positions = tracks
+            .iter()
+            .enumerate()
+            .map(|(position, track)| (track.id, position))
+            .collect();
+
+        Self {
+            revision,
+            bpm: data.bpm(),
+            tempo_map: TempoMap::from_project(
+                &data.project_manager,
+                data.bpm(),
+                automation_resolution,
+            ),
+            session: data.project_manager.session().clone(),
+            markers: data.project_manager.markers().clone(),
+            locators: data.project_manager.locators().clone(),
+            sync: data.project_manager.sync().clone(),
+            ports: data.project_manager.ports().clone(),
+            inputs: data.project_manager.inputs().clone(),
+            selected_track: data.selected_track(),
+            tracks,
+            positions,
+        }
+    }
+
+    /// get the compiled tracks
+    pub fn tracks(&self) -> &[PlaybackTrack] {
+        &self.tracks
+    }
+
+    /// get a compiled track by id
+    pub fn track(&self, id: TrackId) -> Option<&PlaybackTrack> {
+        self.positions
+            .get(&id)
+            .map(|position| &self.tracks[*position])
+    }
+}
+
+/// snapshot shared between the publisher and the playback engine
+pub type SharedSnapshot = Arc<ArcSwap<PlaybackSnapshot>>;
+
+/// compiles and publishes a new snapshot whenever the data changes
+#[derive(Debug, Clone)]
+pub struct SnapshotPublisher {
+    /// the data which is compiled
+    storage: StorageInterface,
+    /// where new snapshots are stored
+    snapshot: SharedSnapshot,
+    /// tempo lanes are sampled every this many ticks
+    automation_resolution: Tick,
+}
+
+impl SnapshotPublisher {
+    /// creates a new publisher, compiling the first snapshot right away
+    pub fn new(storage: StorageInterface, automation_resolution: Tick) -> Self {
+        let snapshot = {
+            let data = storage.read().unwrap();
+            PlaybackSnapshot::compile(&data, storage.revision(), automation_resolution)
+        };
+        Self {
+            storage,
+            snapshot: Arc::new(ArcSwap::from_pointee(snapshot)),
+            automation_resolution,
+        }
+    }
+
+    /// get the shared snapshot
+    pub fn snapshot(&self) -> SharedSnapshot {
+        Arc::clone(&self.snapshot)
+    }
+
+    /// compiles and stores a new snapshot if the data changed since the last one
+    pub fn publish(&self) {
+        let data = self.storage.read().unwrap();
+        // the revision only changes while the data is locked for writing
+        let revision = self.storage.revision();
+        if revision != self.snapshot.load().revision {
+            let snapshot = PlaybackSnapshot::compile(&data, revision, self.automation_resolution);
+            self.snapshot.store(Arc::new(snapshot));
+        }
+    }
+
+    /// publishes on a separate thread for every change, so compiling never stalls playback,
+    /// the thread stops once nobody else holds the snapshot
+    pub fn spawn(self) -> thread::JoinHandle<()> {
+        let mut changes = self.storage.subscribe();
+        thread::spawn(move || loop {
+            self.publish();
+            match changes.blocking_recv() {
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => break,
+            }
+            if Arc::strong_count(&self.snapshot) == 1 {
+                break;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hexencer_core::data::Clip;
+
+    use super::*;
+
+    #[test]
+    fn edits_publish_a_new_snapshot() {
+        let storage = StorageInterface::from_data_layer(DataLayer::default());
+        let track_id = {
+            let mut data = storage.write().unwrap();
+            data.project_manager.push_track();
+            data.project_manager.tracks().get(0).unwrap().id
+        };
+        let publisher = SnapshotPublisher::new(storage.clone(), Tick::from(24));
+        let snapshot = publisher.snapshot();
+        let first = snapshot.load_full();
+        assert!(first
+            .track(track_id)
+            .unwrap()
+            .notes_at(Tick::zero())
+            .is_none());
+
+        let clip = Clip::new(Tick::from(960), "clip", Tick::from(1920));
+        storage.write().unwrap().add_clip(track_id, clip).unwrap();
+        publisher.publish();
+
+        let second = snapshot.load_full();
+        assert!(second.revision > first.revision);
+        let track = second.track(track_id).unwrap();
+        assert_eq!(track.notes_at(Tick::from(960)).unwrap().ons, vec![(46, 64)]);
+        assert_eq!(track.notes_at(Tick::from(1440)).unwrap().offs, vec![46]);
+        // the old snapshot is left untouched
+        assert!(first
+            .track(track_id)
+            .unwrap()
+            .notes_at(Tick::from(960))
+            .is_none());
+    }
+}
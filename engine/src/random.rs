@@ -0,0 +1,39 @@
+/// seed used when none is given, fixed so renders of the same project are identical
+const DEFAULT_SEED: u64 = 0x2545_f491_4f6c_dd1d;
+
+/// small xorshift random generator, deterministic so offline renders match playback
+#[derive(Debug, Clone)]
+pub struct Random {
+    /// current state of the generator
+    state: u64,
+}
+
+impl Default for Random {
+    fn default() -> Self {
+        Self::new(DEFAULT_SEED)
+    }
+}
+
+impl Random {
+    /// creates a generator with the given seed, a seed of 0 is replaced by the default seed
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: if seed == 0 { DEFAULT_SEED } else { seed },
+        }
+    }
+
+    /// get the next random number
+    pub fn next_u64(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.state = x;
+        x
+    }
+
+    /// get a random index below 'len', 'len' must not be 0
+    pub fn index(&mut self, len: usize) -> usize {
+        (self.next_u64() % len as u64) as usize
+    }
+}
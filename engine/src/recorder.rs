@@ -0,0 +1,303 @@
+use std::{collections::HashMap, ops::Range};
+
+use hexencer_core::{
+    data::{MidiMessage, RecordMode, RecordedEvent, Take},
+    Tick, TrackId,
+};
+
+/// how incoming messages are recorded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RecordSettings {
+    /// how a recording is combined with the clips it overlaps
+    pub mode: RecordMode,
+    /// true if every pass through the loop is kept as a separate take, otherwise passes are merged
+    pub loop_takes: bool,
+    /// grid recorded events are moved to, keeping the length of notes, 'None' keeps them as played
+    pub quantize: Option<Tick>,
+}
+
+/// a note which is held down while recording
+#[derive(Debug, Clone, Copy)]
+struct HeldNote {
+    /// tick at which the note was played
+    played: Tick,
+    /// tick the note starts at after quantizing
+    start: Tick,
+    /// the note on message
+    message: MidiMessage,
+}
+
+/// what is recorded during one pass of the transport
+#[derive(Debug)]
+struct Pass {
+    /// first tick of the pass
+    start: Tick,
+    /// last tick reached by the pass, the end of the loop after wrapping
+    end: Tick,
+    /// recorded events per track, in the order the tracks first received one
+    events: Vec<(TrackId, Vec<RecordedEvent>)>,
+}
+
+impl Pass {
+    /// creates an empty pass starting at 'start'
+    fn new(start: Tick) -> Self {
+        Self {
+            start,
+            end: start,
+            events: Vec::new(),
+        }
+    }
+
+    /// adds an event recorded onto a track
+    fn push(&mut self, track_id: TrackId, event: RecordedEvent) {
+        match self.events.iter_mut().find(|(id, _)| *id == track_id) {
+            Some((_, events)) => events.push(event),
+            None => self.events.push((track_id, vec![event])),
+        }
+    }
+
+    /// turns the pass into a take per track which received events, limited to the punch range
+    fn into_takes(self, punch: &Range<Tick>) -> Vec<Take> {
+        let start = self.start.max(punch.start);
+        let range = start..self.end.min(punch.end).max(start + Tick::from(1));
+        self.events
+            .into_iter()
+            .map(|(track_id, mut events)| {
+                events.sort_by_key(|event| event.start);
+                Take {
+                    track_id,
+                    range: range.clone(),
+                    events,
+                }
+            })
+            .collect()
+    }
+}
+
+/// turns the messages received by armed tracks into takes, pairing note ons with their note offs
+#[derive(Debug, Default)]
+pub(crate) struct Recorder {
+    /// how messages are recorded
+    settings: RecordSettings,
+    /// the pass being recorded, 'None' when not recording
+    pass: Option<Pass>,
+    /// notes held down per track and key
+    held: HashMap<(TrackId, u8), HeldNote>,
+    /// finished passes of this recording
+    takes: Vec<Take>,
+    /// tick from which messages are recorded, 'None' records from the start
+    punch_in: Option<Tick>,
+    /// tick from which no new messages are recorded, 'None' records until the end
+    punch_out: Option<Tick>,
+}
+
+impl Recorder {
+    /// get the settings used for recording
+    pub(crate) fn settings(&self) -> &RecordSettings {
+        &self.settings
+    }
+
+    /// set how the next recorded messages are recorded
+    pub(crate) fn set_settings(&mut self, settings: RecordSettings) {
+        self.settings = settings;
+    }
+
+    /// set the punch in and punch out locators, a missing locator leaves that side open
+    pub(crate) fn set_punch(&mut self, punch_in: Option<Tick>, punch_out: Option<Tick>) {
+        self.punch_in = punch_in;
+        self.punch_out = punch_out;
+    }
+
+    /// get the ticks between the punch locators, nothing new is recorded outside of them
+    fn punch(&self) -> Range<Tick> {
+        self.punch_in.unwrap_or_default()..self.punch_out.unwrap_or(Tick::from(u64::MAX))
+    }
+
+    /// true if messages are being recorded
+    pub(crate) fn is_recording(&self) -> bool {
+        self.pass.is_some()
+    }
+
+    /// starts recording at 'tick', discarding anything which was not finished
+    pub(crate) fn start(&mut self, tick: Tick) {
+        self.pass = Some(Pass::new(tick));
+        self.held.clear();
+        self.takes.clear();
+    }
+
+    /// records a message received by a track at 'tick', ignored when not recording
+    pub(crate) fn input(&mut self, track_id: TrackId, tick: Tick, message: MidiMessage) {
+        let punch = self.punch();
+        let Some(pass) = &mut self.pass else {
+            return;
+        };
+        // messages timestamped just before the pass started belong to its first tick
+        let tick = tick.max(pass.start);
+        pass.end = pass.end.max(tick);
+        // outside of the punch range only notes which are held are still released
+        let punched_in = punch.contains(&tick);
+        match message {
+            MidiMessage::NoteOn { velocity, .. } if velocity > 0 && !punched_in => {}
+            MidiMessage::NoteOn { key, velocity } if velocity > 0 => {
+                let start = quantize(tick, self.settings.quantize);
+                let note = HeldNote {
+                    played: tick,
+                    start,
+                    message,
+                };
+                // a retriggered key ends the note it was holding
+                if let Some(held) = self.held.insert((track_id, key), note) {
+                    pass.push(track_id, released(held, tick));
+                }
+            }
+            MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+                if let Some(held) = self.held.remove(&(track_id, key)) {
+                    pass.push(track_id, released(held, tick));
+                }
+            }
+            _ if !punched_in => {}
+            message => {
+                let start = quantize(tick, self.settings.quantize);
+                pass.push(
+                    track_id,
+                    RecordedEvent {
+                        start,
+                        end: start + Tick::from(1),
+                        message,
+                    },
+                );
+            }
+        }
+    }
+
+    /// called when the playhead wraps from the end of 'range' to its start, held notes end at the
+    /// end of the loop and, when keeping loop takes, the next pass starts
+    pub(crate) fn wrap(&mut self, range: Range<Tick>) {
+        let Some(mut pass) = self.pass.take() else {
+            return;
+        };
+        self.release_held(&mut pass, range.end);
+        pass.end = pass.end.max(range.end);
+        // events quantized onto the end of the loop are played at its start
+        for (_, events) in pass.events.iter_mut() {
+            for event in events.iter_mut().filter(|event| event.start >= range.end) {
+                let length = event.end - event.start;
+                event.start = range.start;
+                event.end = range.start + length;
+            }
+        }
+        match self.settings.loop_takes {
+            true => {
+                self.takes.extend(pass.into_takes(&self.punch()));
+                self.pass = Some(Pass::new(range.start));
+            }
+            false => {
+                pass.start = pass.start.min(range.start);
+                self.pass = Some(pass);
+            }
+        }
+    }
+
+    /// stops recording at 'tick', returning the takes which were recorded in the order of the passes
+    pub(crate) fn finish(&mut self, tick: Tick) -> Vec<Take> {
+        let punch = self.punch();
+        if let Some(mut pass) = self.pass.take() {
+            let tick = tick.min(punch.end).max(pass.start);
+            self.release_held(&mut pass, tick);
+            pass.end = pass.end.max(tick);
+            self.takes.extend(pass.into_takes(&punch));
+        }
+        std::mem::take(&mut self.takes)
+    }
+
+    /// ends every held note at 'tick'
+    fn release_held(&mut self, pass: &mut Pass, tick: Tick) {
+        let mut held: Vec<_> = self.held.drain().collect();
+        held.sort_by_key(|(_, note)| note.played);
+        for ((track_id, _), note) in held {
+            pass.push(track_id, released(note, tick));
+        }
+    }
+}
+
+/// the recorded event of a note released at 'tick', quantizing keeps the played length
+fn released(note: HeldNote, tick: Tick) -> RecordedEvent {
+    let length = tick.saturating_sub(note.played).max(Tick::from(1));
+    RecordedEvent {
+        start: note.start,
+        end: note.start + length,
+        message: note.message,
+    }
+}
+
+/// moves 'tick' to the nearest line of the grid
+fn quantize(tick: Tick, grid: Option<Tick>) -> Tick {
+    match grid.map(|grid| grid.as_u64()).filter(|grid| *grid > 0) {
+        Some(grid) => Tick::from((tick.as_u64() + grid / 2) / grid * grid),
+        None => tick,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pairs_notes_and_keeps_a_take_per_loop_pass() {
+        let track_id = TrackId::default();
+        let on = |key| MidiMessage::NoteOn { key, velocity: 100 };
+        let off = |key| MidiMessage::NoteOff { key, velocity: 0 };
+        let mut recorder = Recorder::default();
+        recorder.set_settings(RecordSettings {
+            loop_takes: true,
+            quantize: Some(Tick::from(120)),
+            ..RecordSettings::default()
+        });
+
+        recorder.start(Tick::from(0));
+        recorder.input(track_id, Tick::from(130), on(60));
+        recorder.input(track_id, Tick::from(370), off(60));
+        recorder.input(track_id, Tick::from(800), on(62));
+        recorder.wrap(Tick::from(0)..Tick::from(960));
+        recorder.input(track_id, Tick::from(10), on(64));
+        let takes = recorder.finish(Tick::from(50));
+
+        assert_eq!(takes.len(), 2);
+        let notes: Vec<_> = takes[0]
+            .events
+            .iter()
+            .map(|event| (event.start.as_u64(), event.end.as_u64()))
+            .collect();
+        // quantized starts keep the played length, held notes end at the loop end
+        assert_eq!(notes, vec![(120, 360), (840, 1000)]);
+        assert_eq!(takes[0].range, Tick::from(0)..Tick::from(960));
+        assert_eq!(takes[1].range, Tick::from(0)..Tick::from(50));
+        assert_eq!(takes[1].events[0].start, Tick::from(0));
+        assert!(!recorder.is_recording());
+    }
+
+    #[test]
+    fn records_only_between_the_punch_locators() {
+        let track_id = TrackId::default();
+        let on = |key| MidiMessage::NoteOn { key, velocity: 100 };
+        let off = |key| MidiMessage::NoteOff { key, velocity: 0 };
+        let mut recorder = Recorder::default();
+        recorder.set_punch(Some(Tick::from(480)), Some(Tick::from(960)));
+
+        recorder.start(Tick::from(0));
+        recorder.input(track_id, Tick::from(100), on(60));
+        recorder.input(track_id, Tick::from(200), off(60));
+        recorder.input(track_id, Tick::from(500), on(62));
+        recorder.input(track_id, Tick::from(1000), on(64));
+        let takes = recorder.finish(Tick::from(1200));
+
+        let notes: Vec<_> = takes[0]
+            .events
+            .iter()
+            .map(|event| (event.start.as_u64(), event.end.as_u64()))
+            .collect();
+        // a note held at the punch out ends there
+        assert_eq!(notes, vec![(500, 960)]);
+        assert_eq!(takes[0].range, Tick::from(480)..Tick::from(960));
+    }
+}
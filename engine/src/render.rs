@@ -0,0 +1,155 @@
+use std::{ops::Range, sync::Arc};
+
+use hexencer_core::{data::StorageInterface, Tick};
+
+use crate::{clock::VirtualClock, sequencer::TimedMessage, Sequencer};
+
+/// what part of the project to render
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenderSettings {
+    /// tick at which rendering starts
+    pub start: Tick,
+    /// tick at which rendering stops, this tick is not played
+    pub end: Tick,
+    /// range which is repeated, like the loop range during live playback
+    pub loop_range: Option<Range<Tick>>,
+    /// number of times the loop range is played before playback continues past it
+    pub loop_count: u32,
+}
+
+impl RenderSettings {
+    /// creates settings which render the given range once, without looping
+    pub fn new(range: Range<Tick>) -> Self {
+        Self {
+            start: range.start,
+            end: range.end,
+            loop_range: None,
+            loop_count: 1,
+        }
+    }
+
+    /// repeat 'range' so it is played 'count' times
+    pub fn with_loop(mut self, range: Range<Tick>, count: u32) -> Self {
+        self.loop_range = Some(range);
+        self.loop_count = count;
+        self
+    }
+}
+
+/// renders the project without waiting for real time, returning every outgoing message
+/// stamped with its tick and its time since tick zero as the sequencer stamped them, messages
+/// are produced by the same code as live playback, so they match what would be heard
+pub fn render(storage: &StorageInterface, settings: &RenderSettings) -> Vec<TimedMessage> {
+    let clock = VirtualClock::new();
+    let (midi_sender, mut midi_receiver) = tokio::sync::mpsc::unbounded_channel();
+    // no commands are sent, the render drives the sequencer directly
+    let (_command_sender, command_receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut sequencer = Sequencer::with_clock(
+        storage.clone(),
+        midi_sender,
+        command_receiver,
+        Arc::new(clock.clone()),
+    );
+
+    sequencer.locate(settings.start);
+    clock.set(sequencer.position_time());
+    // the loop of the project's locators is never used, only the loop of the settings
+    let loop_range = settings
+        .loop_range
+        .clone()
+        .filter(|_| settings.loop_count > 1);
+    sequencer.set_loop(loop_range);
+    sequencer.play();
+
+    let mut rendered = Vec::new();
+    let mut loops = 1;
+    while sequencer.position() < settings.end {
+        let tick = sequencer.position();
+        clock.set(sequencer.position_time());
+        sequencer.process_tick();
+        if sequencer.position() <= tick {
+            loops += 1;
+            if loops >= settings.loop_count {
+                sequencer.set_loop(None);
+            }
+        }
+        rendered.extend(std::iter::from_fn(|| midi_receiver.try_recv().ok()));
+    }
+
+    // release notes which are still sounding when the render ends
+    clock.set(sequencer.position_time());
+    sequencer.release_notes();
+    rendered.extend(std::iter::from_fn(|| midi_receiver.try_recv().ok()));
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use hexencer_core::data::{Clip, DataLayer, MidiMessage};
+
+    use super::*;
+
+    #[test]
+    fn renders_loops_with_their_time() {
+        let mut data = DataLayer::default();
+        data.project_manager.push_track();
+        let track_id = data.project_manager.tracks().get(0).unwrap().id;
+        data.add_clip(track_id, Clip::new(Tick::zero(), "clip", Tick::from(960)))
+            .unwrap();
+        let storage = StorageInterface::from_data_layer(data);
+
+        let settings = RenderSettings::new(Tick::zero()..Tick::from(600))
+            .with_loop(Tick::zero()..Tick::from(300), 2);
+        let rendered: Vec<_> = render(&storage, &settings)
+            .into_iter()
+            .map(|message| (message.tick.as_u64(), message.time, message.message))
+            .collect();
+
+        // at 120 bpm a tick takes 1/960th of a second
+        let at = |ticks: u64| Duration::from_secs_f64(ticks as f64 / 960.0);
+        let on = |key| MidiMessage::NoteOn { key, velocity: 64 };
+        let off = |key| MidiMessage::NoteOff { key, velocity: 0 };
+        assert_eq!(
+            rendered,
+            vec![
+                (0, at(0), on(46)),
+                (240, at(240), on(47)),
+                (300, at(300), off(46)),
+                (300, at(300), off(47)),
+                (0, at(300), on(46)),
+                (240, at(540), on(47)),
+                (480, at(780), off(46)),
+                (600, at(900), off(47)),
+            ]
+        );
+    }
+
+    #[test]
+    fn ignores_the_loop_of_the_project() {
+        let mut data = DataLayer::default();
+        data.project_manager.push_track();
+        let track_id = data.project_manager.tracks().get(0).unwrap().id;
+        data.add_clip(track_id, Clip::new(Tick::zero(), "clip", Tick::from(960)))
+            .unwrap();
+        data.project_manager.edit_locators(|locators| {
+            locators.loop_range = Some(Tick::zero()..Tick::from(300));
+            locators.loop_enabled = true;
+        });
+        let storage = StorageInterface::from_data_layer(data);
+
+        let settings = RenderSettings::new(Tick::zero()..Tick::from(600));
+        let rendered: Vec<_> = render(&storage, &settings)
+            .into_iter()
+            .map(|message| (message.tick.as_u64(), message.message))
+            .collect();
+
+        let on = |key| MidiMessage::NoteOn { key, velocity: 64 };
+        let off = |key| MidiMessage::NoteOff { key, velocity: 0 };
+        assert_eq!(
+            rendered,
+            vec![(0, on(46)), (240, on(47)), (480, off(46)), (600, off(47))]
+        );
+    }
+}
@@ -1,22 +1,148 @@
 use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
     sync::{Arc, RwLock},
     time::Duration,
 };
 
 use hexencer_core::{
-    data::{ClipKey, StorageInterface},
-    Tick,
+    data::{
+        AutomationTarget, AutomationValue, DataLayer, MarkerCollection, MidiMessage, RecordMode,
+        StorageInterface, Take, TempoMap,
+    },
+    Tick, TrackId,
 };
-use tokio::time;
-use tracing::info;
+use tokio::{sync::watch, time};
 
-use crate::midi_engine::MidiEngineSender;
+use crate::{
+    active_notes::ActiveNotes,
+    arpeggiator::Arpeggiator,
+    chase::ChannelState,
+    clock::{Clock, RealClock},
+    external_sync::{ClockFollower, SyncSource},
+    input::{TrackInput, TrackInputReceiver},
+    launcher::{ClipLauncher, LaunchQuantize, LaunchTarget, LauncherState},
+    midi_clock,
+    midi_engine::{MidiEngineSender, CHANNEL_COUNT},
+    playback::{SharedSnapshot, SnapshotPublisher},
+    recorder::{RecordSettings, Recorder},
+    step_input::{StepEdit, StepInput, StepSettings},
+    timecode::{self, TimecodeFollower},
+};
 
 /// used to send a command to a 'Sequencer'
 pub type SequencerSender = tokio::sync::mpsc::UnboundedSender<SequencerCommand>;
 /// used to receive a command by a 'Sequencer'
 pub type SequencerReceiver = tokio::sync::mpsc::UnboundedReceiver<SequencerCommand>;
 
+/// used to send the edits of the sequencer to the editor
+pub type ProjectEditSender = tokio::sync::mpsc::UnboundedSender<ProjectEdit>;
+/// used by the editor to receive the edits of the sequencer
+pub type ProjectEditReceiver = tokio::sync::mpsc::UnboundedReceiver<ProjectEdit>;
+
+/// longest time the sequencer sleeps before checking for due ticks and changed data
+const MAX_WAIT: Duration = Duration::from_millis(10);
+/// how far ahead of the clock ticks are played, the midi engine holds their messages until
+/// they are due, two wakeups so a late wakeup still sends messages in time
+const LOOKAHEAD: Duration = Duration::from_millis(20);
+
+/// a message produced by playback, stamped with the tick and time at which it is due
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimedMessage {
+    /// tick which produced the message
+    pub tick: Tick,
+    /// time at which the message is due, measured on the sequencer's clock
+    pub time: Duration,
+    /// the message to send
+    pub message: MidiMessage,
+    /// midi port to send the message to
+    pub port: u8,
+    /// midi channel to send the message on
+    pub channel: u8,
+}
+
+/// a change to the project made by the sequencer, applied by the editor so playback never
+/// waits on the data layer
+#[derive(Debug, Clone)]
+pub enum ProjectEdit {
+    /// takes recorded in one go, added as a single undo step
+    Recording {
+        /// the recorded takes, in the order of the passes
+        takes: Vec<Take>,
+        /// how the takes are combined with the clips they overlap
+        mode: RecordMode,
+    },
+    /// a step entered by step input
+    Step(StepEdit),
+    /// a new loop range for the locators of the project, 'None' disables looping
+    Loop(Option<Range<Tick>>),
+}
+
+impl ProjectEdit {
+    /// applies the edit to the project of 'data'
+    pub fn apply(self, data: &mut DataLayer) {
+        match self {
+            ProjectEdit::Recording { takes, mode } => {
+                data.project_manager.record_takes(takes, mode);
+            }
+            ProjectEdit::Step(step) => {
+                let clip_id = step.clip_id;
+                data.project_manager
+                    .edit_events(clip_id, |events| step.apply(events));
+            }
+            ProjectEdit::Loop(range) => {
+                data.project_manager
+                    .edit_locators(|locators| locators.set_loop(range));
+            }
+        }
+    }
+}
+
+/// position of the transport, published by the sequencer whenever it changes
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct TransportPosition {
+    /// true if the transport is running
+    pub running: bool,
+    /// tick under the playhead
+    pub tick: Tick,
+    /// time at which 'tick' is due, measured on the sequencer's clock
+    pub time: Duration,
+}
+
+impl TransportPosition {
+    /// get the tick due at 'time' while the transport runs, following 'tempo_map'
+    pub fn tick_at(&self, time: Duration, tempo_map: &TempoMap) -> Tick {
+        let offset = match time < self.time {
+            true => -(self.time - time).as_secs_f64(),
+            false => (time - self.time).as_secs_f64(),
+        };
+        let seconds = tempo_map.seconds_at(self.tick) + offset;
+        tempo_map.tick_at(seconds.max(0.0))
+    }
+}
+
+/// notes to release and start on a track during a single tick
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct NoteChanges {
+    /// keys of the notes to release
+    pub offs: Vec<u8>,
+    /// notes to start as (key, velocity)
+    pub ons: Vec<(u8, u8)>,
+}
+
+impl NoteChanges {
+    /// true if there are no notes to release or start
+    pub fn is_empty(&self) -> bool {
+        self.offs.is_empty() && self.ons.is_empty()
+    }
+
+    /// adds the changes of 'other', keeping note offs before note ons
+    pub fn append(&mut self, other: NoteChanges) {
+        self.offs.extend(other.offs);
+        self.ons.extend(other.ons);
+    }
+}
+
 /// possible 'Sequencer' commands
 pub enum SequencerCommand {
     /// start playing the sequencer
@@ -27,6 +153,46 @@ pub enum SequencerCommand {
     Reset,
     /// pause the sequencer
     Pause,
+    /// silence every channel of every port, for stuck notes or runaway controllers
+    Panic,
+    /// move the playhead to a tick, releasing the notes which are sounding
+    Locate(Tick),
+    /// move the playhead to the first marker with the given name
+    LocateMarker(String),
+    /// move the playhead to the first marker after it
+    NextMarker,
+    /// move the playhead to the last marker before it
+    PreviousMarker,
+    /// set whether notes sounding at the playhead are retriggered when playback starts or jumps
+    SetNoteChase(bool),
+    /// set where the tempo and transport are taken from
+    SetSyncSource(SyncSource),
+    /// a clock or transport message received on a midi input, followed when it is the sync source
+    ExternalSync {
+        /// input port the message was received on
+        port: u8,
+        /// the received message
+        message: MidiMessage,
+    },
+    /// a message received on a midi input echoed to the instrument of the selected track,
+    /// the notes it sounds are released with the notes of the sequencer
+    Thru(TimedMessage),
+    /// set the range which is repeated during playback in the locators of the project,
+    /// 'None' disables looping
+    SetLoop(Option<Range<Tick>>),
+    /// set whether messages received by armed tracks are recorded while the transport runs
+    Record(bool),
+    /// set how messages are recorded
+    SetRecordSettings(RecordSettings),
+    /// enter the notes received while stopped one step at a time into a clip, 'None' disables step input
+    SetStepInput(Option<StepSettings>),
+    /// launch a clip or scene from the session grid
+    Launch {
+        /// what to launch
+        target: LaunchTarget,
+        /// moment at which the launch takes effect
+        quantize: LaunchQuantize,
+    },
 }
 
 /// hold this to interact with the sequencer
@@ -36,22 +202,72 @@ pub struct SequencerHandle {
     pub state: Arc<RwLock<SequencerState>>,
     /// used to send commands to the sequencer
     pub command_sender: SequencerSender,
+    /// position of the playhead, changes whenever the sequencer moves it
+    pub position: watch::Receiver<Tick>,
 }
 
 /// the 'Sequencer' keep track of the tick and processes events ensuring they are sent to the right engine
 #[derive(Debug)]
 pub struct Sequencer {
-    /// state of the sequencer, housing current tick etc
+    /// state of the sequencer, a copy of 'transport' shared with the gui
     pub state: Arc<RwLock<SequencerState>>,
-    /// the data layer, used to store and retreive projects, etc
-    storage: StorageInterface,
+    /// state of the sequencer as seen by the playback loop, only ever touched by the sequencer
+    transport: SequencerState,
+    /// true if 'state' could not be updated because it was locked
+    state_stale: bool,
+    /// publishes the position of the playhead
+    position: watch::Sender<Tick>,
+    /// publishes the position of the transport with the time it is due, used to timestamp input
+    transport_position: watch::Sender<TransportPosition>,
+    /// compiles the data layer into snapshots
+    publisher: SnapshotPublisher,
+    /// immutable view of the data layer used during playback, never blocks on editor locks
+    snapshot: SharedSnapshot,
     /// use this to send commands to the midi engine, like playing a note
     midi_engine_sender: MidiEngineSender,
     /// this is used to receive any commands for the sequencer to process
     command_receiver: SequencerReceiver,
+    /// receives the messages of armed tracks, 'None' until an input router is connected
+    input_receiver: Option<TrackInputReceiver>,
+    /// turns the messages of armed tracks into takes
+    recorder: Recorder,
+    /// sends the recordings and entered steps to the editor, 'None' until an editor is connected
+    edit_sender: Option<ProjectEditSender>,
+    /// enters the messages received while stopped into a clip, 'None' when step input is disabled
+    step_input: Option<StepInput>,
+    /// runtime state of the arpeggiators of tracks which have one enabled
+    arpeggiators: HashMap<TrackId, Arpeggiator>,
+    /// tracks on which a clip launched from the session took over from the arrangement
+    launched_tracks: HashSet<TrackId>,
+    /// last value sent by each automation target, used to skip duplicate values
+    automation_values: HashMap<(TrackId, AutomationTarget), AutomationValue>,
+    /// notes which are sounding, so they can be released when needed
+    active_notes: ActiveNotes,
+    /// plays the clips launched from the session grid
+    launcher: ClipLauncher,
+    /// follows the external clock, 'None' when following the tempo map
+    follower: Option<ClockFollower>,
+    /// follows incoming midi time code, 'None' when not chasing timecode
+    timecode: Option<TimecodeFollower>,
+    /// source of time used to decide which ticks are due
+    clock: Arc<dyn Clock>,
+    /// tempo map of the snapshot used to convert ticks to time
+    tempo_map: TempoMap,
+    /// revision of the snapshot 'tempo_map' was taken from
+    tempo_revision: u64,
+    /// tick from which the time of later ticks is measured
+    anchor_tick: Tick,
+    /// time at which 'anchor_tick' is due
+    anchor_time: Duration,
+    /// time of the latest tick which was played, ticks are played ahead of the clock
+    played_until: Duration,
+    /// loop range used instead of the locators of the project while rendering,
+    /// 'Some(None)' disables looping
+    loop_override: Option<Option<Range<Tick>>>,
 }
 
-#[derive(Debug)]
+/// state of the 'Sequencer', shared with the gui
+#[derive(Debug, Clone)]
 pub struct SequencerState {
     /// true if the sequencer is running
     running: bool,
@@ -59,6 +275,24 @@ pub struct SequencerState {
     pub current_tick: Tick,
     /// parts per quarter note, how many ticks per beat
     ppqn: u32,
+    /// automation lanes are sampled every this many ticks, fixed when the sequencer is created
+    automation_resolution: Tick,
+    /// range which is repeated during playback, taken from the locators of the project
+    loop_range: Option<Range<Tick>>,
+    /// true if notes which started before the playhead are retriggered when playback starts or jumps
+    pub chase_notes: bool,
+    /// clips playing and queued in the session grid
+    pub launcher: LauncherState,
+    /// where the tempo and transport are taken from
+    pub sync_source: SyncSource,
+    /// tempo estimated from the external clock, when following one
+    pub external_bpm: Option<f64>,
+    /// true if messages received by armed tracks are recorded while the transport runs
+    pub recording: bool,
+    /// how messages are recorded
+    pub record_settings: RecordSettings,
+    /// position in the clip at which step input enters the next step, 'None' when disabled
+    pub step_cursor: Option<Tick>,
 }
 
 impl SequencerState {
@@ -68,8 +302,27 @@ impl SequencerState {
             running: false,
             current_tick: Tick::zero(),
             ppqn: 480,
+            automation_resolution: Tick::from(24),
+            loop_range: None,
+            chase_notes: true,
+            launcher: LauncherState::default(),
+            sync_source: SyncSource::Internal,
+            external_bpm: None,
+            recording: false,
+            record_settings: RecordSettings::default(),
+            step_cursor: None,
         }
     }
+
+    /// get the range which is repeated during playback
+    pub fn loop_range(&self) -> Option<Range<Tick>> {
+        self.loop_range.clone()
+    }
+
+    /// get the number of ticks automation lanes are sampled at
+    pub fn automation_resolution(&self) -> Tick {
+        self.automation_resolution
+    }
 }
 
 impl Default for SequencerState {
@@ -79,71 +332,595 @@ impl Default for SequencerState {
 }
 
 impl Sequencer {
-    /// creates a new 'Sequencer'
+    /// creates a new 'Sequencer' following wall time
     pub fn new(
         storage: StorageInterface,
         midi_engine_sender: MidiEngineSender,
         command_receiver: SequencerReceiver,
     ) -> Self {
+        let clock = Arc::new(RealClock::new());
+        Self::with_clock(storage, midi_engine_sender, command_receiver, clock)
+    }
+
+    /// creates a new 'Sequencer' which runs against the given clock
+    pub fn with_clock(
+        storage: StorageInterface,
+        midi_engine_sender: MidiEngineSender,
+        command_receiver: SequencerReceiver,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        let transport = SequencerState::new();
+        let publisher = SnapshotPublisher::new(storage, transport.automation_resolution);
+        let snapshot = publisher.snapshot();
+        let (tempo_map, tempo_revision) = {
+            let snapshot = snapshot.load();
+            (snapshot.tempo_map.clone(), snapshot.revision)
+        };
         Self {
             state: Arc::new(RwLock::new(SequencerState::new())),
-            storage,
+            transport,
+            state_stale: false,
+            position: watch::Sender::new(Tick::zero()),
+            transport_position: watch::Sender::new(TransportPosition::default()),
+            snapshot,
+            publisher,
             midi_engine_sender,
             command_receiver,
+            input_receiver: None,
+            recorder: Recorder::default(),
+            edit_sender: None,
+            step_input: None,
+            arpeggiators: HashMap::new(),
+            launched_tracks: HashSet::new(),
+            automation_values: HashMap::new(),
+            active_notes: ActiveNotes::new(),
+            launcher: ClipLauncher::new(),
+            follower: None,
+            timecode: None,
+            clock,
+            tempo_map,
+            tempo_revision,
+            anchor_tick: Tick::zero(),
+            anchor_time: Duration::ZERO,
+            played_until: Duration::ZERO,
+            loop_override: None,
         }
     }
 
     /// run the sequencer
     pub async fn run(mut self) {
-        let mut interval = time::interval(Duration::from_micros(self.tick_duration()));
+        let _publisher = self.publisher.clone().spawn();
 
         loop {
+            let wait = self.next_wakeup().saturating_sub(self.now());
             tokio::select! {
-                _ = interval.tick() => {
-                    self.tick().await;
+                _ = time::sleep(wait) => {
+                    self.update();
                 }
                 Some(command) = self.command_receiver.recv() => {
-                    self.handle_command(command).await;
+                    self.handle_command(command);
+                }
+                Some(input) = recv_input(&mut self.input_receiver) => {
+                    self.record_input(input);
                 }
             }
+            self.publish_state();
+        }
+    }
+
+    /// get the snapshot of the data used during playback, kept up to date while the sequencer runs
+    pub fn snapshot(&self) -> SharedSnapshot {
+        Arc::clone(&self.snapshot)
+    }
+
+    /// get the clock of the sequencer, so other parts of the engine can timestamp on the same clock
+    pub fn clock(&self) -> Arc<dyn Clock> {
+        Arc::clone(&self.clock)
+    }
+
+    /// records the messages routed to armed tracks by an 'InputRouter' from now on
+    pub fn record_from(&mut self, input_receiver: TrackInputReceiver) {
+        self.input_receiver = Some(input_receiver);
+    }
+
+    /// sends the recordings, entered steps and loop changes to the editor through 'edit_sender'
+    /// from now on, the editor applies them to the project
+    pub fn send_edits_to(&mut self, edit_sender: ProjectEditSender) {
+        self.edit_sender = Some(edit_sender);
+    }
+
+    /// get a receiver which is notified whenever the playhead moves
+    pub fn subscribe_position(&self) -> watch::Receiver<Tick> {
+        self.position.subscribe()
+    }
+
+    /// get a receiver of the position of the transport, which tells the tick of a time while it runs
+    pub fn subscribe_transport(&self) -> watch::Receiver<TransportPosition> {
+        self.transport_position.subscribe()
+    }
+
+    /// handles all commands which were sent since the last call, without waiting for new ones
+    pub fn process_commands(&mut self) {
+        // messages received before a command were played before it, like a stop
+        while let Some(input) = self
+            .input_receiver
+            .as_mut()
+            .and_then(|receiver| receiver.try_recv().ok())
+        {
+            self.record_input(input);
+        }
+        while let Ok(command) = self.command_receiver.try_recv() {
+            self.handle_command(command);
         }
+        self.publish_state();
+    }
+
+    /// processes every tick which is due within the lookahead of the current time of the clock
+    pub fn update(&mut self) {
+        self.process_until(self.now());
+        self.publish_state();
     }
 
-    /// handles ticking of the sequencer
-    async fn tick(&mut self) {
-        if self.state.read().unwrap().running {
-            self.process_events();
-            let mut state = self.state.write().unwrap();
-            state.current_tick.tick();
+    /// get the current time of the sequencer's clock
+    fn now(&self) -> Duration {
+        self.clock.now()
+    }
+
+    /// get the time at which the sequencer should wake up to process the next ticks, the ticks
+    /// due before then were already played ahead
+    fn next_wakeup(&self) -> Duration {
+        let latest = self.now() + MAX_WAIT;
+        match self.transport.running && self.may_advance() {
+            true => self
+                .time_of(self.transport.current_tick)
+                .saturating_sub(LOOKAHEAD)
+                .clamp(self.now(), latest),
+            false => latest,
+        }
+    }
+
+    /// get the time to stamp messages sent outside of a tick with, never before the ticks
+    /// which were played ahead so they keep their order in the midi engine
+    fn send_time(&self) -> Duration {
+        self.now().max(self.played_until)
+    }
+
+    /// get the time at which a tick is due, following the tempo map
+    fn time_of(&self, tick: Tick) -> Duration {
+        let seconds = self.tempo_map.seconds_at(tick) - self.tempo_map.seconds_at(self.anchor_tick);
+        let offset = Duration::from_secs_f64(seconds.abs());
+        match seconds < 0.0 {
+            true => self.anchor_time.saturating_sub(offset),
+            false => self.anchor_time + offset,
+        }
+    }
+
+    /// get the tick which is due at 'time', the inverse of 'time_of'
+    fn tick_at_time(&self, time: Duration) -> Tick {
+        let anchor = self.tempo_map.seconds_at(self.anchor_tick);
+        let offset = match time < self.anchor_time {
+            true => -(self.anchor_time - time).as_secs_f64(),
+            false => (time - self.anchor_time).as_secs_f64(),
+        };
+        self.tempo_map.tick_at((anchor + offset).max(0.0))
+    }
+
+    /// measure the time of ticks from 'tick', which is due at 'time'
+    fn anchor(&mut self, time: Duration, tick: Tick) {
+        self.anchor_time = time;
+        self.anchor_tick = tick;
+    }
+
+    /// takes the tempo map of a newer snapshot, keeping the time of the current tick
+    fn sync_tempo(&mut self) {
+        // the tempo of an external clock replaces the tempo map
+        if self.follower.is_some() {
+            return;
+        }
+        let snapshot = self.snapshot.load();
+        if snapshot.revision == self.tempo_revision {
+            return;
+        }
+        self.tempo_revision = snapshot.revision;
+        if snapshot.tempo_map != self.tempo_map {
+            let tick = self.transport.current_tick;
+            let time = self.time_of(tick);
+            self.tempo_map = snapshot.tempo_map.clone();
+            self.anchor(time, tick);
+        }
+    }
+
+    /// processes every tick which is due before 'now' and the lookahead in one batch
+    fn process_until(&mut self, now: Duration) {
+        if !self.transport.running {
+            return;
+        }
+        let clock_stopped = self
+            .follower
+            .as_ref()
+            .is_some_and(|follower| follower.timed_out(now));
+        let timecode_stopped = self
+            .timecode
+            .as_ref()
+            .is_some_and(|follower| follower.timed_out(now));
+        if clock_stopped || timecode_stopped {
+            tracing::warn!("external sync stopped, pausing playback");
+            self.pause();
+            return;
+        }
+        self.sync_tempo();
+        let until = now + LOOKAHEAD;
+        while self.time_of(self.transport.current_tick) <= until && self.may_advance() {
+            self.process_tick();
+        }
+    }
+
+    /// true if the playhead may advance, when following an external clock it waits for its pulses
+    fn may_advance(&self) -> bool {
+        self.follower
+            .as_ref()
+            .map(ClockFollower::can_advance)
+            .unwrap_or(true)
+    }
+
+    /// processes the current tick and moves the playhead to the next one, wrapping at the loop end
+    pub(crate) fn process_tick(&mut self) {
+        let tick = self.transport.current_tick;
+        let time = self.time_of(tick);
+        self.played_until = time;
+        for message in self.process_events(tick, time) {
+            self.send_timed(message);
+        }
+        self.publish_launcher_state();
+
+        let loop_range = self.loop_range();
+        let transport = &mut self.transport;
+        transport.current_tick.tick();
+        transport.loop_range = loop_range.clone();
+        let wrapped = match loop_range {
+            Some(range) if transport.current_tick >= range.end => {
+                transport.current_tick = range.start;
+                Some(range)
+            }
+            _ => None,
+        };
+        if let Some(range) = wrapped {
+            let time = self.time_of(range.end);
+            self.played_until = time;
+            self.anchor(time, range.start);
+            self.recorder.wrap(range.clone());
+            // the notes end with the loop
+            self.release_notes_at(range.end, time);
+            self.launcher.relocate(range.start);
+            let sync = self.snapshot.load().sync.clone();
+            self.send_sync(midi_clock::locate_messages(&sync, range.start, true));
+            let full_frame = timecode::full_frame_messages(&sync, &self.tempo_map, range.start);
+            self.send_sync(full_frame);
+        }
+        if let Some(follower) = &mut self.follower {
+            follower.advance();
+        }
+        self.state_stale = true;
+        self.position.send_replace(self.transport.current_tick);
+    }
+
+    /// get the position of the playhead
+    pub(crate) fn position(&self) -> Tick {
+        self.transport.current_tick
+    }
+
+    /// get the time at which the tick under the playhead is due
+    pub(crate) fn position_time(&self) -> Duration {
+        self.time_of(self.transport.current_tick)
+    }
+
+    /// moves the playhead to 'tick', releasing sounding notes, playback continues from 'tick' right away,
+    /// while stopped the time of ticks keeps following the tempo map
+    pub(crate) fn locate(&mut self, tick: Tick) {
+        self.release_notes();
+        // a jump ends the recording, it continues from the new position
+        let recording = self.recorder.is_recording();
+        self.commit_recording();
+        self.sync_tempo();
+        // while running the new position follows the ticks which were played ahead
+        let time = match self.transport.running {
+            true => self.send_time(),
+            false => self.time_of(tick),
+        };
+        self.anchor(time, tick);
+        self.transport.current_tick = tick;
+        self.launcher.relocate(tick);
+        if let Some(follower) = &mut self.follower {
+            follower.reset();
+        }
+        // resend the automation values of the new position
+        self.automation_values.clear();
+        self.state_stale = true;
+        self.position.send_replace(tick);
+        let sync = self.snapshot.load().sync.clone();
+        self.send_sync(midi_clock::locate_messages(
+            &sync,
+            tick,
+            self.transport.running,
+        ));
+        self.send_sync(timecode::full_frame_messages(&sync, &self.tempo_map, tick));
+        if self.transport.running {
+            self.chase();
+        }
+        if recording {
+            self.recorder.start(tick);
+        }
+    }
+
+    /// sends sync messages as (message, port) to their ports
+    fn send_sync(&mut self, messages: Vec<(MidiMessage, u8)>) {
+        for (message, port) in messages {
+            self.send(message, port, 0);
+        }
+    }
+
+    /// restores the controller values of every track at the playhead, and when enabled
+    /// retriggers the notes which started before it and are still sounding
+    fn chase(&mut self) {
+        let tick = self.transport.current_tick;
+        let snapshot = self.snapshot.load_full();
+        let mut outgoing = Vec::new();
+        for track in snapshot.tracks() {
+            // clips launched from the session replace the arrangement
+            if self.launcher.is_playing(track.id) {
+                continue;
+            }
+            let (port, channel) = (track.port, track.channel);
+
+            let mut state = ChannelState::new();
+            for message in track.messages_before(tick) {
+                state.apply(message);
+            }
+            for lane in track.automation.iter().filter(|lane| lane.is_active) {
+                let Some(value) = lane.sample(tick) else {
+                    continue;
+                };
+                self.automation_values
+                    .insert((track.id, lane.target), value);
+                if let AutomationValue::Midi(message) = value {
+                    state.apply(&message);
+                }
+            }
+            for message in state.messages() {
+                outgoing.push((message, port, channel));
+            }
+
+            if !self.transport.chase_notes {
+                continue;
+            }
+            match track.arpeggiator {
+                Some(settings) => {
+                    let arpeggiator = self
+                        .arpeggiators
+                        .entry(track.id)
+                        .or_insert_with(|| Arpeggiator::new(settings));
+                    for (key, velocity) in track.notes_sounding_at(tick) {
+                        arpeggiator.note_on(key, velocity);
+                    }
+                }
+                None => {
+                    for (key, velocity) in track.notes_sounding_at(tick) {
+                        outgoing.push((MidiMessage::NoteOn { key, velocity }, port, channel));
+                    }
+                }
+            }
+        }
+        for (message, port, channel) in outgoing {
+            self.send(message, port, channel);
+        }
+    }
+
+    /// moves the playhead to the marker picked from the markers of the project, if there is one
+    fn locate_marker(&mut self, pick: impl FnOnce(&MarkerCollection, Tick) -> Option<Tick>) {
+        let tick = {
+            let snapshot = self.snapshot.load();
+            pick(&snapshot.markers, self.transport.current_tick)
+        };
+        if let Some(tick) = tick {
+            self.locate(tick);
+        }
+    }
+
+    /// copies the transport to the shared state, skipped when the state is locked by a reader
+    fn publish_state(&mut self) {
+        let position = TransportPosition {
+            running: self.transport.running,
+            tick: self.transport.current_tick,
+            time: self.time_of(self.transport.current_tick),
+        };
+        self.transport_position.send_if_modified(|current| {
+            let changed = *current != position;
+            *current = position;
+            changed
+        });
+        if !self.state_stale {
+            return;
+        }
+        if let Ok(mut state) = self.state.try_write() {
+            state.clone_from(&self.transport);
+            self.state_stale = false;
         }
     }
 
     /// handles processing of commands
-    async fn handle_command(&mut self, command: SequencerCommand) {
+    fn handle_command(&mut self, command: SequencerCommand) {
         match command {
             SequencerCommand::Play => {
-                self.play().await;
+                // an external clock or timecode starts playback by itself
+                if self.transport.sync_source == SyncSource::Internal {
+                    self.play();
+                }
             }
             SequencerCommand::Stop => {
-                self.stop().await;
+                self.stop();
             }
             SequencerCommand::Reset => {
-                self.reset().await;
+                self.reset();
             }
             SequencerCommand::Pause => {
-                self.pause().await;
+                self.pause();
+            }
+            SequencerCommand::Panic => {
+                self.panic();
+            }
+            SequencerCommand::Locate(tick) => {
+                self.locate(tick);
+            }
+            SequencerCommand::LocateMarker(name) => {
+                self.locate_marker(|markers, _| markers.find(&name).map(|marker| marker.tick));
+            }
+            SequencerCommand::NextMarker => {
+                self.locate_marker(|markers, tick| markers.next(tick).map(|marker| marker.tick));
+            }
+            SequencerCommand::PreviousMarker => {
+                self.locate_marker(|markers, tick| {
+                    markers.previous(tick).map(|marker| marker.tick)
+                });
+            }
+            SequencerCommand::SetNoteChase(enabled) => {
+                self.transport.chase_notes = enabled;
+                self.state_stale = true;
+            }
+            SequencerCommand::SetSyncSource(source) => {
+                self.set_sync_source(source);
+            }
+            SequencerCommand::ExternalSync { port, message } => {
+                self.external_sync(port, message);
+            }
+            SequencerCommand::Thru(message) => {
+                self.send_timed(message);
+            }
+            SequencerCommand::SetLoop(range) => {
+                // the locators of the project stay the only loop, playback follows once they change
+                self.send_edit(ProjectEdit::Loop(range));
+            }
+            SequencerCommand::Record(enabled) => {
+                self.set_recording(enabled);
+            }
+            SequencerCommand::SetRecordSettings(settings) => {
+                self.recorder.set_settings(settings);
+                self.transport.record_settings = settings;
+                self.state_stale = true;
+            }
+            SequencerCommand::SetStepInput(settings) => {
+                self.set_step_input(settings);
+            }
+            SequencerCommand::Launch { target, quantize } => {
+                self.launch(target, quantize);
+            }
+        }
+    }
+
+    /// enables step input with the given settings, or disables it
+    fn set_step_input(&mut self, settings: Option<StepSettings>) {
+        self.step_input = match (self.step_input.take(), settings) {
+            (Some(mut step_input), Some(settings)) => {
+                step_input.set_settings(settings);
+                Some(step_input)
+            }
+            (None, Some(settings)) => Some(StepInput::new(settings)),
+            (_, None) => None,
+        };
+        self.transport.step_cursor = self.step_input.as_ref().map(StepInput::cursor);
+        self.state_stale = true;
+    }
+
+    /// enters a message received while stopped into the clip of the step input, only messages
+    /// of the track holding the clip are used, the editor adds the entered notes to the clip
+    fn enter_step(&mut self, input: TrackInput) {
+        let Some(step_input) = &mut self.step_input else {
+            return;
+        };
+        let clip_id = step_input.settings().clip_id;
+        let Some(duration) = self
+            .snapshot
+            .load()
+            .track(input.track_id)
+            .and_then(|track| track.clip_duration(clip_id))
+        else {
+            return;
+        };
+        let Some(step) = step_input.input(input.event.message) else {
+            return;
+        };
+        // steps past the end of the clip would never be heard
+        if step_input.cursor() >= duration {
+            tracing::info!("step input reached the end of the clip");
+            return;
+        }
+        let edit = step_input.apply(step);
+        self.transport.step_cursor = Some(step_input.cursor());
+        self.state_stale = true;
+        if let Some(edit) = edit {
+            self.send_edit(ProjectEdit::Step(edit));
+        }
+    }
+
+    /// enables or disables recording, disabling it adds what was recorded to the project
+    fn set_recording(&mut self, enabled: bool) {
+        self.transport.recording = enabled;
+        self.state_stale = true;
+        match enabled {
+            true if self.transport.running && !self.recorder.is_recording() => {
+                self.recorder.start(self.transport.current_tick);
             }
+            true => {}
+            false => self.commit_recording(),
+        }
+    }
+
+    /// records a message received by an armed track at the tick due when it was received
+    fn record_input(&mut self, input: TrackInput) {
+        if !self.transport.running {
+            self.enter_step(input);
+            return;
+        }
+        if !self.recorder.is_recording() {
+            return;
         }
+        // the playhead is brought up to date so a wrap of the loop is seen first
+        self.process_until(self.now());
+        let tick = self.tick_at_time(input.event.time);
+        self.punch();
+        self.recorder
+            .input(input.track_id, tick, input.event.message);
     }
 
-    /// calculate the duration of a tick
-    fn tick_duration(&self) -> u64 {
-        let state = self.state.read().unwrap();
-        let storage = self.storage.read().unwrap();
-        let bpm = storage.bpm();
-        let beat_duration = 60.0 / bpm;
-        let tick_duration = (beat_duration / state.ppqn as f64) * 1000.0;
-        (tick_duration * 1000.0) as u64
+    /// records only between the punch locators of the project
+    fn punch(&mut self) {
+        let locators = &self.snapshot.load().locators;
+        self.recorder
+            .set_punch(locators.punch_in, locators.punch_out);
+    }
+
+    /// stops recording and sends the recorded takes to the editor, which adds them as one undo step
+    fn commit_recording(&mut self) {
+        if !self.recorder.is_recording() {
+            return;
+        }
+        self.punch();
+        let takes = self.recorder.finish(self.transport.current_tick);
+        if takes.is_empty() {
+            return;
+        }
+        let mode = self.recorder.settings().mode;
+        self.send_edit(ProjectEdit::Recording { takes, mode });
+    }
+
+    /// sends an edit to the editor
+    fn send_edit(&self, edit: ProjectEdit) {
+        let sent = self
+            .edit_sender
+            .as_ref()
+            .is_some_and(|sender| sender.send(edit).is_ok());
+        if !sent {
+            tracing::error!("could not send an edit, no editor is connected");
+        }
     }
 
     // /// starts listening for and processing commands
@@ -184,63 +961,426 @@ impl Sequencer {
     //     }
     // }
 
-    /// sends stop signals to both midi ports
-    async fn stop(&mut self) {
-        let mut state = self.state.write().unwrap();
-        state.running = false;
+    /// switch between the tempo map, an external clock and incoming timecode
+    fn set_sync_source(&mut self, source: SyncSource) {
+        self.transport.sync_source = source;
+        self.transport.external_bpm = None;
+        self.state_stale = true;
+        self.follower = match source {
+            SyncSource::External { port } => Some(ClockFollower::new(port)),
+            _ => None,
+        };
+        self.timecode = match source {
+            SyncSource::Timecode { port, offset } => Some(TimecodeFollower::new(port, offset)),
+            _ => None,
+        };
+        if self.follower.is_none() {
+            // take the tempo map of the project again
+            self.tempo_revision = u64::MAX;
+            self.sync_tempo();
+        }
+    }
+
+    /// follows a clock, transport or timecode message received on a midi input
+    fn external_sync(&mut self, port: u8, message: MidiMessage) {
+        if self.timecode.as_ref().map(TimecodeFollower::port) == Some(port) {
+            self.follow_timecode(message);
+            return;
+        }
+        if self.follower.as_ref().map(ClockFollower::port) != Some(port) {
+            return;
+        }
+        match message {
+            MidiMessage::TimingClock => self.external_pulse(),
+            MidiMessage::Start => {
+                self.locate(Tick::zero());
+                self.play();
+            }
+            MidiMessage::Continue => self.play(),
+            MidiMessage::Stop => self.pause(),
+            // the song position is only valid while stopped
+            MidiMessage::SongPosition { position } if !self.transport.running => {
+                self.locate(midi_clock::song_position_tick(position));
+            }
+            _ => {}
+        }
+    }
+
+    /// moves the playhead along with a pulse of the external clock, using the smoothed
+    /// tempo of the clock until the next pulse arrives
+    fn external_pulse(&mut self) {
+        let now = self.now();
+        let running = self.transport.running;
+        let Some(follower) = &mut self.follower else {
+            return;
+        };
+        let behind = follower.pulse(now, running);
+        if let Some(bpm) = follower.bpm() {
+            self.tempo_map = TempoMap::new(bpm);
+            self.transport.external_bpm = Some(bpm);
+            self.state_stale = true;
+        }
+        if running {
+            // the pulse marks the moment the tick after the ones still to be played is due
+            self.anchor(now, self.transport.current_tick + Tick::from(behind));
+            self.process_until(now);
+        }
+    }
+
+    /// locates to incoming timecode, starting playback when it runs and correcting
+    /// the playhead when it drifts away from it
+    fn follow_timecode(&mut self, message: MidiMessage) {
+        let now = self.now();
+        let Some(follower) = &mut self.timecode else {
+            return;
+        };
+        let Some(seconds) = follower.receive(&message, now) else {
+            return;
+        };
+        let tick = self.tempo_map.tick_at(seconds);
+        // a full frame is a jump of the position, it does not start playback
+        if matches!(message, MidiMessage::FullFrame(_)) || !self.transport.running {
+            self.locate(tick);
+        }
+        if matches!(message, MidiMessage::QuarterFrame { .. }) {
+            match self.transport.running {
+                true => {
+                    // the playhead runs ahead of the clock, the tick due now is compared
+                    let playing = self.tick_at_time(now);
+                    let drift = seconds - self.tempo_map.seconds_at(playing);
+                    match drift.abs() > timecode::MAX_DRIFT.as_secs_f64() {
+                        true => self.locate(tick),
+                        false => self.anchor(now, tick),
+                    }
+                }
+                false => self.play(),
+            }
+        }
+    }
+
+    /// waits for new pulses and timecode from the external sync source, used when playback stops
+    fn reset_followers(&mut self) {
+        if let Some(follower) = &mut self.follower {
+            follower.reset();
+        }
+        if let Some(follower) = &mut self.timecode {
+            follower.reset();
+        }
+    }
+
+    /// stops the sequencer, releasing all sounding notes
+    fn stop(&mut self) {
+        self.commit_recording();
+        self.transport.running = false;
+        self.state_stale = true;
+        let sync = self.snapshot.load().sync.clone();
+        self.send_sync(midi_clock::stop_messages(&sync));
+        self.reset_followers();
+        self.stop_session();
+        self.release_notes();
     }
 
     /// start playing the sequencer
-    async fn play(&mut self) {
-        let mut state = self.state.write().unwrap();
-        state.running = true;
-    }
-
-    /// process events at the current tick, sending them to the midi engine
-    fn process_events(&mut self) {
-        let storage = self.storage.read().unwrap();
-        let state = self.state.read().unwrap();
-        let tracks = &storage.project_manager.track_collection;
-        for track in tracks.iter() {
-            let clip_key = ClipKey {
-                start: state.current_tick,
-            };
+    pub(crate) fn play(&mut self) {
+        if self.transport.running {
+            return;
+        }
+        // resend the current automation values when playback starts
+        self.automation_values.clear();
+        self.sync_tempo();
+        self.anchor(self.send_time(), self.transport.current_tick);
+        self.transport.running = true;
+        self.state_stale = true;
+        let sync = self.snapshot.load().sync.clone();
+        self.send_sync(midi_clock::start_messages(
+            &sync,
+            self.transport.current_tick,
+        ));
+        let tick = self.transport.current_tick;
+        self.send_sync(timecode::full_frame_messages(&sync, &self.tempo_map, tick));
+        self.chase();
+        if self.transport.recording {
+            self.recorder.start(tick);
+        }
+    }
+
+    /// process the events of a tick, returning the messages it produced
+    fn process_events(&mut self, tick: Tick, time: Duration) -> Vec<TimedMessage> {
+        let resolution = self.transport.automation_resolution;
+        let sample_automation = tick.as_u64().is_multiple_of(resolution.as_u64().max(1));
+        let mut outgoing = Vec::new();
+        {
+            let snapshot = self.snapshot.load_full();
+            for (message, port) in midi_clock::clock_messages(&snapshot.sync, tick) {
+                outgoing.push((message, port, 0));
+            }
+            let quarter_frames =
+                timecode::quarter_frame_messages(&snapshot.sync, &self.tempo_map, tick);
+            for (message, port) in quarter_frames {
+                outgoing.push((message, port, 0));
+            }
+            let mut session_changes = self.launcher.process(&snapshot.session, tick);
+            for track in snapshot.tracks() {
+                let port = track.port;
+                let channel = track.channel;
+                // clips launched from the session take over from the arrangement
+                let mut changes = match self.launcher.is_playing(track.id) {
+                    true if self.launched_tracks.insert(track.id) => {
+                        // arrangement notes which are still sounding are released
+                        let mut offs: Vec<_> =
+                            track.notes_sounding_at(tick).map(|(key, _)| key).collect();
+                        offs.extend(
+                            track
+                                .notes_at(tick)
+                                .into_iter()
+                                .flat_map(|notes| notes.offs.clone()),
+                        );
+                        NoteChanges {
+                            offs,
+                            ons: Vec::new(),
+                        }
+                    }
+                    true => NoteChanges::default(),
+                    false => {
+                        self.launched_tracks.remove(&track.id);
+                        for message in track.messages_at(tick) {
+                            outgoing.push((*message, port, channel));
+                        }
+                        track.notes_at(tick).cloned().unwrap_or_default()
+                    }
+                };
+                if let Some(session) = session_changes.remove(&track.id) {
+                    changes.append(session);
+                }
 
-            if let Some(clip) = track.clip_collection.get(&clip_key) {
-                for (tick, event_segments) in clip.events.iter() {
-                    if *tick == state.current_tick {
-                        for segment in event_segments {
-                            info!("process event {:?}", segment);
+                if sample_automation {
+                    for lane in track.automation.iter().filter(|lane| lane.is_active) {
+                        let Some(value) = lane.sample(tick) else {
+                            continue;
+                        };
+                        let previous = self
+                            .automation_values
+                            .insert((track.id, lane.target), value);
+                        if previous == Some(value) {
+                            continue;
+                        }
+                        match value {
+                            AutomationValue::Midi(message) => {
+                                outgoing.push((message, port, channel))
+                            }
+                            // tempo lanes are followed through the tempo map
+                            AutomationValue::Tempo(_) => {}
                         }
                     }
                 }
+
+                match track.arpeggiator {
+                    Some(settings) => {
+                        let arpeggiator = self
+                            .arpeggiators
+                            .entry(track.id)
+                            .or_insert_with(|| Arpeggiator::new(settings));
+                        if *arpeggiator.settings() != settings {
+                            arpeggiator.set_settings(settings);
+                        }
+                        for key in changes.offs {
+                            arpeggiator.note_off(key);
+                        }
+                        for (key, velocity) in changes.ons {
+                            arpeggiator.note_on(key, velocity);
+                        }
+                        for message in arpeggiator.process(tick) {
+                            outgoing.push((message, port, channel));
+                        }
+                    }
+                    None => {
+                        if let Some(mut arpeggiator) = self.arpeggiators.remove(&track.id) {
+                            if let Some(message) = arpeggiator.reset() {
+                                outgoing.push((message, port, channel));
+                            }
+                        }
+                        for key in changes.offs {
+                            let message = MidiMessage::NoteOff { key, velocity: 0 };
+                            outgoing.push((message, port, channel));
+                        }
+                        for (key, velocity) in changes.ons {
+                            outgoing.push((MidiMessage::NoteOn { key, velocity }, port, channel));
+                        }
+                    }
+                }
+            }
+        }
+
+        outgoing
+            .into_iter()
+            .map(|(message, port, channel)| TimedMessage {
+                tick,
+                time,
+                message,
+                port,
+                channel,
+            })
+            .collect()
+    }
+
+    /// sends a message to the midi engine to be played right after the messages already sent,
+    /// skipping note offs for notes which are not sounding
+    fn send(&mut self, message: MidiMessage, port: u8, channel: u8) {
+        self.send_timed(TimedMessage {
+            tick: self.transport.current_tick,
+            time: self.send_time(),
+            message,
+            port,
+            channel,
+        });
+    }
+
+    /// sends a message to the midi engine to be played at its time,
+    /// skipping note offs for notes which are not sounding
+    fn send_timed(&mut self, message: TimedMessage) {
+        if self
+            .active_notes
+            .track(&message.message, message.port, message.channel)
+        {
+            let _ = self.midi_engine_sender.send(message);
+        }
+    }
+
+    /// sends note offs for all sounding notes, including those played by arpeggiators
+    pub(crate) fn release_notes(&mut self) {
+        self.release_notes_at(self.transport.current_tick, self.send_time());
+    }
+
+    /// sends note offs for all sounding notes, stamped with 'tick' and 'time'
+    fn release_notes_at(&mut self, tick: Tick, time: Duration) {
+        self.reset_arpeggiators();
+        for (message, port, channel) in self.active_notes.release_all() {
+            let _ = self.midi_engine_sender.send(TimedMessage {
+                tick,
+                time,
+                message,
+                port,
+                channel,
+            });
+        }
+    }
+
+    /// get the range which is repeated, the active loop of the project's locators
+    /// unless a render replaced it
+    fn loop_range(&self) -> Option<Range<Tick>> {
+        match &self.loop_override {
+            Some(range) => range.clone(),
+            None => self.snapshot.load().locators.active_loop(),
+        }
+    }
+
+    /// repeat 'range' instead of the loop of the project's locators, used when rendering,
+    /// empty ranges disable looping
+    pub(crate) fn set_loop(&mut self, range: Option<Range<Tick>>) {
+        let range = range.filter(|range| range.start < range.end);
+        if let Some(range) = range.clone() {
+            if self.transport.current_tick >= range.end {
+                self.locate(range.start);
+            }
+        }
+        self.loop_override = Some(range);
+        self.state_stale = true;
+    }
+
+    /// resets all arpeggiators, sending note offs for notes they left sounding
+    fn reset_arpeggiators(&mut self) {
+        let mut outgoing = Vec::new();
+        let snapshot = self.snapshot.load_full();
+        for (track_id, arpeggiator) in self.arpeggiators.iter_mut() {
+            let Some(message) = arpeggiator.reset() else {
+                continue;
+            };
+            if let Some(track) = snapshot.track(*track_id) {
+                outgoing.push((message, track.port, track.channel));
+            }
+        }
+        for (message, port, channel) in outgoing {
+            self.send(message, port, channel);
+        }
+    }
+
+    /// stops all clips launched from the session, releasing their notes
+    fn stop_session(&mut self) {
+        let changes = self.launcher.stop_all();
+        self.send_note_changes(changes);
+        self.publish_launcher_state();
+    }
+
+    /// sends the note offs of note changes per track, used when clips stop outside of a tick
+    fn send_note_changes(&mut self, changes: HashMap<TrackId, NoteChanges>) {
+        let mut outgoing = Vec::new();
+        let snapshot = self.snapshot.load_full();
+        for (track_id, changes) in changes {
+            if let Some(track) = snapshot.track(track_id) {
+                for key in changes.offs {
+                    let message = MidiMessage::NoteOff { key, velocity: 0 };
+                    outgoing.push((message, track.port, track.channel));
+                }
             }
-            // for event in event_entry.iter() {
-            //     let event_type = event.event_type;
-            //     tracing::info!("{} - {}", track, event_type);
+        }
+        for (message, port, channel) in outgoing {
+            self.send(message, port, channel);
+        }
+    }
+
+    /// queue a clip or scene launch from the session grid
+    fn launch(&mut self, target: LaunchTarget, quantize: LaunchQuantize) {
+        let tick = self.transport.current_tick;
+        let snapshot = self.snapshot.load_full();
+        self.launcher
+            .launch(&snapshot.session, target, quantize, tick);
+        self.publish_launcher_state();
+    }
 
-            //     if event.is_active {
-            //         let message = event_type.get_message();
-            //         let instrument = &track.instrument;
-            //         self.midi_engine_sender.as_mut().map(|sender| {
-            //             sender.send((message, instrument.port, instrument.channel))
-            //         });
-            //     }
-            // }
+    /// copies the launcher state to the sequencer state if it changed
+    fn publish_launcher_state(&mut self) {
+        if let Some(launcher) = self.launcher.take_changed() {
+            self.transport.launcher = launcher;
+            self.state_stale = true;
         }
     }
 
     /// reset the sequencer
-    async fn reset(&self) {
-        let mut state = self.state.write().unwrap();
-        state.current_tick = 0.into();
-        state.running = false;
+    fn reset(&mut self) {
+        self.stop();
+        self.locate(Tick::zero());
     }
 
-    /// pause the sequencer
-    async fn pause(&self) {
-        let mut state = self.state.write().unwrap();
-        state.running = false;
+    /// pause the sequencer, releasing all sounding notes
+    fn pause(&mut self) {
+        self.commit_recording();
+        self.transport.running = false;
+        self.state_stale = true;
+        let sync = self.snapshot.load().sync.clone();
+        self.send_sync(midi_clock::stop_messages(&sync));
+        self.reset_followers();
+        self.release_notes();
+    }
+
+    /// releases the sounding notes, then silences and resets every channel of every port
+    fn panic(&mut self) {
+        self.release_notes();
+        let snapshot = self.snapshot.load_full();
+        for port in snapshot.ports.iter().map(|port| port.id) {
+            for channel in 0..CHANNEL_COUNT {
+                for message in MidiMessage::panic() {
+                    self.send(message, port, channel);
+                }
+            }
+        }
+    }
+}
+
+/// waits for the next message of an armed track, forever when no input router is connected
+async fn recv_input(receiver: &mut Option<TrackInputReceiver>) -> Option<TrackInput> {
+    match receiver {
+        Some(receiver) => receiver.recv().await,
+        None => std::future::pending().await,
     }
 }
 
@@ -254,3 +1394,562 @@ impl Sequencer {
 //     task::spawn(sequencer.listen(sequencer_receiver));
 //     sequencer_sender
 // }
+
+#[cfg(test)]
+mod tests {
+    use hexencer_core::{
+        data::{
+            event_list::{EventCollection, EventSegment},
+            Clip, ClipSlot, ClockOutput, DataId, DataLayer, FrameRate, Marker, Timecode,
+        },
+        event::EventType,
+    };
+
+    use super::*;
+    use crate::{clock::VirtualClock, InputEvent};
+
+    /// messages sent to the midi engine as (message, port, channel)
+    type Sent = Vec<(MidiMessage, u8, u8)>;
+
+    /// creates a sequencer of 'storage' on a virtual clock, returning it with its clock, the sender of
+    /// its commands and a function taking the messages it sent
+    fn test_sequencer(
+        storage: StorageInterface,
+    ) -> (
+        Sequencer,
+        VirtualClock,
+        SequencerSender,
+        impl FnMut() -> Sent,
+    ) {
+        let clock = VirtualClock::new();
+        let (midi_sender, mut midi_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let (command_sender, command_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let sequencer = Sequencer::with_clock(
+            storage,
+            midi_sender,
+            command_receiver,
+            Arc::new(clock.clone()),
+        );
+        let received = move || {
+            std::iter::from_fn(|| midi_receiver.try_recv().ok())
+                .map(|sent: TimedMessage| (sent.message, sent.port, sent.channel))
+                .collect()
+        };
+        (sequencer, clock, command_sender, received)
+    }
+
+    #[test]
+    fn plays_notes_when_their_time_comes() {
+        let mut data = DataLayer::default();
+        data.project_manager.push_track();
+        let track_id = data.project_manager.tracks().get(0).unwrap().id;
+        data.add_clip(track_id, Clip::new(Tick::zero(), "clip", Tick::from(960)))
+            .unwrap();
+        let storage = StorageInterface::from_data_layer(data);
+
+        let (mut sequencer, clock, command_sender, mut received) = test_sequencer(storage);
+
+        command_sender.send(SequencerCommand::Play).unwrap();
+        sequencer.process_commands();
+        sequencer.update();
+        let on = |key| (MidiMessage::NoteOn { key, velocity: 64 }, 0, 0);
+        let off = |key| (MidiMessage::NoteOff { key, velocity: 0 }, 0, 0);
+        assert_eq!(received(), vec![on(46)]);
+
+        // at 120 bpm a tick takes 1/960th of a second, 300ms covers tick 240,
+        // the ticks due within the lookahead of 20ms are played ahead
+        clock.advance(Duration::from_millis(300));
+        sequencer.update();
+        assert_eq!(received(), vec![on(47)]);
+        assert_eq!(
+            sequencer.state.read().unwrap().current_tick,
+            Tick::from(308)
+        );
+
+        clock.advance(Duration::from_millis(700));
+        sequencer.update();
+        assert_eq!(received(), vec![off(46), off(47)]);
+    }
+
+    #[test]
+    fn a_stop_follows_the_notes_played_ahead() {
+        let mut data = DataLayer::default();
+        data.project_manager.push_track();
+        let track_id = data.project_manager.tracks().get(0).unwrap().id;
+        data.add_clip(track_id, Clip::new(Tick::zero(), "clip", Tick::from(960)))
+            .unwrap();
+        let storage = StorageInterface::from_data_layer(data);
+        let clock = VirtualClock::new();
+        let (midi_sender, mut midi_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let (command_sender, command_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let mut sequencer = Sequencer::with_clock(
+            storage,
+            midi_sender,
+            command_receiver,
+            Arc::new(clock.clone()),
+        );
+
+        // the note at tick 240 is due at 250ms, it is sent early with its own time
+        command_sender.send(SequencerCommand::Play).unwrap();
+        sequencer.process_commands();
+        clock.set(Duration::from_millis(235));
+        sequencer.update();
+        command_sender.send(SequencerCommand::Stop).unwrap();
+        sequencer.process_commands();
+        let sent: Vec<_> = std::iter::from_fn(|| midi_receiver.try_recv().ok())
+            .map(|sent| (sent.message, sent.time))
+            .collect();
+        let on = |key| MidiMessage::NoteOn { key, velocity: 64 };
+        let off = |key| MidiMessage::NoteOff { key, velocity: 0 };
+        // the ticks up to 255ms were played, the last one is tick 244
+        let played = Duration::from_millis(250);
+        let stopped = Duration::from_secs_f64(244.0 / 960.0);
+        assert_eq!(
+            sent,
+            vec![
+                (on(46), Duration::ZERO),
+                (on(47), played),
+                (off(46), stopped),
+                (off(47), stopped),
+            ]
+        );
+    }
+
+    #[test]
+    fn locate_releases_notes_and_publishes_the_position() {
+        let mut data = DataLayer::default();
+        data.project_manager.push_track();
+        let track_id = data.project_manager.tracks().get(0).unwrap().id;
+        data.add_clip(track_id, Clip::new(Tick::zero(), "clip", Tick::from(1920)))
+            .unwrap();
+        data.project_manager
+            .edit_markers(|markers| markers.add(Marker::new("end", Tick::from(960))));
+        let storage = StorageInterface::from_data_layer(data);
+
+        let (mut sequencer, clock, command_sender, mut received) = test_sequencer(storage);
+        let position = sequencer.subscribe_position();
+        let on = |key| (MidiMessage::NoteOn { key, velocity: 64 }, 0, 0);
+        let off = |key| (MidiMessage::NoteOff { key, velocity: 0 }, 0, 0);
+
+        command_sender.send(SequencerCommand::Play).unwrap();
+        sequencer.process_commands();
+        clock.advance(Duration::from_millis(100));
+        sequencer.update();
+        assert_eq!(received(), vec![on(46)]);
+        assert_eq!(*position.borrow(), Tick::from(116));
+
+        command_sender
+            .send(SequencerCommand::LocateMarker(String::from("end")))
+            .unwrap();
+        sequencer.process_commands();
+        assert_eq!(received(), vec![off(46)]);
+        assert_eq!(*position.borrow(), Tick::from(960));
+
+        // playback continues from the marker right away
+        sequencer.update();
+        assert_eq!(received(), vec![on(50)]);
+        assert_eq!(*position.borrow(), Tick::from(961));
+    }
+
+    #[test]
+    fn loops_between_the_locators_of_the_project() {
+        let mut data = DataLayer::default();
+        data.project_manager.push_track();
+        let track_id = data.project_manager.tracks().get(0).unwrap().id;
+        data.add_clip(track_id, Clip::new(Tick::zero(), "clip", Tick::from(1920)))
+            .unwrap();
+        data.project_manager.edit_locators(|locators| {
+            locators.loop_range = Some(Tick::zero()..Tick::from(300));
+            locators.loop_enabled = true;
+        });
+        let storage = StorageInterface::from_data_layer(data);
+
+        let (mut sequencer, clock, command_sender, mut received) = test_sequencer(storage.clone());
+        let (edit_sender, mut edit_receiver) = tokio::sync::mpsc::unbounded_channel();
+        sequencer.send_edits_to(edit_sender);
+        let on = |key| (MidiMessage::NoteOn { key, velocity: 64 }, 0, 0);
+        let off = |key| (MidiMessage::NoteOff { key, velocity: 0 }, 0, 0);
+
+        command_sender.send(SequencerCommand::Play).unwrap();
+        sequencer.process_commands();
+        clock.advance(Duration::from_millis(400));
+        sequencer.update();
+        assert_eq!(received(), vec![on(46), on(47), off(46), off(47), on(46)]);
+        assert!(sequencer.position() < Tick::from(300));
+
+        // disabling the loop writes the locators of the project, playback then runs past its end
+        command_sender
+            .send(SequencerCommand::SetLoop(None))
+            .unwrap();
+        sequencer.process_commands();
+        let edit = edit_receiver.try_recv().unwrap();
+        edit.apply(&mut storage.write().unwrap());
+        let locators = storage.read().unwrap().project_manager.locators().clone();
+        assert!(!locators.loop_enabled);
+        assert_eq!(locators.loop_range, Some(Tick::zero()..Tick::from(300)));
+        sequencer.publisher.publish();
+        clock.advance(Duration::from_millis(400));
+        sequencer.update();
+        assert_eq!(received(), vec![on(47), off(46)]);
+        assert!(sequencer.position() > Tick::from(300));
+    }
+
+    #[test]
+    fn launched_clips_release_the_notes_of_the_arrangement() {
+        let mut data = DataLayer::default();
+        data.project_manager.push_track();
+        let track_id = data.project_manager.tracks().get(0).unwrap().id;
+        data.add_clip(track_id, Clip::new(Tick::zero(), "clip", Tick::from(1920)))
+            .unwrap();
+        let mut events = EventCollection::new();
+        events.add_event(
+            Tick::zero(),
+            EventSegment::new2(Tick::zero(), Tick::from(480), 60, 64, true),
+        );
+        let launched = Clip::with_events(Tick::zero(), "launched", Tick::from(960), events);
+        data.project_manager
+            .edit_session(|session| session.set_slot(track_id, 0, ClipSlot::new(launched)));
+        let storage = StorageInterface::from_data_layer(data);
+
+        let (mut sequencer, clock, command_sender, mut received) = test_sequencer(storage);
+        let on = |key| (MidiMessage::NoteOn { key, velocity: 64 }, 0, 0);
+        let off = |key| (MidiMessage::NoteOff { key, velocity: 0 }, 0, 0);
+
+        command_sender.send(SequencerCommand::Play).unwrap();
+        sequencer.process_commands();
+        clock.advance(Duration::from_millis(300));
+        sequencer.update();
+        assert_eq!(received(), vec![on(46), on(47)]);
+
+        command_sender
+            .send(SequencerCommand::Launch {
+                target: LaunchTarget::Slot { track_id, scene: 0 },
+                quantize: LaunchQuantize::Immediate,
+            })
+            .unwrap();
+        sequencer.process_commands();
+        clock.advance(Duration::from_millis(10));
+        sequencer.update();
+        assert_eq!(received(), vec![off(46), off(47), on(60)]);
+    }
+
+    #[test]
+    fn playing_mid_song_chases_controllers_and_notes() {
+        let mut data = DataLayer::default();
+        data.project_manager.push_track();
+        let track_id = data.project_manager.tracks().get(0).unwrap().id;
+        let clip = Clip::new(Tick::zero(), "clip", Tick::from(1920));
+        let clip_id = clip.id;
+        data.add_clip(track_id, clip).unwrap();
+        let volume = MidiMessage::ControlChange {
+            controller: 7,
+            value: 90,
+        };
+        data.project_manager.edit_events(clip_id, |events| {
+            let segment = EventSegment {
+                id: DataId::new(),
+                start: Tick::from(100),
+                end: Tick::from(101),
+                event_type: EventType::Midi(volume),
+                is_active: true,
+            };
+            events.add_event(Tick::from(100), segment);
+        });
+        let storage = StorageInterface::from_data_layer(data);
+
+        let (mut sequencer, _, command_sender, mut received) = test_sequencer(storage);
+        let on = |key| (MidiMessage::NoteOn { key, velocity: 64 }, 0, 0);
+
+        command_sender
+            .send(SequencerCommand::Locate(Tick::from(300)))
+            .unwrap();
+        command_sender.send(SequencerCommand::Play).unwrap();
+        sequencer.process_commands();
+        assert_eq!(received(), vec![(volume, 0, 0), on(46), on(47)]);
+
+        // without note chase only the controllers are restored
+        command_sender
+            .send(SequencerCommand::SetNoteChase(false))
+            .unwrap();
+        command_sender
+            .send(SequencerCommand::Locate(Tick::from(250)))
+            .unwrap();
+        sequencer.process_commands();
+        let off = |key| (MidiMessage::NoteOff { key, velocity: 0 }, 0, 0);
+        assert_eq!(received(), vec![off(46), off(47), (volume, 0, 0)]);
+    }
+
+    #[test]
+    fn stop_releases_sequenced_and_echoed_notes_and_panic_silences_every_channel() {
+        let mut data = DataLayer::default();
+        data.project_manager.push_track();
+        let track_id = data.project_manager.tracks().get(0).unwrap().id;
+        data.add_clip(track_id, Clip::new(Tick::zero(), "clip", Tick::from(960)))
+            .unwrap();
+        data.project_manager.edit_ports(|ports| {
+            ports.assign(0, "synth");
+            ports.assign(3, "drums");
+        });
+        let storage = StorageInterface::from_data_layer(data);
+
+        let (mut sequencer, clock, command_sender, mut received) = test_sequencer(storage);
+
+        command_sender.send(SequencerCommand::Play).unwrap();
+        sequencer.process_commands();
+        clock.advance(Duration::from_millis(300));
+        sequencer.update();
+        assert_eq!(received().len(), 2);
+        // notes echoed from an input are released with the others
+        let thru = MidiMessage::NoteOn {
+            key: 60,
+            velocity: 100,
+        };
+        command_sender
+            .send(SequencerCommand::Thru(TimedMessage {
+                tick: Tick::zero(),
+                time: Duration::ZERO,
+                message: thru,
+                port: 0,
+                channel: 2,
+            }))
+            .unwrap();
+        sequencer.process_commands();
+        assert_eq!(received(), vec![(thru, 0, 2)]);
+
+        command_sender.send(SequencerCommand::Stop).unwrap();
+        sequencer.process_commands();
+        let off = |key, channel| (MidiMessage::NoteOff { key, velocity: 0 }, 0, channel);
+        assert_eq!(received(), vec![off(46, 0), off(47, 0), off(60, 2)]);
+
+        command_sender.send(SequencerCommand::Panic).unwrap();
+        sequencer.process_commands();
+        let panic = received();
+        assert_eq!(panic.len(), 2 * CHANNEL_COUNT as usize * 3);
+        assert_eq!(
+            panic.last(),
+            Some(&(MidiMessage::AllNoteOff, 3, CHANNEL_COUNT - 1))
+        );
+    }
+
+    #[test]
+    fn clock_outputs_follow_the_transport() {
+        let mut data = DataLayer::default();
+        data.project_manager
+            .edit_sync(|sync| sync.set_clock_output(ClockOutput::new(1)));
+        let storage = StorageInterface::from_data_layer(data);
+
+        let (mut sequencer, clock, command_sender, mut sent) = test_sequencer(storage);
+        let mut received = move || {
+            sent()
+                .into_iter()
+                .map(|(message, port, _)| (message, port))
+                .collect::<Vec<_>>()
+        };
+
+        command_sender.send(SequencerCommand::Play).unwrap();
+        sequencer.process_commands();
+        // at 120 bpm a clock pulse is due every 1/48th of a second
+        clock.set(Duration::from_secs_f64(1.0 / 48.0));
+        sequencer.update();
+        assert_eq!(
+            received(),
+            vec![
+                (MidiMessage::Start, 1),
+                (MidiMessage::TimingClock, 1),
+                (MidiMessage::TimingClock, 1),
+            ]
+        );
+
+        command_sender
+            .send(SequencerCommand::Locate(Tick::from(960)))
+            .unwrap();
+        command_sender.send(SequencerCommand::Pause).unwrap();
+        sequencer.process_commands();
+        assert_eq!(
+            received(),
+            vec![
+                (MidiMessage::Stop, 1),
+                (MidiMessage::SongPosition { position: 8 }, 1),
+                (MidiMessage::Continue, 1),
+                (MidiMessage::Stop, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn follows_an_external_clock_until_it_drops_out() {
+        let mut data = DataLayer::default();
+        data.project_manager.push_track();
+        let track_id = data.project_manager.tracks().get(0).unwrap().id;
+        data.add_clip(track_id, Clip::new(Tick::zero(), "clip", Tick::from(960)))
+            .unwrap();
+        let storage = StorageInterface::from_data_layer(data);
+
+        let (mut sequencer, clock, command_sender, mut received) = test_sequencer(storage);
+        let position = sequencer.subscribe_position();
+        let sync = |port, message| SequencerCommand::ExternalSync { port, message };
+
+        command_sender
+            .send(SequencerCommand::SetSyncSource(SyncSource::External {
+                port: 2,
+            }))
+            .unwrap();
+        command_sender.send(sync(2, MidiMessage::Start)).unwrap();
+        sequencer.process_commands();
+        sequencer.update();
+        assert!(received().is_empty());
+
+        // pulses from other ports are ignored
+        command_sender
+            .send(sync(3, MidiMessage::TimingClock))
+            .unwrap();
+        command_sender
+            .send(sync(2, MidiMessage::TimingClock))
+            .unwrap();
+        sequencer.process_commands();
+        assert_eq!(
+            received(),
+            vec![(
+                MidiMessage::NoteOn {
+                    key: 46,
+                    velocity: 64
+                },
+                0,
+                0
+            )]
+        );
+        // the ticks up to the next pulse are played ahead
+        assert_eq!(*position.borrow(), Tick::from(20));
+
+        // the playhead moves on with the next pulse, but never runs past the pulses
+        clock.advance(Duration::from_secs_f64(1.0 / 48.0));
+        command_sender
+            .send(sync(2, MidiMessage::TimingClock))
+            .unwrap();
+        sequencer.process_commands();
+        assert_eq!(*position.borrow(), Tick::from(40));
+        clock.advance(Duration::from_millis(100));
+        sequencer.update();
+        assert_eq!(*position.borrow(), Tick::from(40));
+        let bpm = sequencer.state.read().unwrap().external_bpm.unwrap();
+        assert!((bpm - 120.0).abs() < 1e-3);
+
+        clock.advance(Duration::from_secs(1));
+        sequencer.update();
+        assert_eq!(
+            received(),
+            vec![(
+                MidiMessage::NoteOff {
+                    key: 46,
+                    velocity: 0
+                },
+                0,
+                0
+            )]
+        );
+        assert!(!sequencer.state.read().unwrap().running);
+    }
+
+    #[test]
+    fn chases_incoming_timecode() {
+        let mut data = DataLayer::default();
+        data.project_manager.push_track();
+        let track_id = data.project_manager.tracks().get(0).unwrap().id;
+        data.add_clip(track_id, Clip::new(Tick::zero(), "clip", Tick::from(960)))
+            .unwrap();
+        let storage = StorageInterface::from_data_layer(data);
+
+        let (mut sequencer, clock, command_sender, mut received) = test_sequencer(storage);
+        let position = sequencer.subscribe_position();
+
+        let offset = Duration::from_secs(3600);
+        command_sender
+            .send(SequencerCommand::SetSyncSource(SyncSource::Timecode {
+                port: 1,
+                offset,
+            }))
+            .unwrap();
+        // 01:00:00:05 at 25 fps is 0.2 seconds after the start of the project
+        let timecode = Timecode::from_seconds(3600.2, FrameRate::Fps25);
+        for piece in 0..8 {
+            let data = timecode.quarter_frame(piece);
+            let message = MidiMessage::QuarterFrame { data };
+            command_sender
+                .send(SequencerCommand::ExternalSync { port: 1, message })
+                .unwrap();
+        }
+        sequencer.process_commands();
+        // the last piece arrives 0.07 seconds after the frame, 259 ticks after the start
+        assert_eq!(*position.borrow(), Tick::from(259));
+        assert!(sequencer.state.read().unwrap().running);
+        // notes which are sounding at the timecode are chased
+        let on = |key| (MidiMessage::NoteOn { key, velocity: 64 }, 0, 0);
+        assert_eq!(received(), vec![on(46), on(47)]);
+
+        // without timecode playback pauses
+        clock.advance(Duration::from_secs(1));
+        sequencer.update();
+        assert!(!sequencer.state.read().unwrap().running);
+    }
+
+    #[test]
+    fn records_notes_of_armed_tracks_into_a_clip() {
+        let mut data = DataLayer::default();
+        data.project_manager.push_track();
+        let track_id = data.project_manager.tracks().get(0).unwrap().id;
+        let storage = StorageInterface::from_data_layer(data);
+
+        let (mut sequencer, clock, command_sender, _) = test_sequencer(storage.clone());
+        let (input_sender, input_receiver) = tokio::sync::mpsc::unbounded_channel();
+        sequencer.record_from(input_receiver);
+        let (edit_sender, mut edit_receiver) = tokio::sync::mpsc::unbounded_channel();
+        sequencer.send_edits_to(edit_sender);
+        let input = |millis, message| TrackInput {
+            track_id,
+            event: InputEvent {
+                input: 0,
+                time: Duration::from_millis(millis),
+                message,
+                channel: 0,
+            },
+        };
+
+        command_sender.send(SequencerCommand::Record(true)).unwrap();
+        command_sender.send(SequencerCommand::Play).unwrap();
+        sequencer.process_commands();
+        // at 120 bpm a tick takes 1/960th of a second
+        let note = MidiMessage::NoteOn {
+            key: 60,
+            velocity: 90,
+        };
+        clock.advance(Duration::from_millis(400));
+        sequencer.update();
+        input_sender.send(input(250, note)).unwrap();
+        input_sender
+            .send(input(
+                375,
+                MidiMessage::NoteOff {
+                    key: 60,
+                    velocity: 0,
+                },
+            ))
+            .unwrap();
+        clock.advance(Duration::from_millis(100));
+        command_sender.send(SequencerCommand::Stop).unwrap();
+        sequencer.process_commands();
+
+        // the recording is added by the editor, the sequencer leaves the data alone
+        assert_eq!(storage.read().unwrap().project_manager.undo_name(), None);
+        let edit = edit_receiver.try_recv().unwrap();
+        edit.apply(&mut storage.write().unwrap());
+        let data = storage.read().unwrap();
+        let project = &data.project_manager;
+        let track = project.tracks().get_by_id(track_id).unwrap();
+        let clip = track.clip_collection.values().next().unwrap();
+        assert_eq!(clip.start, Tick::zero());
+        let (tick, segments) = clip.events().iter().next().unwrap();
+        assert_eq!(*tick, Tick::from(240));
+        assert_eq!(segments[0].end, Tick::from(360));
+        assert_eq!(project.undo_name(), Some("recording"));
+    }
+}
@@ -0,0 +1,219 @@
+use hexencer_core::{
+    data::{
+        event_list::{EventCollection, EventSegment},
+        ClipId, MidiMessage,
+    },
+    DataId, Tick,
+};
+
+/// how notes are entered one step at a time while the transport is stopped, the notes reach
+/// step input through the record armed track holding the clip
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StepSettings {
+    /// clip the notes are entered into
+    pub clip_id: ClipId,
+    /// length of a step, the note value the cursor advances by
+    pub length: Tick,
+    /// key which skips a step without entering notes
+    pub rest_key: Option<u8>,
+    /// key which lengthens the notes of the previous step by a step
+    pub tie_key: Option<u8>,
+}
+
+/// the notes a step adds to a clip and the notes it lengthens, applied by the editor
+#[derive(Debug, Clone)]
+pub struct StepEdit {
+    /// clip the step is entered into
+    pub clip_id: ClipId,
+    /// notes entered by the step, relative to the clip start
+    pub notes: Vec<EventSegment>,
+    /// notes of the previous step lengthened by a tie, with their new end
+    pub tied: Vec<(DataId, Tick)>,
+}
+
+impl StepEdit {
+    /// enters the step into the events of its clip
+    pub fn apply(self, events: &mut EventCollection) {
+        for note in self.notes {
+            events.add_event(note.start, note);
+        }
+        for (id, end) in self.tied {
+            events.set_end(id, end);
+        }
+    }
+}
+
+/// what a step does to the clip
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Step {
+    /// enter the notes as (key, velocity) at the cursor
+    Chord(Vec<(u8, u8)>),
+    /// skip a step
+    Rest,
+    /// lengthen the notes of the previous step
+    Tie,
+}
+
+/// collects the notes held on a keyboard into chords and enters them at a cursor
+#[derive(Debug)]
+pub(crate) struct StepInput {
+    /// how notes are entered
+    settings: StepSettings,
+    /// position in the clip at which the next step is entered, relative to the clip start
+    cursor: Tick,
+    /// keys which are held down
+    held: Vec<u8>,
+    /// notes of the chord being played, in the order they were pressed
+    chord: Vec<(u8, u8)>,
+    /// ids of the notes entered by the previous step, lengthened by a tie
+    previous: Vec<DataId>,
+}
+
+impl StepInput {
+    /// creates a new 'StepInput' with its cursor at the start of the clip
+    pub(crate) fn new(settings: StepSettings) -> Self {
+        Self {
+            settings,
+            cursor: Tick::zero(),
+            held: Vec::new(),
+            chord: Vec::new(),
+            previous: Vec::new(),
+        }
+    }
+
+    /// get the settings of the step input
+    pub(crate) fn settings(&self) -> &StepSettings {
+        &self.settings
+    }
+
+    /// change the settings, the cursor stays in place when the clip does not change
+    pub(crate) fn set_settings(&mut self, settings: StepSettings) {
+        if settings.clip_id != self.settings.clip_id {
+            *self = Self::new(settings);
+        }
+        self.settings = settings;
+    }
+
+    /// get the position at which the next step is entered, relative to the clip start
+    pub(crate) fn cursor(&self) -> Tick {
+        self.cursor
+    }
+
+    /// handles a message of the keyboard, returning a step once a chord is released
+    /// or a rest or tie key is pressed
+    pub(crate) fn input(&mut self, message: MidiMessage) -> Option<Step> {
+        match message {
+            MidiMessage::NoteOn { key, velocity } if velocity > 0 => {
+                if Some(key) == self.settings.rest_key {
+                    return Some(Step::Rest);
+                }
+                if Some(key) == self.settings.tie_key {
+                    return Some(Step::Tie);
+                }
+                if !self.held.contains(&key) {
+                    self.held.push(key);
+                    self.chord.push((key, velocity));
+                }
+                None
+            }
+            MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+                let index = self.held.iter().position(|held| *held == key)?;
+                self.held.remove(index);
+                // the chord is entered once every note of it is released
+                match self.held.is_empty() {
+                    true => Some(Step::Chord(std::mem::take(&mut self.chord))),
+                    false => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// moves the cursor to the next step, returning what the step changes in the clip,
+    /// 'None' for a rest
+    pub(crate) fn apply(&mut self, step: Step) -> Option<StepEdit> {
+        let start = self.cursor;
+        let end = start + self.settings.length;
+        let mut edit = StepEdit {
+            clip_id: self.settings.clip_id,
+            notes: Vec::new(),
+            tied: Vec::new(),
+        };
+        match step {
+            Step::Chord(notes) => {
+                edit.notes = notes
+                    .into_iter()
+                    .map(|(key, velocity)| EventSegment::new2(start, end, key, velocity, true))
+                    .collect();
+                self.previous = edit.notes.iter().map(|note| note.id).collect();
+            }
+            Step::Rest => {
+                self.rest();
+                return None;
+            }
+            Step::Tie => {
+                edit.tied = self.previous.iter().map(|id| (*id, end)).collect();
+            }
+        }
+        self.cursor = end;
+        Some(edit)
+    }
+
+    /// moves the cursor to the next step without entering notes, a tie after a rest ties nothing
+    fn rest(&mut self) {
+        self.previous.clear();
+        self.cursor = self.cursor + self.settings.length;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chords_rests_and_ties_advance_the_cursor() {
+        let on = |key| MidiMessage::NoteOn { key, velocity: 90 };
+        let off = |key| MidiMessage::NoteOff { key, velocity: 0 };
+        let mut events = EventCollection::new();
+        let mut step_input = StepInput::new(StepSettings {
+            clip_id: ClipId::new(),
+            length: Tick::from(120),
+            rest_key: Some(21),
+            tie_key: Some(22),
+        });
+        let mut play = |step_input: &mut StepInput, message| {
+            if let Some(edit) = step_input
+                .input(message)
+                .and_then(|step| step_input.apply(step))
+            {
+                edit.apply(&mut events);
+            }
+        };
+
+        // a chord is entered once all of its notes are released
+        play(&mut step_input, on(60));
+        play(&mut step_input, on(64));
+        play(&mut step_input, off(60));
+        assert_eq!(step_input.cursor(), Tick::zero());
+        play(&mut step_input, off(64));
+        assert_eq!(step_input.cursor(), Tick::from(120));
+        play(&mut step_input, on(22));
+        play(&mut step_input, on(21));
+        play(&mut step_input, on(67));
+        play(&mut step_input, off(67));
+        assert_eq!(step_input.cursor(), Tick::from(480));
+
+        let notes: Vec<_> = events
+            .iter()
+            .flat_map(|(_, segments)| segments.iter())
+            .map(|segment| {
+                (
+                    segment.get_key(),
+                    segment.start.as_u64(),
+                    segment.end.as_u64(),
+                )
+            })
+            .collect();
+        assert_eq!(notes, vec![(60, 0, 240), (64, 0, 240), (67, 360, 480)]);
+    }
+}
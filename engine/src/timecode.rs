@@ -0,0 +1,164 @@
+use std::time::Duration;
+
+use hexencer_core::{
+    data::{MidiMessage, SyncSettings, TempoMap, Timecode},
+    Tick,
+};
+
+use crate::external_sync::DROPOUT_TIMEOUT;
+
+/// number of quarter frame messages sent for every frame
+const QUARTER_FRAMES_PER_FRAME: u64 = 4;
+/// number of quarter frame pieces which together carry a full timecode
+const PIECES: u64 = 8;
+/// bitmask of a complete set of received pieces
+const ALL_PIECES: u8 = 0xFF;
+/// largest difference between the playhead and incoming timecode which is corrected smoothly,
+/// the playhead jumps to the timecode when it drifts further away
+pub const MAX_DRIFT: Duration = Duration::from_millis(100);
+
+/// get the quarter frames due while a tick plays, positions follow the tempo map
+pub fn quarter_frame_messages(
+    sync: &SyncSettings,
+    tempo_map: &TempoMap,
+    tick: Tick,
+) -> Vec<(MidiMessage, u8)> {
+    let Some(output) = sync.timecode_output() else {
+        return Vec::new();
+    };
+    let per_second = output.rate.fps() * QUARTER_FRAMES_PER_FRAME as f64;
+    let first = (tempo_map.seconds_at(tick) * per_second).ceil() as u64;
+    let end = (tempo_map.seconds_at(tick + Tick::from(1)) * per_second).ceil() as u64;
+    (first..end)
+        .map(|quarter_frame| {
+            let piece = quarter_frame % PIECES;
+            // every piece carries the timecode of the frame on which piece 0 was sent
+            let frame = (quarter_frame - piece) / QUARTER_FRAMES_PER_FRAME;
+            let timecode = Timecode::from_frames(frame, output.rate);
+            let data = timecode.quarter_frame(piece as u8);
+            (MidiMessage::QuarterFrame { data }, output.port)
+        })
+        .collect()
+}
+
+/// get the full frame message which tells devices the timecode jumped to a tick
+pub fn full_frame_messages(
+    sync: &SyncSettings,
+    tempo_map: &TempoMap,
+    tick: Tick,
+) -> Vec<(MidiMessage, u8)> {
+    sync.timecode_output()
+        .map(|output| {
+            let timecode = Timecode::from_seconds(tempo_map.seconds_at(tick), output.rate);
+            (MidiMessage::FullFrame(timecode), output.port)
+        })
+        .into_iter()
+        .collect()
+}
+
+/// assembles incoming midi time code into positions of the project
+#[derive(Debug, Clone)]
+pub struct TimecodeFollower {
+    /// input port the timecode is received on
+    port: u8,
+    /// timecode at which the project starts
+    offset: Duration,
+    /// data of the last received quarter frame of each piece
+    pieces: [u8; 8],
+    /// bitmask of the pieces received since piece 0
+    received: u8,
+    /// time at which the last quarter frame was received
+    last_quarter_frame: Option<Duration>,
+}
+
+impl TimecodeFollower {
+    /// creates a new 'TimecodeFollower' for the timecode of an input port
+    pub fn new(port: u8, offset: Duration) -> Self {
+        Self {
+            port,
+            offset,
+            pieces: [0; 8],
+            received: 0,
+            last_quarter_frame: None,
+        }
+    }
+
+    /// get the input port the timecode is received on
+    pub fn port(&self) -> u8 {
+        self.port
+    }
+
+    /// takes a message received at 'now', returning the position in seconds from the start of
+    /// the project once a full timecode is known, positions before the start are skipped
+    pub fn receive(&mut self, message: &MidiMessage, now: Duration) -> Option<f64> {
+        let timecode = match *message {
+            MidiMessage::QuarterFrame { data } => {
+                self.last_quarter_frame = Some(now);
+                let piece = data >> 4 & 0x07;
+                if piece == 0 {
+                    self.received = 0;
+                }
+                self.pieces[piece as usize] = data;
+                self.received |= 1 << piece;
+                if piece != 7 || self.received != ALL_PIECES {
+                    return None;
+                }
+                let timecode = Timecode::from_quarter_frames(&self.pieces);
+                // the last piece arrives seven quarter frames after the frame it describes
+                let late =
+                    (PIECES - 1) as f64 / (QUARTER_FRAMES_PER_FRAME as f64 * timecode.rate.fps());
+                timecode.to_seconds() + late
+            }
+            MidiMessage::FullFrame(timecode) => {
+                self.received = 0;
+                timecode.to_seconds()
+            }
+            _ => return None,
+        };
+        let seconds = timecode - self.offset.as_secs_f64();
+        (seconds >= 0.0).then_some(seconds)
+    }
+
+    /// true if no quarter frame was received for too long while the timecode was running
+    pub fn timed_out(&self, now: Duration) -> bool {
+        self.last_quarter_frame
+            .is_some_and(|last| now.saturating_sub(last) > DROPOUT_TIMEOUT)
+    }
+
+    /// forgets the received pieces, used when playback stops
+    pub fn reset(&mut self) {
+        self.received = 0;
+        self.last_quarter_frame = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hexencer_core::data::{FrameRate, TimecodeOutput};
+
+    use super::*;
+
+    #[test]
+    fn follows_the_timecode_it_sends() {
+        let mut sync = SyncSettings::default();
+        sync.set_timecode_output(Some(TimecodeOutput {
+            port: 0,
+            rate: FrameRate::Fps25,
+        }));
+        let tempo_map = TempoMap::new(120.0);
+        // at 120 bpm a second takes 960 ticks, in which 100 quarter frames are sent
+        let start = Tick::from(960 * 3600);
+        let sent: Vec<_> = (0..96)
+            .flat_map(|tick| quarter_frame_messages(&sync, &tempo_map, start + Tick::from(tick)))
+            .collect();
+        assert_eq!(sent.len(), 10);
+
+        let mut follower = TimecodeFollower::new(0, Duration::from_secs(3000));
+        let positions: Vec<_> = sent
+            .iter()
+            .filter_map(|(message, _)| follower.receive(message, Duration::ZERO))
+            .collect();
+        assert_eq!(positions.len(), 1);
+        assert!((positions[0] - (600.0 + 7.0 / 100.0)).abs() < 1e-9);
+    }
+}
@@ -11,7 +11,12 @@ use std::time::Instant;
 
 use hexencer_core::data::{ClipId, StorageInterface};
 use hexencer_core::{DataId, Tick, TrackId};
-use hexencer_engine::{midi_engine, Sequencer, SequencerCommand, SequencerHandle};
+use hexencer_engine::{
+    midi_engine::{self, MidiEngineHandle, PortCommand},
+    start_virtual_inputs, Clock, InputRouter, MidiInputEngine, MidirBackend, PortState, PortStatus,
+    ProjectEditReceiver, RealClock, Sequencer, SequencerCommand, SequencerHandle, StepSettings,
+    VirtualInputs,
+};
 use iced::advanced::graphics::color;
 use iced::advanced::widget::Tree;
 use iced::advanced::{layout, mouse, renderer, Layout, Widget};
@@ -228,6 +233,18 @@ pub enum Message {
     ResetSequencer,
     /// pauses the sequencer
     PauseSequencer,
+    /// look for midi outputs which disappeared or returned
+    RescanPorts,
+    /// start or stop recording onto the armed tracks
+    ToggleRecord,
+    /// arm or disarm the selected track, armed tracks receive recording and step input
+    ToggleArm,
+    /// undo the latest edit
+    Undo,
+    /// turn what was just played into a clip on the selected track
+    Capture,
+    /// start or stop entering notes one step at a time into the selected clip
+    ToggleStepInput,
     /// set clip to selected
     SelectClip {
         /// id of the recently selected clip
@@ -244,6 +261,14 @@ struct Hexencer {
     storage: StorageInterface,
     /// sequencer
     sequencer_handle: SequencerHandle,
+    /// midi engine
+    midi_engine_handle: MidiEngineHandle,
+    /// virtual inputs other applications can send to, kept open for as long as the application runs
+    virtual_inputs: VirtualInputs,
+    /// midi inputs played into armed tracks and echoed to the selected track
+    input_engine: MidiInputEngine,
+    /// receives the recordings and steps of the sequencer, which are added to the project here
+    edit_receiver: ProjectEditReceiver,
     /// a clip that was dropped
     dropped_clip: Option<ClipId>, // TODO #53 move this elsewhere
     /// the origin of the drag for the clip that was dropped
@@ -318,13 +343,67 @@ impl<Message> canvas::Program<Message> for LineState {
 impl Default for Hexencer {
     fn default() -> Self {
         let storage = StorageInterface::new();
-        let midi_sender = midi_engine::start_midi_engine();
+        // the midi engine plays the messages of the sequencer at their time on its clock
+        let clock: Arc<dyn Clock> = Arc::new(RealClock::new());
+        let midi_engine_handle = {
+            let mut data = storage.write().unwrap();
+            // a new project has no ports, the first outputs are used for the first logical ports
+            if data.project_manager.ports().is_empty() {
+                let outputs = midi_engine::list_outputs().unwrap_or_default();
+                data.project_manager.edit_ports(|ports| {
+                    for (id, name) in outputs.iter().take(2).enumerate() {
+                        ports.assign(id as u8, name);
+                    }
+                });
+            }
+            midi_engine::start_midi_engine(
+                MidirBackend::new(),
+                data.project_manager.ports(),
+                clock.clone(),
+            )
+        };
         let (sequencer_sender, sequencer_receiver) = tokio::sync::mpsc::unbounded_channel();
-        let sequencer = Sequencer::new(storage.clone(), midi_sender, sequencer_receiver);
+        let mut sequencer = Sequencer::with_clock(
+            storage.clone(),
+            midi_engine_handle.sender.clone(),
+            sequencer_receiver,
+            clock,
+        );
+
+        let (track_sender, track_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let mut input_engine = MidiInputEngine::new(InputRouter::new(
+            sequencer.snapshot(),
+            sequencer.clock(),
+            sequencer.subscribe_transport(),
+            sequencer_sender.clone(),
+            track_sender,
+        ));
+        sequencer.record_from(track_receiver);
+        let (edit_sender, edit_receiver) = tokio::sync::mpsc::unbounded_channel();
+        sequencer.send_edits_to(edit_sender);
+        let virtual_inputs = start_virtual_inputs(
+            storage.read().unwrap().project_manager.ports(),
+            &sequencer_sender,
+            input_engine.router(),
+        );
+        {
+            let mut data = storage.write().unwrap();
+            // a new project has no inputs, the first input is used
+            if data.project_manager.inputs().is_empty() {
+                let inputs = midi_engine::list_inputs().unwrap_or_default();
+                if let Some(name) = inputs.first() {
+                    data.project_manager.edit_inputs(|map| map.assign(0, name));
+                }
+            }
+            for error in input_engine.open_all(data.project_manager.inputs()) {
+                tracing::warn!("{}", error);
+            }
+        }
 
         let sequencer_handle = SequencerHandle {
             state: Arc::clone(&sequencer.state),
             command_sender: sequencer_sender,
+            position: sequencer.subscribe_position(),
         };
 
         tokio::spawn(sequencer.run());
@@ -351,6 +430,10 @@ impl Default for Hexencer {
             drag_origin: 0.0,
             line_state: LineState::new(),
             sequencer_handle,
+            midi_engine_handle,
+            virtual_inputs,
+            input_engine,
+            edit_receiver,
             selected_clip: None,
             notes,
         }
@@ -358,9 +441,17 @@ impl Default for Hexencer {
 }
 
 impl Hexencer {
+    /// adds the recordings and steps of the sequencer to the project, so the sequencer never waits on the data layer
+    fn apply_edits(&mut self) {
+        while let Ok(edit) = self.edit_receiver.try_recv() {
+            edit.apply(&mut self.storage.write().unwrap());
+        }
+    }
+
     /// update the application state
     fn update(&mut self, message: Message) {
         
+        self.apply_edits();
         if let Some(dropped_clip) = self.dropped_clip {
             tracing::info!("dropped clip: {:?}", dropped_clip);
             self.dropped_clip = None;
@@ -388,14 +479,7 @@ impl Hexencer {
                     .move_clip(clip_id, track_id, tick);
             }
             Message::Tick(instant) => {
-                let tick = self
-                    .sequencer_handle
-                    .state
-                    .read()
-                    .unwrap()
-                    .current_tick
-                    .as_f64()
-                    / 480.0;
+                let tick = self.sequencer_handle.position.borrow().as_f64() / 480.0;
                 self.line_state.update2(instant, tick);
             }
             Message::PlaySequencer => {
@@ -419,10 +503,81 @@ impl Hexencer {
                     .expect("unable to send command");
                 info!("pause sequencer command sent");
             }
+            Message::RescanPorts => {
+                self.midi_engine_handle
+                    .port_commands
+                    .send(PortCommand::Rescan)
+                    .expect("unable to send command");
+            }
+            Message::ToggleRecord => {
+                let recording = self.sequencer_handle.state.read().unwrap().recording;
+                self.sequencer_handle
+                    .command_sender
+                    .send(SequencerCommand::Record(!recording))
+                    .expect("unable to send command");
+            }
+            Message::ToggleArm => {
+                let mut data = self.storage.write().unwrap();
+                let Some(track_id) = data.selected_track() else {
+                    return;
+                };
+                data.project_manager
+                    .edit_track(track_id, |track| track.set_record_arm(!track.record_arm));
+            }
+            Message::Capture => {
+                let position = *self.sequencer_handle.position.borrow();
+                let mut data = self.storage.write().unwrap();
+                let Some(track_id) = data.selected_track() else {
+                    return;
+                };
+                if let Some(capture) = self.input_engine.router().capture(track_id, position) {
+                    // the tempo is left alone, so undoing the capture undoes all of it
+                    if let Some(bpm) = capture.bpm {
+                        tracing::info!("captured a phrase played at {} bpm", bpm);
+                    }
+                    data.project_manager.add_capture(capture.take);
+                }
+            }
+            Message::ToggleStepInput => {
+                let stepping = self
+                    .sequencer_handle
+                    .state
+                    .read()
+                    .unwrap()
+                    .step_cursor
+                    .is_some();
+                // sixteenth notes, the lowest two keys of a piano enter rests and ties
+                let settings = match stepping {
+                    true => None,
+                    false => self.selected_clip.map(|clip_id| StepSettings {
+                        clip_id,
+                        length: Tick::from(120),
+                        rest_key: Some(21),
+                        tie_key: Some(22),
+                    }),
+                };
+                self.sequencer_handle
+                    .command_sender
+                    .send(SequencerCommand::SetStepInput(settings))
+                    .expect("unable to send command");
+            }
+            Message::Undo => {
+                let undone = self.storage.write().unwrap().project_manager.undo();
+                if let Some(name) = undone {
+                    info!("undo {}", name);
+                }
+            }
             Message::SelectClip { clip_id } => {
                 println!("test");
                 info!("selected clip {}", clip_id);
                 self.selected_clip = Some(clip_id);
+                // the inputs are echoed to the instrument of the track holding the clip
+                let mut data = self.storage.write().unwrap();
+                let track_id = data
+                    .project_manager
+                    .clip_location(clip_id)
+                    .map(|(track_id, _)| track_id);
+                data.select_track(track_id);
             }
             Message::EventOccured(event) => {
                 if let Event::Keyboard(keyboard::Event::KeyPressed {key, location: _, modifiers, text: _}) = event {
@@ -437,27 +592,9 @@ impl Hexencer {
 
     /// remove a clip from the storage
     pub fn _remove_clip(&mut self, clip_id: ClipId) {
-        let mut to_remove = None;
-
         let mut data = self.storage.write().unwrap();
-        let track_collection = &mut data.project_manager.track_collection;
-        for track in track_collection.tracks() {
-            for (clip_key, clip) in track.clip_collection.iter() {
-                if clip.id() == clip_id {
-                    info!("clip found: {:?} in track {:?}", clip_id, track.id);
-                    to_remove = Some((track.id, *clip_key));
-                    break;
-                }
-            }
-            if to_remove.is_some() {
-                break;
-            }
-        }
-
-        if let Some((track_id, clip_key)) = to_remove {
-            if let Some(track) = track_collection.get_mut(track_id) {
-                track.clip_collection.remove(&clip_key).unwrap();
-            }
+        if data.project_manager.take_clip(clip_id).is_some() {
+            info!("clip removed: {:?}", clip_id);
         }
     }
 
@@ -474,7 +611,11 @@ impl Hexencer {
                 .align_items(Alignment::Center),
         );
 
-        let bottom = status_bar(self.storage.clone(), &self.sequencer_handle);
+        let bottom = status_bar(
+            self.storage.clone(),
+            &self.sequencer_handle,
+            &self.midi_engine_handle.ports.borrow(),
+        );
 
         let elements = self.create_track_elements();
 
@@ -521,7 +662,7 @@ impl Hexencer {
     // TODO make this faster, now causing visual glitches
     fn create_track_elements(&self) -> Vec<Element<Message>> {
         let storage = self.storage.read().unwrap();
-        let track_collection = &storage.project_manager.track_collection;
+        let track_collection = storage.project_manager.tracks();
 
         track_collection
             .iter()
@@ -558,7 +699,7 @@ impl Hexencer {
         let storage = self.storage.read().unwrap();
         let clip_collection = &storage
             .project_manager
-            .track_collection
+            .tracks()
             .get(track_index)
             .unwrap()
             .clip_collection;
@@ -600,27 +741,16 @@ impl Hexencer {
             None => 50.0,
         };
 
-        let track_collection = &self
-            .storage
-            .read()
-            .unwrap()
-            .project_manager
-            .track_collection;
+        let storage = self.storage.read().unwrap();
 
         let mut label = "null".to_string();
 
         if let Some(id) = self.selected_clip {
-            let mut clip = None;
-            for track in track_collection.iter() {
-                if let Some(track_clip) = track.clip_collection.find(id) {
-                    clip = Some(track_clip)
-                };
-            }
-
-            if let Some(clip) = clip {
+            if let Some(clip) = storage.project_manager.find_clip(id) {
                 label = clip.start.to_string();
             }
         }
+        drop(storage);
 
         let notes = self.draw_notes(label);
         let content = column![header, notes];
@@ -641,7 +771,7 @@ impl Hexencer {
             if let Some(selected_clip) = self.selected_clip {
                 let storage = self.storage.read().unwrap();
                 if let Some(clip) = storage.project_manager.find_clip(selected_clip) {
-                    for (tick, event) in clip.events.iter() {
+                    for (tick, event) in clip.events().iter() {
                         for segment in event {
                             match segment.event_type {
                                 hexencer_core::event::EventType::Midi(message) => match message {
@@ -664,6 +794,7 @@ impl Hexencer {
                                     hexencer_core::data::MidiMessage::AllNoteOff => {
                                         info!("all notes of");
                                     }
+                                    _ => {}
                                 },
                             }
                         }
@@ -686,14 +817,50 @@ impl Hexencer {
 }
 
 /// create the status bar ui
-fn status_bar(storage: StorageInterface, sequencer: &SequencerHandle) -> Element<'_, Message> {
+fn status_bar<'a>(
+    storage: StorageInterface,
+    sequencer: &'a SequencerHandle,
+    ports: &[PortStatus],
+) -> Element<'a, Message> {
     let play_button = button("play").on_press(Message::PlaySequencer);
     let pause_button = button("pause").on_press(Message::PauseSequencer);
     let reset_button = button("reset").on_press(Message::ResetSequencer);
-
-    let state = sequencer.state.read().unwrap();
-    let test = state.current_tick;
-    let current_tick = test;
+    let rescan_button = button("rescan").on_press(Message::RescanPorts);
+    let record_label = match sequencer.state.read().unwrap().recording {
+        true => "recording",
+        false => "record",
+    };
+    let record_button = button(record_label).on_press(Message::ToggleRecord);
+    let armed = {
+        let data = storage.read().unwrap();
+        data.selected_track()
+            .and_then(|track_id| data.project_manager.tracks().get_by_id(track_id))
+            .is_some_and(|track| track.record_arm)
+    };
+    let arm_label = match armed {
+        true => "armed",
+        false => "arm",
+    };
+    let arm_button = button(arm_label).on_press(Message::ToggleArm);
+    let undo_button = button("undo").on_press(Message::Undo);
+    let capture_button = button("capture").on_press(Message::Capture);
+    let step_label = match sequencer.state.read().unwrap().step_cursor {
+        Some(cursor) => format!("step {}", cursor),
+        None => String::from("step"),
+    };
+    let step_button = button(text(step_label)).on_press(Message::ToggleStepInput);
+
+    let missing: Vec<&str> = ports
+        .iter()
+        .filter(|port| port.state == PortState::Missing)
+        .map(|port| port.name.as_str())
+        .collect();
+    let ports_widget = match missing.is_empty() {
+        true => text(""),
+        false => text(format!("missing: {}", missing.join(", "))),
+    };
+
+    let current_tick = *sequencer.position.borrow();
     let tick_widget = text(current_tick.to_string());
 
     let bpm = storage.read().unwrap().bpm();
@@ -704,6 +871,13 @@ fn status_bar(storage: StorageInterface, sequencer: &SequencerHandle) -> Element
             play_button,
             pause_button,
             reset_button,
+            record_button,
+            arm_button,
+            undo_button,
+            capture_button,
+            step_button,
+            rescan_button,
+            ports_widget,
             horizontal_space(),
             bpm_widget,
             horizontal_space(),